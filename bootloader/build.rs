@@ -0,0 +1,27 @@
+//! Puts the flash layout of the chip selected by the features where the linker
+//! finds it as `memory.x`.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() {
+    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let memory = if env::var_os("CARGO_FEATURE_RP235XA").is_some() {
+        "memory-rp235x.x"
+    } else {
+        "memory-rp2040.x"
+    };
+    fs::copy(memory, out.join("memory.x")).unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+
+    println!("cargo:rerun-if-changed=memory-rp2040.x");
+    println!("cargo:rerun-if-changed=memory-rp235x.x");
+
+    println!("cargo:rustc-link-arg-bins=--nmagic");
+    println!("cargo:rustc-link-arg-bins=-Tlink.x");
+    if env::var_os("CARGO_FEATURE_RP2040").is_some() {
+        println!("cargo:rustc-link-arg-bins=-Tlink-rp.x");
+    }
+    println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
+}
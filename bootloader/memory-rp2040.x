@@ -0,0 +1,26 @@
+MEMORY {
+    /*
+     * Flash of the Pico W, 2 MiB. The display and IR boards link their
+     * firmware to ACTIVE and keep the same layout in their `memory.x`.
+     *
+     * DFU takes the update and needs one sector more than ACTIVE for the
+     * swap.
+     */
+    BOOT2            : ORIGIN = 0x10000000, LENGTH = 0x100
+    FLASH            : ORIGIN = 0x10000100, LENGTH = 24K - 0x100
+    BOOTLOADER_STATE : ORIGIN = 0x10006000, LENGTH = 4K
+    ACTIVE           : ORIGIN = 0x10007000, LENGTH = 1008K
+    DFU              : ORIGIN = 0x10103000, LENGTH = 1012K
+    /* The first 1K of RAM keeps the firmware's crash record over a restart */
+    CRASH            : ORIGIN = 0x20000000, LENGTH = 1K
+    RAM              : ORIGIN = 0x20000400, LENGTH = 263K
+}
+
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(BOOT2);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(BOOT2);
+
+__bootloader_active_start = ORIGIN(ACTIVE) - ORIGIN(BOOT2);
+__bootloader_active_end = ORIGIN(ACTIVE) + LENGTH(ACTIVE) - ORIGIN(BOOT2);
+
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(BOOT2);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(BOOT2);
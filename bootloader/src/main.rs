@@ -0,0 +1,63 @@
+#![no_std]
+#![no_main]
+
+//! Bootloader of all boards, for firmware updates over the network.
+//!
+//! Swaps in the firmware an update left in the DFU partition, swaps it back
+//! when the new firmware resets before marking itself booted, and starts the
+//! firmware in the ACTIVE partition. The partitions are laid out in
+//! `memory-rp2040.x` and `memory-rp235x.x`, the chip is selected with the
+//! `rp2040` or `rp235xa` feature.
+
+use core::cell::RefCell;
+
+use cortex_m_rt::{entry, exception};
+use defmt_rtt as _;
+use embassy_boot_rp::*;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Duration;
+
+#[cfg(not(any(feature = "rp2040", feature = "rp235xa")))]
+compile_error!("enable exactly one of the `rp2040` or `rp235xa` features");
+
+#[cfg(all(feature = "rp2040", feature = "rp235xa"))]
+compile_error!("the `rp2040` and `rp235xa` features are mutually exclusive");
+
+/// Flash size the layouts assume, the same for both chips.
+const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+/// A swap that takes longer than this is taken for a hang and restarted.
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(8);
+
+#[entry]
+fn main() -> ! {
+    let p = embassy_rp::init(Default::default());
+
+    let flash = WatchdogFlash::<FLASH_SIZE>::start(p.FLASH, p.WATCHDOG, WATCHDOG_TIMEOUT);
+    let flash = Mutex::new(RefCell::new(flash));
+
+    let config = BootLoaderConfig::from_linkerfile_blocking(&flash, &flash, &flash);
+    let active_offset = config.active.offset();
+    let bl: BootLoader = BootLoader::prepare(config);
+
+    unsafe { bl.load(embassy_rp::flash::FLASH_BASE as u32 + active_offset) }
+}
+
+#[unsafe(no_mangle)]
+#[cfg_attr(target_os = "none", unsafe(link_section = ".HardFault.user"))]
+unsafe extern "C" fn HardFault() {
+    cortex_m::peripheral::SCB::sys_reset();
+}
+
+#[exception]
+unsafe fn DefaultHandler(_: i16) -> ! {
+    const SCB_ICSR: *const u32 = 0xE000_ED04 as *const u32;
+    let irqn = unsafe { core::ptr::read_volatile(SCB_ICSR) } as u8 as i16 - 16;
+
+    panic!("DefaultHandler #{:?}", irqn);
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    cortex_m::asm::udf();
+}
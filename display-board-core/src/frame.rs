@@ -0,0 +1,145 @@
+//! Off-screen copy of the display, used to find what changed between two
+//! renders so only those pixels are pushed to the SSD1306.
+
+use core::convert::Infallible;
+
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+
+pub const WIDTH: u32 = 128;
+pub const HEIGHT: u32 = 64;
+
+/// One bit per pixel, eight vertical pixels per byte like the SSD1306 memory.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Frame {
+    buffer: [u8; (WIDTH * HEIGHT / 8) as usize],
+}
+
+impl Frame {
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0; (WIDTH * HEIGHT / 8) as usize],
+        }
+    }
+
+    /// Calls `set_pixel` for every pixel that differs from `previous`.
+    pub fn changes_since(&self, previous: &Frame, mut set_pixel: impl FnMut(u32, u32, bool)) {
+        for (index, (new, old)) in self.buffer.iter().zip(previous.buffer.iter()).enumerate() {
+            let changed = new ^ old;
+            if changed == 0 {
+                continue;
+            }
+
+            let x = index as u32 % WIDTH;
+            let y = index as u32 / WIDTH * 8;
+            for bit in 0..8 {
+                if changed & (1 << bit) != 0 {
+                    set_pixel(x, y + bit, new & (1 << bit) != 0);
+                }
+            }
+        }
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OriginDimensions for Frame {
+    fn size(&self) -> Size {
+        Size::new(WIDTH, HEIGHT)
+    }
+}
+
+impl DrawTarget for Frame {
+    type Color = BinaryColor;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            if point.x < 0 || point.y < 0 || point.x >= WIDTH as i32 || point.y >= HEIGHT as i32 {
+                continue;
+            }
+
+            let (x, y) = (point.x as u32, point.y as u32);
+            let index = (y / 8 * WIDTH + x) as usize;
+            let bit = 1 << (y % 8);
+            if color.is_on() {
+                self.buffer[index] |= bit;
+            } else {
+                self.buffer[index] &= !bit;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.buffer.fill(if color.is_on() { 0xff } else { 0 });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+
+    use super::*;
+
+    fn changes(new: &Frame, old: &Frame) -> Vec<(u32, u32, bool)> {
+        let mut changes = Vec::new();
+        new.changes_since(old, |x, y, on| changes.push((x, y, on)));
+        changes
+    }
+
+    #[test]
+    fn no_change() {
+        let mut frame = Frame::new();
+        Rectangle::new(Point::new(10, 5), Size::new(20, 12))
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::On))
+            .draw(&mut frame)
+            .unwrap();
+        assert_eq!(changes(&frame, &frame.clone()), []);
+        assert_eq!(changes(&Frame::new(), &Frame::new()), []);
+    }
+
+    #[test]
+    fn single_cell() {
+        let old = Frame::new();
+        let mut new = old.clone();
+        // In the second page of rows, bit 1 of its byte
+        Pixel(Point::new(127, 9), BinaryColor::On).draw(&mut new).unwrap();
+        assert_eq!(changes(&new, &old), [(127, 9, true)]);
+        // And back off
+        assert_eq!(changes(&old, &new), [(127, 9, false)]);
+
+        // Outside the display nothing is drawn
+        let mut outside = old.clone();
+        Pixel(Point::new(128, 0), BinaryColor::On).draw(&mut outside).unwrap();
+        Pixel(Point::new(0, -1), BinaryColor::On).draw(&mut outside).unwrap();
+        assert_eq!(changes(&outside, &old), []);
+    }
+
+    #[test]
+    fn full_redraw() {
+        let old = Frame::new();
+        let mut new = old.clone();
+        new.clear(BinaryColor::On).unwrap();
+
+        let changes = changes(&new, &old);
+        assert_eq!(changes.len(), (WIDTH * HEIGHT) as usize);
+        assert!(changes.iter().all(|&(x, y, on)| x < WIDTH && y < HEIGHT && on));
+        let mut seen = [[false; WIDTH as usize]; HEIGHT as usize];
+        for (x, y, _) in changes {
+            assert!(!seen[y as usize][x as usize], "({}, {}) twice", x, y);
+            seen[y as usize][x as usize] = true;
+        }
+    }
+}
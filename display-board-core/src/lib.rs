@@ -0,0 +1,12 @@
+#![cfg_attr(not(test), no_std)]
+
+//! What the display board shows: the status lines from the main board, the
+//! pages built from them and their drawing.
+//!
+//! Drawing goes through a generic `DrawTarget`, so all of it builds for the
+//! host as well and is tested there against the off-screen [`frame::Frame`].
+
+pub mod frame;
+pub mod lot;
+pub mod render;
+pub mod ui;
@@ -0,0 +1,183 @@
+//! Parking lot state as reported by the main board.
+
+use heapless::Vec;
+
+/// Largest lot the display board can show.
+pub const MAX_SPOTS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum SensorState {
+    Occupied,
+    NotOccupied,
+    /// Free but held for a reservation, not offered to others.
+    Reserved,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum BarrierState {
+    Open,
+    Closed,
+    Locked,
+    /// Held open until an operator clears the emergency.
+    Emergency,
+}
+
+/// Peer boards whose link health is shown on the display.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Peer {
+    Main,
+    Ir,
+}
+
+/// Parking session statistics kept by the main board.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct LotStats {
+    /// Sessions finished since the main board booted.
+    pub sessions: u32,
+    /// Average session length, `None` before the first one finished.
+    pub average_dwell_secs: Option<u64>,
+    /// Sessions finished in the last hour.
+    pub turnover_per_hour: u32,
+    /// Most spots occupied at the same time.
+    pub peak_occupied: u8,
+}
+
+/// One status line sent by the main board.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    /// `Lot: 01R0`
+    Lot(Spots),
+    /// `Barrier: Open`, `Barrier: Closed`, `Barrier: Locked` or
+    /// `Barrier: Emergency`
+    Barrier(BarrierState),
+    /// `Stats: 12 840 3 4`, see [`LotStats`] for the fields in order. The
+    /// average is `-` until a session finished.
+    Stats(LotStats),
+    /// `Link: ir 12` or `Link: ir never`, the age in seconds of the main
+    /// board's last contact with a peer.
+    Link(Peer, Option<u64>),
+}
+
+/// State of every spot, in spot order.
+pub type Spots = Vec<SensorState, MAX_SPOTS>;
+
+/// Counts the spots that are neither occupied nor reserved.
+pub fn free_spaces(spots: &[SensorState]) -> usize {
+    spots
+        .iter()
+        .filter(|s| **s == SensorState::NotOccupied)
+        .count()
+}
+
+/// Parses one status line sent by the main board.
+pub fn parse_message(line: &str) -> Option<Message> {
+    let (kind, value) = line.trim().split_once(':')?;
+    let value = value.trim();
+
+    match kind {
+        "Lot" => parse_snapshot(value).map(Message::Lot),
+        "Barrier" => parse_barrier(value).map(Message::Barrier),
+        "Stats" => parse_stats(value).map(Message::Stats),
+        "Link" => parse_link(value),
+        _ => None,
+    }
+}
+
+/// Parses a lot snapshot, with one `1` (occupied), `0` (free) or `R`
+/// (reserved) per spot in spot order.
+fn parse_snapshot(states: &str) -> Option<Spots> {
+    let mut spots = Spots::new();
+    for c in states.chars() {
+        let state = match c {
+            '1' => SensorState::Occupied,
+            '0' => SensorState::NotOccupied,
+            'R' => SensorState::Reserved,
+            _ => return None,
+        };
+        spots.push(state).ok()?;
+    }
+
+    if spots.is_empty() {
+        return None;
+    }
+
+    Some(spots)
+}
+
+fn parse_barrier(state: &str) -> Option<BarrierState> {
+    match state {
+        "Open" => Some(BarrierState::Open),
+        "Closed" => Some(BarrierState::Closed),
+        "Locked" => Some(BarrierState::Locked),
+        "Emergency" => Some(BarrierState::Emergency),
+        _ => None,
+    }
+}
+
+fn parse_stats(value: &str) -> Option<LotStats> {
+    let mut fields = value.split_whitespace();
+    let sessions = fields.next()?.parse().ok()?;
+    let average_dwell_secs = match fields.next()? {
+        "-" => None,
+        secs => Some(secs.parse().ok()?),
+    };
+    let turnover_per_hour = fields.next()?.parse().ok()?;
+    let peak_occupied = fields.next()?.parse().ok()?;
+
+    Some(LotStats {
+        sessions,
+        average_dwell_secs,
+        turnover_per_hour,
+        peak_occupied,
+    })
+}
+
+fn parse_link(value: &str) -> Option<Message> {
+    let (peer, age) = value.split_once(' ')?;
+    let peer = match peer {
+        "ir" => Peer::Ir,
+        _ => return None,
+    };
+    let age = match age.trim() {
+        "never" => None,
+        age => Some(age.parse().ok()?),
+    };
+
+    Some(Message::Link(peer, age))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_status_lines() {
+        use SensorState::*;
+        let spots = Spots::from_slice(&[NotOccupied, Occupied, Reserved, NotOccupied]).unwrap();
+        assert_eq!(parse_message("Lot: 01R0\r"), Some(Message::Lot(spots.clone())));
+        assert_eq!(free_spaces(&spots), 2);
+        assert_eq!(parse_message("Barrier: Emergency"), Some(Message::Barrier(BarrierState::Emergency)));
+        assert_eq!(
+            parse_message("Stats: 12 - 3 4"),
+            Some(Message::Stats(LotStats {
+                sessions: 12,
+                average_dwell_secs: None,
+                turnover_per_hour: 3,
+                peak_occupied: 4
+            }))
+        );
+        assert_eq!(parse_message("Link: ir never"), Some(Message::Link(Peer::Ir, None)));
+        assert_eq!(parse_message("Link: ir 12"), Some(Message::Link(Peer::Ir, Some(12))));
+    }
+
+    #[test]
+    fn rejects_bad_status_lines() {
+        assert_eq!(parse_message("Lot: "), None);
+        assert_eq!(parse_message("Lot: 01x0"), None);
+        assert_eq!(parse_message(&std::format!("Lot: {}", "0".repeat(MAX_SPOTS + 1))), None);
+        assert_eq!(parse_message("Barrier: Ajar"), None);
+        assert_eq!(parse_message("Stats: 12 840 3"), None);
+        assert_eq!(parse_message("Link: display 3"), None);
+        assert_eq!(parse_message("Free spaces: 2/4"), None);
+    }
+}
@@ -0,0 +1,449 @@
+//! Drawing of the display pages.
+//!
+//! Everything here goes through a generic [`DrawTarget`], so the same code
+//! drives the SSD1306 on the board and the framebuffer of the snapshot tests
+//! on the host. The snapshots are in `src/snapshots`, run the tests with
+//! `UPDATE_SNAPSHOTS=1` to write them again after changing a page.
+
+use core::fmt::Write as FmtWrite;
+use core::net::Ipv4Addr;
+
+use embedded_graphics::mono_font::ascii::{FONT_10X20, FONT_5X8, FONT_6X10};
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{Line, PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Alignment, Baseline, Text, TextStyleBuilder};
+
+use crate::lot::{self, BarrierState, LotStats, SensorState};
+use crate::ui::View;
+
+/// Height of the status bar at the top of the screen, including its separator.
+const STATUS_BAR_HEIGHT: u32 = 11;
+
+/// Space between two spot boxes and around the map.
+const GAP: u32 = 2;
+
+/// Most spots that are still drawn on a single row.
+const MAX_SINGLE_ROW: usize = 6;
+
+/// Most boxes drawn on one row of the map.
+const MAX_COLUMNS: usize = 8;
+
+/// Clears the target and draws the page described by `view`.
+pub fn draw_view<D>(target: &mut D, view: &View) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    match view {
+        View::Connecting(ip) => draw_connecting(target, *ip),
+        View::Emergency => draw_emergency(target),
+        View::Lot(spots) => draw_lot(target, spots),
+        View::Barrier(state) => draw_barrier(target, *state),
+        View::Stats(stats, spots) => draw_stats(target, *stats, *spots),
+        View::Links { main, ir } => draw_links(target, *main, *ir),
+        View::Uptime { secs, utc_secs } => draw_uptime(target, *secs, *utc_secs),
+    }
+}
+
+/// Clears the target and draws the status bar followed by one box per spot.
+/// Occupied spots are filled, free spots are outlined and reserved spots are
+/// outlined with an `R` instead of their number.
+pub fn draw_lot<D>(target: &mut D, spots: &[SensorState]) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    target.clear(BinaryColor::Off)?;
+    draw_status_bar(target, spots)?;
+    draw_map(target, spots)
+}
+
+/// Shows the board's address while waiting for WiFi and the main board.
+fn draw_connecting<D>(target: &mut D, ip: Option<Ipv4Addr>) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    target.clear(BinaryColor::Off)?;
+
+    let text_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+    match ip {
+        Some(ip) => {
+            Text::new("WiFi Connected!", Point::new(0, 8), text_style).draw(target)?;
+            Text::new("IP Address:", Point::new(0, 16), text_style).draw(target)?;
+            let mut ip_buffer = heapless::String::<16>::new();
+            let _ = write!(ip_buffer, "{}", ip);
+            Text::new(&ip_buffer, Point::new(0, 32), text_style).draw(target)?;
+        }
+        None => {
+            Text::new("Connecting...", Point::new(0, 8), text_style).draw(target)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Shows the barrier state in large letters.
+fn draw_barrier<D>(target: &mut D, state: Option<BarrierState>) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    target.clear(BinaryColor::Off)?;
+    draw_title(target, "Barrier")?;
+
+    let text = match state {
+        Some(BarrierState::Open) => "OPEN",
+        Some(BarrierState::Closed) => "CLOSED",
+        Some(BarrierState::Locked) => "LOCKED",
+        Some(BarrierState::Emergency) => "EMERGENCY",
+        None => "?",
+    };
+    draw_centered(target, text)
+}
+
+/// Fills the screen and cuts the emergency notice out of it, so it reads from
+/// afar.
+fn draw_emergency<D>(target: &mut D) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    target.clear(BinaryColor::On)?;
+
+    let size = target.bounding_box().size;
+    let centered = TextStyleBuilder::new()
+        .alignment(Alignment::Center)
+        .baseline(Baseline::Middle)
+        .build();
+    let center_x = size.width as i32 / 2;
+    Text::with_text_style(
+        "EMERGENCY",
+        Point::new(center_x, size.height as i32 / 3),
+        MonoTextStyle::new(&FONT_10X20, BinaryColor::Off),
+        centered,
+    )
+    .draw(target)?;
+    Text::with_text_style(
+        "Barrier held open",
+        Point::new(center_x, size.height as i32 * 3 / 4),
+        MonoTextStyle::new(&FONT_6X10, BinaryColor::Off),
+        centered,
+    )
+    .draw(target)?;
+
+    Ok(())
+}
+
+/// Lists the parking session statistics, one per line.
+fn draw_stats<D>(target: &mut D, stats: Option<LotStats>, spots: usize) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    target.clear(BinaryColor::Off)?;
+    draw_title(target, "Sessions")?;
+
+    let Some(stats) = stats else {
+        return draw_centered(target, "?");
+    };
+
+    let mut lines: [heapless::String<24>; 4] = Default::default();
+    let _ = write!(lines[0], "Total: {}", stats.sessions);
+    let _ = match stats.average_dwell_secs {
+        Some(secs) => write!(lines[1], "Avg stay: {}m{:02}s", secs / 60, secs % 60),
+        None => write!(lines[1], "Avg stay: -"),
+    };
+    let _ = write!(lines[2], "Last hour: {}", stats.turnover_per_hour);
+    let _ = write!(lines[3], "Peak: {}/{}", stats.peak_occupied, spots);
+
+    let text_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+    for (row, line) in lines.iter().enumerate() {
+        let y = (STATUS_BAR_HEIGHT + GAP) as i32 + row as i32 * 12;
+        Text::with_baseline(line, Point::new(0, y), text_style, Baseline::Top).draw(target)?;
+    }
+
+    Ok(())
+}
+
+/// Lists every peer with the time since it was last heard from.
+fn draw_links<D>(target: &mut D, main: Option<u64>, ir: Option<u64>) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    target.clear(BinaryColor::Off)?;
+    draw_title(target, "Links")?;
+
+    let text_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+    for (row, (name, age)) in [("Main", main), ("IR", ir)].into_iter().enumerate() {
+        let mut line = heapless::String::<32>::new();
+        let _ = match age {
+            Some(age) => write!(line, "{}: {}s ago", name, age),
+            None => write!(line, "{}: never", name),
+        };
+        let y = (STATUS_BAR_HEIGHT + GAP) as i32 + row as i32 * 12;
+        Text::with_baseline(&line, Point::new(0, y), text_style, Baseline::Top).draw(target)?;
+    }
+
+    Ok(())
+}
+
+/// Shows the time since boot as `hh:mm:ss`, prefixed by the days once there
+/// are any, and the UTC time of day under it.
+fn draw_uptime<D>(target: &mut D, secs: u64, utc_secs: Option<u64>) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    target.clear(BinaryColor::Off)?;
+    draw_title(target, "Uptime")?;
+
+    let mut text = heapless::String::<16>::new();
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+    if days > 0 {
+        let _ = write!(text, "{}d ", days);
+    }
+    let _ = write!(text, "{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60);
+    draw_centered(target, &text)?;
+
+    let mut clock = heapless::String::<24>::new();
+    let _ = match utc_secs.map(|utc| utc % 86_400) {
+        Some(t) => write!(clock, "UTC {:02}:{:02}:{:02}", t / 3600, t / 60 % 60, t % 60),
+        None => write!(clock, "Clock unsynced"),
+    };
+    let y = target.bounding_box().size.height as i32 - 10;
+    let text_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+    Text::with_baseline(&clock, Point::new(0, y), text_style, Baseline::Top).draw(target)?;
+
+    Ok(())
+}
+
+/// Draws a page title and the separator line under it.
+fn draw_title<D>(target: &mut D, title: &str) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let text_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+    Text::with_baseline(title, Point::zero(), text_style, Baseline::Top).draw(target)?;
+    draw_separator(target)
+}
+
+/// Draws `text` in large letters in the middle of the area under the title.
+fn draw_centered<D>(target: &mut D, text: &str) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let size = target.bounding_box().size;
+    let center = Point::new(
+        size.width as i32 / 2,
+        (STATUS_BAR_HEIGHT + size.height) as i32 / 2,
+    );
+    Text::with_text_style(
+        text,
+        center,
+        MonoTextStyle::new(&FONT_10X20, BinaryColor::On),
+        TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Middle)
+            .build(),
+    )
+    .draw(target)?;
+
+    Ok(())
+}
+
+/// Draws the line separating the status bar from the rest of the page.
+fn draw_separator<D>(target: &mut D) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let width = target.bounding_box().size.width;
+    let y = STATUS_BAR_HEIGHT as i32 - 1;
+    Line::new(Point::new(0, y), Point::new(width as i32 - 1, y))
+        .into_styled(PrimitiveStyle::with_stroke(BinaryColor::On, 1))
+        .draw(target)?;
+
+    Ok(())
+}
+
+/// Draws the free space count and a separator line.
+fn draw_status_bar<D>(target: &mut D, spots: &[SensorState]) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    let width = target.bounding_box().size.width;
+    let text_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+    let free_spaces = lot::free_spaces(spots);
+
+    let mut status = heapless::String::<32>::new();
+    let _ = write!(status, "Free: {}/{}", free_spaces, spots.len());
+    Text::with_baseline(&status, Point::zero(), text_style, Baseline::Top).draw(target)?;
+
+    if free_spaces == 0 {
+        Text::with_text_style(
+            "FULL",
+            Point::new(width as i32 - 1, 0),
+            text_style,
+            TextStyleBuilder::new()
+                .alignment(Alignment::Right)
+                .baseline(Baseline::Top)
+                .build(),
+        )
+        .draw(target)?;
+    }
+
+    draw_separator(target)
+}
+
+/// Lays the spots out in a grid under the status bar. Small lots get a single
+/// row, larger ones are split over two or more rows of at most
+/// [`MAX_COLUMNS`] boxes.
+fn draw_map<D>(target: &mut D, spots: &[SensorState]) -> Result<(), D::Error>
+where
+    D: DrawTarget<Color = BinaryColor>,
+{
+    if spots.is_empty() {
+        return Ok(());
+    }
+
+    let (columns, rows) = grid(spots.len());
+    let size = target.bounding_box().size;
+    let map_height = size.height.saturating_sub(STATUS_BAR_HEIGHT);
+    let box_size = Size::new(
+        size.width.saturating_sub(GAP * (columns + 1)) / columns,
+        map_height.saturating_sub(GAP * (rows + 1)) / rows,
+    );
+
+    // Center the grid horizontally, the division above may leave a few pixels
+    let used_width = columns * box_size.width + (columns - 1) * GAP;
+    let left = (size.width.saturating_sub(used_width) / 2) as i32;
+
+    let label_style = TextStyleBuilder::new()
+        .alignment(Alignment::Center)
+        .baseline(Baseline::Middle)
+        .build();
+
+    for (index, state) in spots.iter().enumerate() {
+        let column = index as u32 % columns;
+        let row = index as u32 / columns;
+        let top_left = Point::new(
+            left + (column * (box_size.width + GAP)) as i32,
+            (STATUS_BAR_HEIGHT + GAP + row * (box_size.height + GAP)) as i32,
+        );
+        let spot_box = Rectangle::new(top_left, box_size);
+
+        // Filled boxes get their number drawn in the background color
+        let (box_style, label_color) = match state {
+            SensorState::Occupied => (PrimitiveStyle::with_fill(BinaryColor::On), BinaryColor::Off),
+            SensorState::NotOccupied | SensorState::Reserved => {
+                (PrimitiveStyle::with_stroke(BinaryColor::On, 1), BinaryColor::On)
+            }
+        };
+        spot_box.into_styled(box_style).draw(target)?;
+
+        let mut label = heapless::String::<4>::new();
+        let _ = match state {
+            SensorState::Reserved => write!(label, "R"),
+            _ => write!(label, "{}", index + 1),
+        };
+        Text::with_text_style(
+            &label,
+            spot_box.center(),
+            MonoTextStyle::new(&FONT_5X8, label_color),
+            label_style,
+        )
+        .draw(target)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the number of columns and rows used for `count` spots.
+fn grid(count: usize) -> (u32, u32) {
+    let columns = if count <= MAX_SINGLE_ROW {
+        count
+    } else {
+        count.div_ceil(2).min(MAX_COLUMNS)
+    };
+    let rows = count.div_ceil(columns);
+
+    (columns as u32, rows as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::string::String;
+
+    use super::*;
+    use crate::frame::{Frame, HEIGHT, WIDTH};
+    use crate::lot::{Spots, MAX_SPOTS};
+
+    /// The page as text, `#` for a lit pixel and `.` for a dark one.
+    fn render(view: &View) -> String {
+        let mut frame = Frame::new();
+        draw_view(&mut frame, view).unwrap();
+
+        let mut lit = [[false; WIDTH as usize]; HEIGHT as usize];
+        frame.changes_since(&Frame::new(), |x, y, on| lit[y as usize][x as usize] = on);
+        let mut out = String::new();
+        for row in lit {
+            out.extend(row.iter().map(|&on| if on { '#' } else { '.' }));
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Compares the page with `src/snapshots/<name>.txt`.
+    fn assert_snapshot(name: &str, view: &View) {
+        let path = std::format!("{}/src/snapshots/{}.txt", env!("CARGO_MANIFEST_DIR"), name);
+        let actual = render(view);
+        if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+            std::fs::write(&path, &actual).unwrap();
+            return;
+        }
+        let expected = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(actual == expected, "{} differs from {}, drawn now:\n{}", name, path, actual);
+    }
+
+    fn spots(states: &[SensorState]) -> Spots {
+        Spots::from_slice(states).unwrap()
+    }
+
+    #[test]
+    fn grid_keeps_small_lots_on_one_row() {
+        assert_eq!(grid(1), (1, 1));
+        assert_eq!(grid(4), (4, 1));
+        assert_eq!(grid(MAX_SINGLE_ROW), (6, 1));
+        assert_eq!(grid(7), (4, 2));
+        assert_eq!(grid(12), (6, 2));
+        assert_eq!(grid(MAX_SPOTS), (MAX_COLUMNS as u32, 2));
+    }
+
+    #[test]
+    fn lot_map() {
+        use SensorState::*;
+        assert_snapshot("lot", &View::Lot(spots(&[Occupied, NotOccupied, Reserved, NotOccupied])));
+        assert_snapshot("lot_full", &View::Lot(spots(&[Occupied, Occupied, Reserved, Occupied])));
+        let ten = [Occupied, NotOccupied, NotOccupied, Occupied, Reserved];
+        assert_snapshot("lot_two_rows", &View::Lot(spots(&[ten, ten].concat())));
+    }
+
+    #[test]
+    fn status_pages() {
+        assert_snapshot("connecting", &View::Connecting(Some(Ipv4Addr::new(192, 168, 23, 41))));
+        assert_snapshot("barrier", &View::Barrier(Some(BarrierState::Locked)));
+        let stats = LotStats {
+            sessions: 12,
+            average_dwell_secs: Some(840),
+            turnover_per_hour: 3,
+            peak_occupied: 4,
+        };
+        assert_snapshot("stats", &View::Stats(Some(stats), 4));
+        assert_snapshot("links", &View::Links { main: Some(2), ir: None });
+        assert_snapshot("uptime", &View::Uptime { secs: 90_061, utc_secs: Some(1_760_000_000) });
+    }
+
+    #[test]
+    fn emergency_fills_the_screen() {
+        assert_snapshot("emergency", &View::Emergency);
+
+        // Mostly lit, the text is cut out of it
+        let lit = render(&View::Emergency).chars().filter(|&c| c == '#').count();
+        assert!(lit > (WIDTH * HEIGHT) as usize * 3 / 4);
+    }
+}
@@ -0,0 +1,133 @@
+//! Pages shown on the OLED and the state they are built from.
+//!
+//! The network side sends [`Update`]s to the UI task, which keeps the
+//! [`Status`], turns it into a [`View`] of the current page and only redraws
+//! when the view differs from the one on screen. Ages and uptime are kept in whole seconds, so those pages redraw at
+//! most once per second.
+
+use core::net::Ipv4Addr;
+
+use embassy_time::Instant;
+
+use crate::lot::{BarrierState, LotStats, Message, Peer, Spots};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Page {
+    Lot,
+    Barrier,
+    Stats,
+    Links,
+    Uptime,
+}
+
+impl Page {
+    pub fn next(self) -> Self {
+        match self {
+            Page::Lot => Page::Barrier,
+            Page::Barrier => Page::Stats,
+            Page::Stats => Page::Links,
+            Page::Links => Page::Uptime,
+            Page::Uptime => Page::Lot,
+        }
+    }
+}
+
+/// Change to the status, sent from the network side to the UI task.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Update {
+    /// The board got its address and is about to wait for the main board.
+    Connected(Ipv4Addr),
+    /// The main board sent something at the given time.
+    MainSeen(Instant),
+    /// One status line from the main board and the time it arrived.
+    Message(Message, Instant),
+}
+
+/// Everything the pages show, as last reported by the main board.
+pub struct Status {
+    ip: Option<Ipv4Addr>,
+    spots: Spots,
+    barrier: Option<BarrierState>,
+    stats: Option<LotStats>,
+    /// Last time anything arrived from the main board.
+    main_last_seen: Option<Instant>,
+    /// IR board link age reported by the main board and when it was reported.
+    ir_last_seen: Option<(u64, Instant)>,
+}
+
+/// What a page shows, compared against the last drawn view to skip redraws.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum View {
+    /// Shown instead of the pages until the main board reports in.
+    Connecting(Option<Ipv4Addr>),
+    /// Shown instead of the pages while the main board reports an emergency.
+    Emergency,
+    Lot(Spots),
+    Barrier(Option<BarrierState>),
+    /// Session statistics and the number of spots, for the peak.
+    Stats(Option<LotStats>, usize),
+    /// Seconds since each peer was last seen, `None` if it never was.
+    Links { main: Option<u64>, ir: Option<u64> },
+    /// Seconds since boot and the UTC time in seconds since the Unix epoch,
+    /// `None` until the clock is synced.
+    Uptime { secs: u64, utc_secs: Option<u64> },
+}
+
+impl Status {
+    pub const fn new() -> Self {
+        Self {
+            ip: None,
+            spots: Spots::new(),
+            barrier: None,
+            stats: None,
+            main_last_seen: None,
+            ir_last_seen: None,
+        }
+    }
+
+    pub fn apply(&mut self, update: Update) {
+        match update {
+            Update::Connected(ip) => self.ip = Some(ip),
+            Update::MainSeen(at) => self.main_last_seen = Some(at),
+            Update::Message(Message::Lot(spots), _) => self.spots = spots,
+            Update::Message(Message::Barrier(state), _) => self.barrier = Some(state),
+            Update::Message(Message::Stats(stats), _) => self.stats = Some(stats),
+            Update::Message(Message::Link(Peer::Ir, age), at) => {
+                self.ir_last_seen = age.map(|age| (age, at))
+            }
+            Update::Message(Message::Link(Peer::Main, _), _) => {}
+        }
+    }
+
+    /// `utc_secs` is the current UTC time if the clock is synced.
+    pub fn view(&self, page: Page, now: Instant, utc_secs: Option<u64>) -> View {
+        if self.main_last_seen.is_none() {
+            return View::Connecting(self.ip);
+        }
+        if self.barrier == Some(BarrierState::Emergency) {
+            return View::Emergency;
+        }
+
+        match page {
+            Page::Lot => View::Lot(self.spots.clone()),
+            Page::Barrier => View::Barrier(self.barrier),
+            Page::Stats => View::Stats(self.stats, self.spots.len()),
+            Page::Links => View::Links {
+                main: self.main_last_seen.map(|at| (now - at).as_secs()),
+                ir: self
+                    .ir_last_seen
+                    .map(|(age, at)| age + (now - at).as_secs()),
+            },
+            Page::Uptime => View::Uptime {
+                secs: now.as_secs(),
+                utc_secs,
+            },
+        }
+    }
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Self::new()
+    }
+}
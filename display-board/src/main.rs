@@ -2,30 +2,31 @@
 #![no_main]
 #![allow(async_fn_in_trait)]
 
-use core::fmt::Write as FmtWrite;
-use core::str::FromStr;
+use core::sync::atomic::{AtomicBool, Ordering};
 
-use embassy_time::{Timer, Duration};
-use cyw43::JoinOptions;
+use embassy_futures::select::{select3, Either3};
+use embassy_time::{Timer, Duration, Instant};
 use defmt::*;
 use embassy_executor::Spawner;
 use embassy_net::tcp::TcpSocket;
-use embassy_net::StackResources;
+use embassy_net::{Ipv4Address, Ipv4Cidr, StackResources, StaticConfigV4};
+use embassy_lab_utils::wifi::Network;
+use embassy_lab_utils::supervisor::{self, Heartbeat};
+#[cfg(feature = "netlog")]
+use embassy_lab_utils::netlog;
+use embassy_lab_utils::{announce, ap, ota, sntp, Addressing};
 use embassy_rp::bind_interrupts;
+use embassy_rp::gpio::{Input, Pull};
 use embassy_rp::i2c::{self, Config as I2cConfig};
-use embedded_graphics::mono_font::ascii::FONT_6X10;
-use embedded_graphics::mono_font::MonoTextStyle;
-use embedded_graphics::pixelcolor::BinaryColor;
-use embedded_graphics::text::Text;
+use embassy_rp::peripherals::I2C0;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use ssd1306::mode::BufferedGraphicsModeAsync;
 use ssd1306::prelude::*;
 use ssd1306::size::DisplaySize128x64;
 use ssd1306::I2CDisplayInterface;
-use ssd1306::Ssd1306;
-use embedded_graphics::draw_target::DrawTarget;
-use embedded_graphics::Drawable;
-use embedded_graphics::prelude::Point;
+use ssd1306::Ssd1306Async;
 use static_cell::StaticCell;
-use {defmt_rtt as _, panic_probe as _};
 
 bind_interrupts!(struct Irqs {
     I2C0_IRQ => embassy_rp::i2c::InterruptHandler<embassy_rp::peripherals::I2C0>;
@@ -33,15 +34,187 @@ bind_interrupts!(struct Irqs {
 
 mod irqs;
 
+use display_board_core::{lot, render};
+use display_board_core::frame::Frame;
+use display_board_core::ui::{Page, Status, Update, View};
+
 const SOCK: usize = 20;
 static RESOURCES: StaticCell<StackResources<SOCK>> = StaticCell::<StackResources<SOCK>>::new();
 const WIFI_NETWORK: &str = "desk";
 const WIFI_PASSWORD: &str = "testing123";
+/// Our usual network first, then the main board's access point.
+const NETWORKS: &[Network] = &[Network::new(WIFI_NETWORK, WIFI_PASSWORD), ap::AP_NETWORK];
+
+/// Name sent to the DHCP server.
+const HOSTNAME: &str = "parking-display";
+/// Lowest level streamed on the log port for `tools/netlog`, `None` keeps
+/// the log on RTT.
+#[cfg(feature = "netlog")]
+const NET_LOG: Option<netlog::Level> = Some(netlog::Level::Info);
+/// Where the clock is synced from, a host name or an IPv4 address.
+const NTP_SERVER: &str = "pool.ntp.org";
+/// Address used when DHCP doesn't answer, the one the main board expects.
+const FALLBACK_IP: Ipv4Address = Ipv4Address::new(192, 168, 23, 41);
+/// How long to wait for a DHCP lease before using [`FALLBACK_IP`].
+const DHCP_FALLBACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long each page stays on screen when the pages rotate on their own.
+const PAGE_INTERVAL: Duration = Duration::from_secs(5);
+/// How long the page chosen with the button stays before rotation resumes.
+const BUTTON_HOLD: Duration = Duration::from_secs(30);
+/// How often the current page is checked for changes, which keeps the ages
+/// and the uptime ticking.
+const UI_TICK: Duration = Duration::from_secs(1);
+/// How long the UI task may go without checking in, a hung display bus stops
+/// it.
+const UI_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of status updates that may wait for the UI task before new ones
+/// are dropped.
+const UPDATE_QUEUE: usize = 8;
+
+type Display = Ssd1306Async<
+    I2CInterface<i2c::I2c<'static, I2C0, i2c::Async>>,
+    DisplaySize128x64,
+    BufferedGraphicsModeAsync<DisplaySize128x64>,
+>;
 
-#[derive(Debug, PartialEq)]
-enum SensorState {
-    Occupied,
-    NotOccupied,
+/// Shortest and longest wait between two display init attempts.
+const INIT_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const INIT_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// Set while the display does not respond, reported back to the main board.
+static DISPLAY_FAULT: AtomicBool = AtomicBool::new(false);
+
+/// Status updates from the network side to the UI task.
+static UPDATES: Channel<CriticalSectionRawMutex, Update, UPDATE_QUEUE> = Channel::new();
+
+/// Hands an update to the UI task without waiting for it. If the UI is behind
+/// the update is dropped; the main board resends its status periodically.
+fn send_update(update: Update) {
+    if UPDATES.try_send(update).is_err() {
+        warn!("UI busy, dropping status update");
+    }
+}
+
+/// Owns the display and shows one page at a time. Pages rotate every
+/// [`PAGE_INTERVAL`], or are picked with the button, and are only redrawn when
+/// what they show changed.
+///
+/// Pages are rendered off-screen and compared with the previous frame, so
+/// only the changed pixels reach the display buffer and the flush only sends
+/// the area around them.
+///
+/// Display errors never stop the task: it keeps applying status updates
+/// headless and retries the init with an increasing delay until the display
+/// answers again.
+#[embassy_executor::task]
+async fn ui_task(mut display: Display, mut button: Input<'static>, heartbeat: Heartbeat) {
+    let mut status = Status::new();
+    let mut page = Page::Lot;
+    let mut page_shown_at = Instant::now();
+    let mut rotation_paused_until: Option<Instant> = None;
+    let mut last_view: Option<View> = None;
+    let mut frame: Option<Frame> = None;
+
+    // Display health, starting with an init attempt right away
+    let mut display_ready = false;
+    let mut init_at = Instant::now();
+    let mut init_backoff = INIT_BACKOFF_MIN;
+
+    loop {
+        heartbeat.beat();
+
+        // Apply everything that queued up while the last frame was flushed
+        while let Ok(update) = UPDATES.try_receive() {
+            status.apply(update);
+        }
+
+        let now = Instant::now();
+
+        // Bring the display (back) up
+        if !display_ready && now >= init_at {
+            match display.init().await {
+                Ok(()) => {
+                    info!("Display ready");
+                    display_ready = true;
+                    init_backoff = INIT_BACKOFF_MIN;
+                    set_display_fault(false);
+
+                    // Whatever was on screen is gone, draw everything again
+                    frame = None;
+                    last_view = None;
+                }
+                Err(e) => {
+                    warn!("Display init failed: {}, retrying in {}s", Debug2Format(&e), init_backoff.as_secs());
+                    init_at = now + init_backoff;
+                    init_backoff = (init_backoff * 2).min(INIT_BACKOFF_MAX);
+                    set_display_fault(true);
+                }
+            }
+        }
+
+        let rotation_paused = rotation_paused_until.is_some_and(|until| now < until);
+        if !rotation_paused && now - page_shown_at >= PAGE_INTERVAL {
+            page = page.next();
+            page_shown_at = now;
+        }
+
+        // Redraw only when the page shows something new
+        let view = status.view(page, now, sntp::now_utc().map(|utc| utc.as_unix_secs()));
+        if display_ready && last_view.as_ref() != Some(&view) {
+            let mut next = Frame::new();
+            let _ = render::draw_view(&mut next, &view);
+
+            match &frame {
+                Some(previous) => next.changes_since(previous, |x, y, on| display.set_pixel(x, y, on)),
+                None => {
+                    display.clear_buffer();
+                    next.changes_since(&Frame::new(), |x, y, on| display.set_pixel(x, y, on));
+                }
+            }
+
+            match display.flush().await {
+                Ok(()) => {
+                    frame = Some(next);
+                    last_view = Some(view);
+                }
+                Err(e) => {
+                    warn!("Display flush failed: {}, running headless", Debug2Format(&e));
+                    display_ready = false;
+                    init_at = now + init_backoff;
+                    set_display_fault(true);
+                }
+            }
+        }
+
+        // Headless, wake up in time for the next init attempt
+        let tick = if display_ready {
+            UI_TICK
+        } else {
+            init_at.saturating_duration_since(Instant::now()).min(UI_TICK)
+        };
+
+        match select3(UPDATES.receive(), button.wait_for_falling_edge(), Timer::after(tick)).await {
+            Either3::First(update) => status.apply(update),
+            Either3::Second(_) => {
+                // Next page, and stay there for a while
+                page = page.next();
+                page_shown_at = Instant::now();
+                rotation_paused_until = Some(page_shown_at + BUTTON_HOLD);
+                info!("Page selected: {}", page);
+
+                // Debounce the button
+                Timer::after(Duration::from_millis(50)).await;
+            }
+            Either3::Third(_) => {}
+        }
+    }
+}
+
+/// Records the display health reported back to the main board.
+fn set_display_fault(fault: bool) {
+    DISPLAY_FAULT.store(fault, Ordering::Relaxed);
 }
 
 #[embassy_executor::main]
@@ -50,15 +223,36 @@ async fn main(spawner: Spawner) {
 
     let peripherals = embassy_rp::init(Default::default());
 
+    // Restart the board whenever the UI hangs
+    supervisor::start(&spawner, peripherals.WATCHDOG);
+
     // Init WiFi driver
-    let (net_device, mut control) = embassy_lab_utils::init_wifi!(&spawner, peripherals).await;
+    let (net_device, control) = embassy_lab_utils::init_wifi!(&spawner, peripherals).await;
 
-    // Default config for dynamic IP address
-    let config = embassy_net::Config::dhcpv4(Default::default());
+    // DHCP, falling back to the address the main board used to expect
+    let addressing = Addressing::DhcpWithFallback {
+        hostname: Some(HOSTNAME),
+        fallback: StaticConfigV4 {
+            address: Ipv4Cidr::new(FALLBACK_IP, 24),
+            gateway: None,
+            dns_servers: heapless::Vec::new(),
+        },
+        timeout: DHCP_FALLBACK_TIMEOUT,
+    };
 
     // Init network stack
-    let stack = embassy_lab_utils::init_network_stack(&spawner, net_device, &RESOURCES, config);
-    
+    let stack = embassy_lab_utils::init_network_stack(&spawner, net_device, &RESOURCES, addressing);
+
+    // Stream the log to `tools/netlog`
+    #[cfg(feature = "netlog")]
+    if let Some(level) = NET_LOG {
+        netlog::start(&spawner, stack, level);
+    }
+
+    // Take firmware updates relayed by the main board
+    let flash = ota::init_flash(peripherals.FLASH);
+    ota::start(&spawner, stack, flash, "display", false);
+
     // Configure I2C for SSD1306 OLED Display
     let i2c = i2c::I2c::new_async(
         peripherals.I2C0,
@@ -68,65 +262,32 @@ async fn main(spawner: Spawner) {
         I2cConfig::default(),
     );
 
-    // Initialize SSD1306 OLED Display
+    // SSD1306 OLED Display, initialized by the UI task
     let interface = I2CDisplayInterface::new(i2c);
-    let mut display: Ssd1306<_, _, _> = Ssd1306::new(interface, DisplaySize128x64, DisplayRotation::Rotate0)
+    let display: Display = Ssd1306Async::new(interface, DisplaySize128x64, DisplayRotation::Rotate0)
         .into_buffered_graphics_mode();
-    display.init().unwrap();
-    display.clear(BinaryColor::Off).unwrap();
 
-    // Parking lot state
-    const TOTAL_SPACES: u64 = 4;
-    let mut free_spaces = TOTAL_SPACES;
+    // Button for choosing the page, wired to ground
+    let button = Input::new(peripherals.PIN_14, Pull::Up);
 
-    // Array to track the state of sensors 1, 2, 3, and 4
-    let mut sensor_states = [const { SensorState::NotOccupied }; 4];
-    
-    // Connect to WiFi
-    loop {
-        match control
-            .join(WIFI_NETWORK, JoinOptions::new(WIFI_PASSWORD.as_bytes()))
-            .await
-        {
-            Ok(_) => {
-                info!("Successfully joined WiFi network: {}", WIFI_NETWORK);
-
-                // Wait until the network stack is configured
-                loop {
-                    if stack.is_config_up() {
-                        if let Some(ip_config) = stack.config_v4() {
-                            let ip = ip_config.address.address();
-                            info!("Assigned IP address: {}", ip);
-
-                            // Display the IP address on the OLED
-                            display.clear(BinaryColor::Off).unwrap();
-                            let text_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
-                            Text::new("WiFi Connected!", Point::new(0, 8), text_style)
-                                .draw(&mut display)
-                                .unwrap();
-                            Text::new("IP Address:", Point::new(0, 16), text_style)
-                                .draw(&mut display)
-                                .unwrap();
-                            let mut ip_buffer = heapless::String::<64>::new();
-                            FmtWrite::write_fmt(&mut ip_buffer, format_args!("{}", ip)).unwrap();
-                            Text::new(&ip_buffer, Point::new(0, 32), text_style)
-                                .draw(&mut display)
-                                .unwrap();
-                            display.flush().unwrap();
-
-                            break;
-                        }
-                    }
-                    Timer::after(Duration::from_millis(100)).await;
-                }
-                break;
-            }
-            Err(err) => {
-                info!("Join failed with status={}", err.status);
-                Timer::after(Duration::from_secs(1)).await;
-            }
-        }
-    }
+    // Hand the display over to the pages
+    spawner.spawn(ui_task(display, button, supervisor::register("ui", UI_TIMEOUT))).unwrap();
+
+    // Connect to WiFi, and keep reconnecting if the link drops
+    embassy_lab_utils::wifi::start(&spawner, control, stack, NETWORKS);
+    let ip = embassy_lab_utils::wifi::wait_connected().await;
+
+    // Tell the main board where we are
+    announce::start(&spawner, stack, "display");
+
+    // Keep the clock synced for the uptime page
+    sntp::start(&spawner, stack, NTP_SERVER);
+
+    // Display the IP address on the OLED
+    send_update(Update::Connected(ip));
+
+    // Connected and showing the lot, keep this firmware
+    ota::healthy();
 
     // Listen for incoming TCP connections on port 6000
     loop {
@@ -158,41 +319,25 @@ async fn main(spawner: Spawner) {
     
             if let Ok(data) = core::str::from_utf8(&buf[..n]) {
                 info!("Received data: {}", data);
-    
-                if let Some((sensor_no, new_state)) = parse_sensor_data(data) {
-                    if sensor_no >= 1 && sensor_no <= 4 {
-                        let sensor_index = (sensor_no - 1) as usize;
-    
-                        if sensor_states[sensor_index] != new_state {
-                            match new_state {
-                                SensorState::Occupied => {
-                                    if free_spaces > 0 {
-                                        free_spaces -= 1;
-                                    }
-                                }
-                                SensorState::NotOccupied => {
-                                    if free_spaces < TOTAL_SPACES {
-                                        free_spaces += 1;
-                                    }
-                                }
-                            }
-    
-                            sensor_states[sensor_index] = new_state;
-    
-                            display.clear(BinaryColor::Off).unwrap();
-                            let text_style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
-                            let mut parking_status = heapless::String::<64>::new();
-                            FmtWrite::write_fmt(
-                                &mut parking_status,
-                                format_args!("Free spaces: {}/{}", free_spaces, TOTAL_SPACES),
-                            )
-                            .unwrap();
-                            Text::new(&parking_status, Point::new(0, 8), text_style)
-                                .draw(&mut display)
-                                .unwrap();
-                            display.flush().unwrap();
-                        }
+
+                // One status item per line, the snapshot is authoritative
+                let now = Instant::now();
+                send_update(Update::MainSeen(now));
+                for message in data.lines().filter_map(lot::parse_message) {
+                    if let lot::Message::Lot(spots) = &message {
+                        info!("Free spaces: {}/{}", lot::free_spaces(spots), spots.len());
                     }
+                    send_update(Update::Message(message, now));
+                }
+
+                // Report the display health back to the main board
+                let reply = if DISPLAY_FAULT.load(Ordering::Relaxed) {
+                    "Display: Fault\n"
+                } else {
+                    "Display: Ok\n"
+                };
+                if let Err(e) = socket.write(reply.as_bytes()).await {
+                    warn!("write error: {:?}", e);
                 }
             }
         }
@@ -200,27 +345,3 @@ async fn main(spawner: Spawner) {
         socket.close();
     }
 }
-
-/// Parses the received data in the format `Sensor x: Occupied` or `Sensor x: Not Occupied`.
-fn parse_sensor_data(data: &str) -> Option<(u64, SensorState)> {
-    let data = data.trim();
-
-    if let Some((sensor_part, state_part)) = data.split_once(":") {
-        let sensor_part = sensor_part.trim();
-        let state_part = state_part.trim();
-
-        if sensor_part.starts_with("Sensor") {
-            if let Some(sensor_no_str) = sensor_part.strip_prefix("Sensor ") {
-                if let Ok(sensor_no) = u64::from_str(sensor_no_str) {
-                    if state_part == "Occupied" {
-                        return Some((sensor_no, SensorState::Occupied));
-                    } else if state_part == "Not Occupied" {
-                        return Some((sensor_no, SensorState::NotOccupied));
-                    }
-                }
-            }
-        }
-    }
-
-    None
-}
\ No newline at end of file
@@ -0,0 +1,295 @@
+//! The crash record kept over a restart and the reports made from it.
+//!
+//! The record sits in RAM that survives the restart, so it is checked with a
+//! magic and an FNV-1a checksum before it is believed; the random RAM after
+//! power on doesn't pass.
+
+use core::fmt::{self, Write};
+
+use heapless::String;
+
+/// Longest detail kept, longer panic messages are cut.
+pub const MAX_DETAIL: usize = 96;
+
+/// Marks a written record, `CRSH`.
+const MAGIC: u32 = 0x4853_5243;
+
+/// What a record says ended the last run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+#[repr(u32)]
+pub enum Ended {
+    /// Still running, the last write kept the uptime current.
+    Running = 0,
+    Panic = 1,
+    Stall = 2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Cause {
+    /// Power on, nothing is known about a last run.
+    PowerOn,
+    /// The reset button, or a restart on purpose like after an update.
+    Restart,
+    Panic,
+    /// A supervised task stopped checking in, the detail names it.
+    Stall,
+    /// The watchdog ran out, something blocked the executor.
+    Watchdog,
+}
+
+impl Cause {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Cause::PowerOn => "power_on",
+            Cause::Restart => "restart",
+            Cause::Panic => "panic",
+            Cause::Stall => "stall",
+            Cause::Watchdog => "watchdog",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        [Cause::PowerOn, Cause::Restart, Cause::Panic, Cause::Stall, Cause::Watchdog]
+            .into_iter()
+            .find(|cause| cause.as_str() == s)
+    }
+}
+
+/// Why a board restarted and how long the run before lasted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    pub cause: Cause,
+    pub uptime_secs: u32,
+    /// `<file>:<line> <message>` of a panic, the task of a stall.
+    pub detail: String<MAX_DETAIL>,
+}
+
+impl Report {
+    /// After power on, when nothing is known.
+    pub fn power_on() -> Self {
+        Report {
+            cause: Cause::PowerOn,
+            uptime_secs: 0,
+            detail: String::new(),
+        }
+    }
+
+    /// `Reset: <board> <cause> <uptime secs> <detail>`, the detail may hold
+    /// spaces and be empty.
+    pub fn write_line(&self, board: &str, out: &mut impl Write) -> fmt::Result {
+        writeln!(out, "Reset: {} {} {} {}", board, self.cause.as_str(), self.uptime_secs, self.detail)
+    }
+
+    /// Parses a line written by [`Report::write_line`] into the board and the
+    /// report.
+    pub fn parse_line(line: &str) -> Option<(&str, Report)> {
+        let mut parts = line.trim().strip_prefix("Reset:")?.trim_start().splitn(4, ' ');
+        let board = parts.next()?;
+        let cause = Cause::parse(parts.next()?)?;
+        let uptime_secs = parts.next()?.parse().ok()?;
+        let mut detail = String::new();
+        push_truncated(&mut detail, parts.next().unwrap_or("").trim());
+        Some((board, Report { cause, uptime_secs, detail }))
+    }
+}
+
+/// What is kept over a restart, as it lies in RAM.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Record {
+    magic: u32,
+    ended: u32,
+    uptime_ms: u64,
+    detail_len: u32,
+    detail: [u8; MAX_DETAIL],
+    checksum: u32,
+}
+
+impl Record {
+    /// `detail` is cut to [`MAX_DETAIL`] bytes, at a character boundary.
+    pub fn new(ended: Ended, uptime_ms: u64, detail: &str) -> Self {
+        let mut cut: String<MAX_DETAIL> = String::new();
+        push_truncated(&mut cut, detail);
+        let mut record = Record {
+            magic: MAGIC,
+            ended: ended as u32,
+            uptime_ms,
+            detail_len: cut.len() as u32,
+            detail: [0; MAX_DETAIL],
+            checksum: 0,
+        };
+        record.detail[..cut.len()].copy_from_slice(cut.as_bytes());
+        record.checksum = record.sum();
+        record
+    }
+
+    /// FNV-1a over everything but the checksum.
+    fn sum(&self) -> u32 {
+        let head = [self.magic, self.ended, self.uptime_ms as u32, (self.uptime_ms >> 32) as u32, self.detail_len];
+        head.iter()
+            .flat_map(|word| word.to_le_bytes())
+            .chain(self.detail.iter().copied())
+            .fold(0x811c_9dc5, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193))
+    }
+
+    fn detail(&self) -> Option<&str> {
+        core::str::from_utf8(self.detail.get(..self.detail_len as usize)?).ok()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.magic == MAGIC && self.checksum == self.sum() && self.detail().is_some()
+    }
+
+    /// What the record tells about the last run, `watchdog` being whether
+    /// the watchdog reset the board. `None` for a record that doesn't check
+    /// out.
+    pub fn report(&self, watchdog: bool) -> Option<Report> {
+        if !self.is_valid() {
+            return None;
+        }
+        let cause = match self.ended {
+            e if e == Ended::Panic as u32 => Cause::Panic,
+            e if e == Ended::Stall as u32 => Cause::Stall,
+            _ if watchdog => Cause::Watchdog,
+            _ => Cause::Restart,
+        };
+        let mut detail = String::new();
+        push_truncated(&mut detail, self.detail()?);
+        Some(Report {
+            cause,
+            uptime_secs: (self.uptime_ms / 1000) as u32,
+            detail,
+        })
+    }
+}
+
+/// Pushes as much of `s` as fits.
+pub fn push_truncated<const N: usize>(out: &mut String<N>, s: &str) {
+    for c in s.chars() {
+        if out.push(c).is_err() {
+            break;
+        }
+    }
+}
+
+/// Cuts what is written to it at the capacity instead of failing, and keeps
+/// it on one line.
+pub struct Truncating(pub String<MAX_DETAIL>);
+
+impl Write for Truncating {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if self.0.push(if c.is_control() { ' ' } else { c }).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(cause: Cause, uptime_secs: u32, detail: &str) -> Report {
+        Report {
+            cause,
+            uptime_secs,
+            detail: String::try_from(detail).unwrap(),
+        }
+    }
+
+    /// The record's bytes, as the RAM holds them.
+    fn bytes_mut(record: &mut Record) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut((record as *mut Record).cast::<u8>(), core::mem::size_of::<Record>()) }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let record = Record::new(Ended::Panic, 61_500, "main.rs:42 oops");
+        assert!(record.is_valid());
+        assert_eq!(record.report(false), Some(report(Cause::Panic, 61, "main.rs:42 oops")));
+        // A panic stays a panic whatever reset the board
+        assert_eq!(record.report(true), Some(report(Cause::Panic, 61, "main.rs:42 oops")));
+
+        let stall = Record::new(Ended::Stall, 9_000, "barrier");
+        assert_eq!(stall.report(true), Some(report(Cause::Stall, 9, "barrier")));
+    }
+
+    #[test]
+    fn running_record_tells_the_reset() {
+        let record = Record::new(Ended::Running, 3_600_000, "");
+        assert_eq!(record.report(true), Some(report(Cause::Watchdog, 3600, "")));
+        assert_eq!(record.report(false), Some(report(Cause::Restart, 3600, "")));
+    }
+
+    #[test]
+    fn damaged_records_are_rejected() {
+        let mut record = Record::new(Ended::Panic, 1000, "main.rs:42 oops");
+        // Random RAM after power on
+        bytes_mut(&mut record).fill(0xa5);
+        assert!(!record.is_valid());
+        assert_eq!(record.report(false), None);
+
+        // One flipped bit anywhere
+        let good = Record::new(Ended::Panic, 1000, "main.rs:42 oops");
+        for i in 0..core::mem::size_of::<Record>() {
+            let mut record = good;
+            bytes_mut(&mut record)[i] ^= 0x10;
+            // Padding isn't covered, and the bytes past the detail don't matter
+            let padding = i >= 4 * 5 + MAX_DETAIL && i < core::mem::offset_of!(Record, checksum);
+            let unused = i >= core::mem::offset_of!(Record, detail) + 15 && i < core::mem::offset_of!(Record, detail) + MAX_DETAIL;
+            if !padding && !unused {
+                assert!(!record.is_valid(), "bit flipped in byte {}", i);
+            }
+        }
+
+        // A checksum that matches but a wrong magic
+        let mut record = good;
+        record.magic = 0;
+        record.checksum = record.sum();
+        assert!(!record.is_valid());
+
+        // A detail that runs past the buffer
+        let mut record = good;
+        record.detail_len = MAX_DETAIL as u32 + 1;
+        record.checksum = record.sum();
+        assert!(!record.is_valid());
+    }
+
+    #[test]
+    fn long_details_are_cut() {
+        let long = "é".repeat(MAX_DETAIL);
+        let record = Record::new(Ended::Panic, 0, &long);
+        let detail = record.report(false).unwrap().detail;
+        assert_eq!(detail.len(), MAX_DETAIL);
+        assert!(long.starts_with(detail.as_str()));
+
+        let mut out = Truncating(String::new());
+        write!(out, "line one\nline two {}", long).unwrap();
+        assert!(out.0.starts_with("line one line two é"));
+        assert_eq!(out.0.len(), MAX_DETAIL);
+    }
+
+    #[test]
+    fn lines_round_trip() {
+        let sent = report(Cause::Panic, 61, "main.rs:42 index out of bounds");
+        let mut line = std::string::String::new();
+        sent.write_line("display", &mut line).unwrap();
+        assert_eq!(line, "Reset: display panic 61 main.rs:42 index out of bounds\n");
+        assert_eq!(Report::parse_line(&line), Some(("display", sent)));
+
+        let mut line = std::string::String::new();
+        Report::power_on().write_line("ir", &mut line).unwrap();
+        assert_eq!(Report::parse_line(&line), Some(("ir", Report::power_on())));
+    }
+
+    #[test]
+    fn bad_lines() {
+        assert_eq!(Report::parse_line("Board: main 192.168.23.155"), None);
+        assert_eq!(Report::parse_line("Reset: main"), None);
+        assert_eq!(Report::parse_line("Reset: main melted 5 hot"), None);
+        assert_eq!(Report::parse_line("Reset: main panic -5 oops"), None);
+        assert_eq!(Report::parse_line("Reset: main stall 5"), Some(("main", report(Cause::Stall, 5, ""))));
+    }
+}
@@ -0,0 +1,403 @@
+//! Minimal DHCP server for the access point, without the socket.
+//!
+//! Answers DISCOVER and REQUEST from a small address pool, one address per
+//! client hardware address. Leases never expire, the pool is sized for the
+//! boards of one lot plus a few phones or laptops.
+
+use core::net::Ipv4Addr;
+
+/// First address handed out, the pool runs up from here.
+const POOL_START: u8 = 100;
+pub const POOL_SIZE: usize = 16;
+
+/// Lease time announced to the clients.
+const LEASE_SECS: u32 = 24 * 60 * 60;
+
+/// Longest reply the server builds.
+pub const REPLY_LEN: usize = 300;
+
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Offset of the options, after the fixed BOOTP header and the magic cookie.
+const OPTIONS_OFFSET: usize = 240;
+
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PAD: u8 = 0;
+const OPT_END: u8 = 255;
+
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+const RELEASE: u8 = 7;
+
+pub type Mac = [u8; 6];
+
+struct Leases {
+    network: [u8; 3],
+    clients: [Option<Mac>; POOL_SIZE],
+}
+
+impl Leases {
+    fn new(server: Ipv4Addr) -> Self {
+        let [a, b, c, _] = server.octets();
+        Self {
+            network: [a, b, c],
+            clients: [None; POOL_SIZE],
+        }
+    }
+
+    fn address(&self, index: usize) -> Ipv4Addr {
+        let [a, b, c] = self.network;
+        Ipv4Addr::new(a, b, c, POOL_START + index as u8)
+    }
+
+    fn index_of(&self, ip: Ipv4Addr) -> Option<usize> {
+        let [a, b, c, d] = ip.octets();
+        if [a, b, c] != self.network {
+            return None;
+        }
+        let index = d.checked_sub(POOL_START)? as usize;
+        (index < POOL_SIZE).then_some(index)
+    }
+
+    /// Address already leased to `mac`, or a free one.
+    fn offer(&mut self, mac: Mac) -> Option<Ipv4Addr> {
+        let index = self
+            .clients
+            .iter()
+            .position(|c| *c == Some(mac))
+            .or_else(|| self.clients.iter().position(Option::is_none))?;
+        Some(self.address(index))
+    }
+
+    /// Confirms the lease of `ip` to `mac`, unless someone else holds it.
+    fn request(&mut self, mac: Mac, ip: Ipv4Addr) -> bool {
+        let Some(index) = self.index_of(ip) else {
+            return false;
+        };
+        match self.clients[index] {
+            Some(owner) if owner != mac => false,
+            _ => {
+                // A client only holds one address
+                self.release(mac);
+                self.clients[index] = Some(mac);
+                true
+            }
+        }
+    }
+
+    fn release(&mut self, mac: Mac) {
+        for client in self.clients.iter_mut().filter(|c| **c == Some(mac)) {
+            *client = None;
+        }
+    }
+}
+
+/// The parts of a client message the server cares about.
+struct ClientMessage<'a> {
+    message_type: u8,
+    mac: Mac,
+    ciaddr: Ipv4Addr,
+    requested_ip: Option<Ipv4Addr>,
+    /// Fixed header of the request, echoed back in the reply.
+    header: &'a [u8],
+}
+
+fn parse(packet: &[u8]) -> Option<ClientMessage<'_>> {
+    // BOOTREQUEST over Ethernet only
+    if packet.len() < OPTIONS_OFFSET || packet[0] != 1 || packet[1] != 1 || packet[2] != 6 {
+        return None;
+    }
+    if packet[236..240] != MAGIC_COOKIE {
+        return None;
+    }
+
+    let mut mac = [0; 6];
+    mac.copy_from_slice(&packet[28..34]);
+    let ciaddr = ipv4_at(packet, 12)?;
+
+    let mut message_type = None;
+    let mut requested_ip = None;
+    let mut options = &packet[OPTIONS_OFFSET..];
+    while let [code, rest @ ..] = options {
+        match *code {
+            OPT_PAD => {
+                options = rest;
+                continue;
+            }
+            OPT_END => break,
+            _ => {}
+        }
+        let (&len, rest) = rest.split_first()?;
+        let value = rest.get(..len as usize)?;
+        match *code {
+            OPT_MESSAGE_TYPE => message_type = value.first().copied(),
+            OPT_REQUESTED_IP => requested_ip = ipv4_at(value, 0),
+            _ => {}
+        }
+        options = &rest[len as usize..];
+    }
+
+    Some(ClientMessage {
+        message_type: message_type?,
+        mac,
+        ciaddr,
+        requested_ip,
+        header: &packet[..OPTIONS_OFFSET],
+    })
+}
+
+fn ipv4_at(bytes: &[u8], offset: usize) -> Option<Ipv4Addr> {
+    let b = bytes.get(offset..offset + 4)?;
+    Some(Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+}
+
+/// Builds the reply to `request` into `out` and returns its length.
+fn reply(out: &mut [u8; REPLY_LEN], request: &ClientMessage, message_type: u8, yiaddr: Ipv4Addr, server: Ipv4Addr) -> usize {
+    out.fill(0);
+    out[..OPTIONS_OFFSET].copy_from_slice(request.header);
+    // BOOTREPLY, no hops, keep xid and flags, clear the addresses
+    out[0] = 2;
+    out[3] = 0;
+    out[8..10].fill(0);
+    out[12..28].fill(0);
+    out[16..20].copy_from_slice(&yiaddr.octets());
+    out[20..24].copy_from_slice(&server.octets());
+
+    let mut len = OPTIONS_OFFSET;
+    let mut option = |code: u8, value: &[u8]| {
+        out[len] = code;
+        out[len + 1] = value.len() as u8;
+        out[len + 2..len + 2 + value.len()].copy_from_slice(value);
+        len += 2 + value.len();
+    };
+    option(OPT_MESSAGE_TYPE, &[message_type]);
+    option(OPT_SERVER_ID, &server.octets());
+    if message_type != NAK {
+        option(OPT_LEASE_TIME, &LEASE_SECS.to_be_bytes());
+        option(OPT_SUBNET_MASK, &[255, 255, 255, 0]);
+        option(OPT_ROUTER, &server.octets());
+    }
+    out[len] = OPT_END;
+    len + 1
+}
+
+/// What the server made of a client message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Answer {
+    /// Not for the server, or nothing to reply.
+    None,
+    /// A DISCOVER while every address is leased.
+    Exhausted,
+    /// An address offered, the reply is in the buffer.
+    Offer { ip: Ipv4Addr, len: usize },
+    /// `ip` leased to `mac`, the reply is in the buffer.
+    Ack { mac: Mac, ip: Ipv4Addr, len: usize },
+    /// A request for an address that isn't the client's to have.
+    Nak { len: usize },
+}
+
+/// The leases of the server at `address`, which has to be in a /24 network.
+pub struct Server {
+    address: Ipv4Addr,
+    leases: Leases,
+}
+
+impl Server {
+    pub fn new(address: Ipv4Addr) -> Self {
+        Self {
+            address,
+            leases: Leases::new(address),
+        }
+    }
+
+    /// Handles the client message in `packet`, building the reply into `out`.
+    /// Replies are broadcast, the clients have no address yet.
+    pub fn handle(&mut self, packet: &[u8], out: &mut [u8; REPLY_LEN]) -> Answer {
+        let Some(request) = parse(packet) else {
+            return Answer::None;
+        };
+
+        match request.message_type {
+            DISCOVER => match self.leases.offer(request.mac) {
+                Some(ip) => Answer::Offer {
+                    ip,
+                    len: reply(out, &request, OFFER, ip, self.address),
+                },
+                None => Answer::Exhausted,
+            },
+            REQUEST => {
+                let ip = request.requested_ip.unwrap_or(request.ciaddr);
+                if self.leases.request(request.mac, ip) {
+                    Answer::Ack {
+                        mac: request.mac,
+                        ip,
+                        len: reply(out, &request, ACK, ip, self.address),
+                    }
+                } else {
+                    Answer::Nak {
+                        len: reply(out, &request, NAK, Ipv4Addr::UNSPECIFIED, self.address),
+                    }
+                }
+            }
+            RELEASE => {
+                self.leases.release(request.mac);
+                Answer::None
+            }
+            _ => Answer::None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER: Ipv4Addr = Ipv4Addr::new(192, 168, 23, 155);
+
+    /// A client message of `message_type` from the client `n`.
+    fn message(message_type: u8, n: u8, requested_ip: Option<Ipv4Addr>) -> std::vec::Vec<u8> {
+        let mut packet = std::vec![0; OPTIONS_OFFSET];
+        packet[..3].copy_from_slice(&[1, 1, 6]);
+        packet[4..8].copy_from_slice(&[0xde, 0xad, 0xbe, n]);
+        packet[28..34].copy_from_slice(&mac(n));
+        packet[236..240].copy_from_slice(&MAGIC_COOKIE);
+        packet.extend_from_slice(&[OPT_PAD, OPT_MESSAGE_TYPE, 1, message_type]);
+        if let Some(ip) = requested_ip {
+            packet.extend_from_slice(&[OPT_REQUESTED_IP, 4]);
+            packet.extend_from_slice(&ip.octets());
+        }
+        packet.push(OPT_END);
+        packet
+    }
+
+    fn mac(n: u8) -> Mac {
+        [2, 0, 0, 0, 0, n]
+    }
+
+    /// The value of option `code` in a reply.
+    fn option(reply: &[u8], code: u8) -> Option<&[u8]> {
+        let mut options = &reply[OPTIONS_OFFSET..];
+        while let [c, len, rest @ ..] = options {
+            if *c == code {
+                return Some(&rest[..*len as usize]);
+            }
+            options = &rest[*len as usize..];
+        }
+        None
+    }
+
+    #[test]
+    fn discover_to_ack() {
+        let mut server = Server::new(SERVER);
+        let mut out = [0; REPLY_LEN];
+        let offered = Ipv4Addr::new(192, 168, 23, 100);
+
+        let Answer::Offer { ip, len } = server.handle(&message(DISCOVER, 1, None), &mut out) else {
+            panic!("no offer");
+        };
+        assert_eq!(ip, offered);
+        let reply = &out[..len];
+        assert_eq!(reply[0], 2);
+        // The transaction and the client's hardware address are kept
+        assert_eq!(reply[4..8], [0xde, 0xad, 0xbe, 1]);
+        assert_eq!(reply[28..34], mac(1));
+        assert_eq!(reply[16..20], offered.octets());
+        assert_eq!(reply[20..24], SERVER.octets());
+        assert_eq!(option(reply, OPT_MESSAGE_TYPE), Some(&[OFFER][..]));
+        assert_eq!(option(reply, OPT_SERVER_ID), Some(&SERVER.octets()[..]));
+        assert_eq!(option(reply, OPT_SUBNET_MASK), Some(&[255, 255, 255, 0][..]));
+        assert_eq!(option(reply, OPT_ROUTER), Some(&SERVER.octets()[..]));
+        assert_eq!(option(reply, OPT_LEASE_TIME), Some(&LEASE_SECS.to_be_bytes()[..]));
+
+        let answer = server.handle(&message(REQUEST, 1, Some(offered)), &mut out);
+        let Answer::Ack { mac: leased, ip, len } = answer else {
+            panic!("no ack: {:?}", answer);
+        };
+        assert_eq!((leased, ip), (mac(1), offered));
+        assert_eq!(option(&out[..len], OPT_MESSAGE_TYPE), Some(&[ACK][..]));
+        assert_eq!(out[16..20], offered.octets());
+
+        // The client keeps its address, the next one gets another
+        assert!(matches!(server.handle(&message(DISCOVER, 1, None), &mut out), Answer::Offer { ip, .. } if ip == offered));
+        assert!(matches!(
+            server.handle(&message(DISCOVER, 2, None), &mut out),
+            Answer::Offer { ip, .. } if ip == Ipv4Addr::new(192, 168, 23, 101)
+        ));
+    }
+
+    #[test]
+    fn requests_for_other_addresses() {
+        let mut server = Server::new(SERVER);
+        let mut out = [0; REPLY_LEN];
+        let first = Ipv4Addr::new(192, 168, 23, 100);
+        assert!(matches!(server.handle(&message(REQUEST, 1, Some(first)), &mut out), Answer::Ack { .. }));
+
+        // Someone else's, outside the pool or another network
+        for ip in [first, Ipv4Addr::new(192, 168, 23, 99), Ipv4Addr::new(192, 168, 23, 116), Ipv4Addr::new(10, 0, 0, 100)] {
+            let answer = server.handle(&message(REQUEST, 2, Some(ip)), &mut out);
+            let Answer::Nak { len } = answer else {
+                panic!("{} not refused: {:?}", ip, answer);
+            };
+            assert_eq!(option(&out[..len], OPT_MESSAGE_TYPE), Some(&[NAK][..]));
+            assert_eq!(option(&out[..len], OPT_LEASE_TIME), None);
+            assert_eq!(out[16..20], [0; 4]);
+        }
+
+        // Released, it is free again
+        assert_eq!(server.handle(&message(RELEASE, 1, None), &mut out), Answer::None);
+        assert!(matches!(server.handle(&message(REQUEST, 2, Some(first)), &mut out), Answer::Ack { .. }));
+    }
+
+    #[test]
+    fn pool_exhaustion() {
+        let mut server = Server::new(SERVER);
+        let mut out = [0; REPLY_LEN];
+        for n in 0..POOL_SIZE as u8 {
+            let Answer::Offer { ip, .. } = server.handle(&message(DISCOVER, n, None), &mut out) else {
+                panic!("no offer for client {}", n);
+            };
+            assert_eq!(ip, Ipv4Addr::new(192, 168, 23, POOL_START + n));
+            assert!(matches!(server.handle(&message(REQUEST, n, Some(ip)), &mut out), Answer::Ack { .. }));
+        }
+
+        let late = POOL_SIZE as u8;
+        assert_eq!(server.handle(&message(DISCOVER, late, None), &mut out), Answer::Exhausted);
+        // Leased clients still get their own address
+        assert!(matches!(server.handle(&message(DISCOVER, 3, None), &mut out), Answer::Offer { .. }));
+
+        server.handle(&message(RELEASE, 3, None), &mut out);
+        assert!(matches!(
+            server.handle(&message(DISCOVER, late, None), &mut out),
+            Answer::Offer { ip, .. } if ip == Ipv4Addr::new(192, 168, 23, POOL_START + 3)
+        ));
+    }
+
+    #[test]
+    fn not_for_the_server() {
+        let mut server = Server::new(SERVER);
+        let mut out = [0; REPLY_LEN];
+        let discover = message(DISCOVER, 1, None);
+
+        // A reply, a bad cookie, cut short, no message type, an option past the end
+        let mut reply = discover.clone();
+        reply[0] = 2;
+        let mut cookie = discover.clone();
+        cookie[236] = 0;
+        let mut untyped = discover.clone();
+        untyped.truncate(OPTIONS_OFFSET);
+        untyped.push(OPT_END);
+        let mut overrun = discover.clone();
+        overrun.truncate(OPTIONS_OFFSET);
+        overrun.extend_from_slice(&[OPT_MESSAGE_TYPE, 4, DISCOVER]);
+        for packet in [&reply[..], &cookie, &discover[..100], &untyped, &overrun] {
+            assert_eq!(server.handle(packet, &mut out), Answer::None);
+        }
+    }
+}
@@ -0,0 +1,11 @@
+#![cfg_attr(not(test), no_std)]
+
+//! What `embassy-lab-utils` encodes and decodes without the board.
+//!
+//! Nothing here needs the chip or the network stack, so it builds for the
+//! host as well and is tested there.
+
+pub mod crash;
+pub mod dhcp;
+pub mod rtt;
+pub mod sntp;
@@ -0,0 +1,158 @@
+//! RTT control block with one up channel, what a debug probe reads the defmt
+//! frames from.
+//!
+//! `defmt-rtt` has its own, but it is the global logger itself and keeps its
+//! channel private, and a program links a single global logger. The netlog
+//! logger, which also mirrors the frames to the network, writes to this one
+//! instead. It follows `defmt-rtt`: a frame that doesn't fit is dropped,
+//! unless the probe asked to block while the buffer is full.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const MODE_MASK: usize = 0b11;
+const MODE_BLOCK_IF_FULL: usize = 2;
+
+/// Up channel of the control block, laid out as the probe expects it.
+#[repr(C)]
+#[allow(dead_code)] // Read by the probe
+pub struct Channel {
+    name: *const u8,
+    buffer: *mut u8,
+    size: usize,
+    write: AtomicUsize,
+    read: AtomicUsize,
+    /// Set by the probe, the low bits pick what happens when the buffer is full.
+    flags: AtomicUsize,
+}
+
+impl Channel {
+    /// Channel `name`, nul-terminated, over `size` bytes at `buffer`.
+    ///
+    /// # Safety
+    ///
+    /// `buffer` must stay valid for `size` bytes, and only be written
+    /// through this channel, by one writer at a time.
+    pub const unsafe fn new(name: &'static [u8], buffer: *mut u8, size: usize) -> Self {
+        Channel {
+            name: name.as_ptr(),
+            buffer,
+            size,
+            write: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+            flags: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn write_all(&self, mut bytes: &[u8]) {
+        let blocking = self.flags.load(Ordering::Relaxed) & MODE_MASK == MODE_BLOCK_IF_FULL;
+        while !bytes.is_empty() {
+            let written = self.write_some(bytes);
+            if written == 0 && !blocking {
+                // Nobody reads, drop the rest like `defmt-rtt` does
+                return;
+            }
+            bytes = &bytes[written..];
+        }
+    }
+
+    fn write_some(&self, bytes: &[u8]) -> usize {
+        let read = self.read.load(Ordering::Relaxed);
+        let write = self.write.load(Ordering::Acquire);
+        // One byte stays free, `read == write` means empty
+        let available = if read > write {
+            read - write - 1
+        } else if read == 0 {
+            self.size - write - 1
+        } else {
+            self.size - write
+        };
+
+        let len = bytes.len().min(available);
+        // SAFETY: `len` bytes from `write` are inside the buffer and not read
+        // by the probe until `write` moves past them
+        unsafe { core::ptr::copy_nonoverlapping(bytes.as_ptr(), self.buffer.add(write), len) };
+        self.write.store((write + len) % self.size, Ordering::Release);
+        len
+    }
+}
+
+/// The control block, found by the probe through its ID. Export it as
+/// `_SEGGER_RTT`.
+#[repr(C)]
+#[allow(dead_code)] // Read by the probe
+pub struct Header {
+    id: [u8; 16],
+    max_up_channels: usize,
+    max_down_channels: usize,
+    pub up_channel: Channel,
+}
+
+impl Header {
+    pub const fn new(up_channel: Channel) -> Self {
+        Header {
+            id: *b"SEGGER RTT\0\0\0\0\0\0",
+            max_up_channels: 1,
+            max_down_channels: 0,
+            up_channel,
+        }
+    }
+}
+
+// SAFETY: the buffer is only written by one writer at a time, as
+// `Channel::new` requires, and read by the probe
+unsafe impl Sync for Header {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Channel over `buffer`, read from the test as the probe would.
+    fn channel(buffer: &mut [u8]) -> Channel {
+        unsafe { Channel::new(b"defmt\0", buffer.as_mut_ptr(), buffer.len()) }
+    }
+
+    /// Reads what the probe would, up to the write position.
+    fn probe_read(channel: &Channel) -> std::vec::Vec<u8> {
+        let mut read = channel.read.load(Ordering::Relaxed);
+        let write = channel.write.load(Ordering::Relaxed);
+        let mut bytes = std::vec::Vec::new();
+        while read != write {
+            bytes.push(unsafe { *channel.buffer.add(read) });
+            read = (read + 1) % channel.size;
+        }
+        channel.read.store(read, Ordering::Relaxed);
+        bytes
+    }
+
+    #[test]
+    fn wraps_around_the_buffer() {
+        let mut buffer = [0; 8];
+        let channel = channel(&mut buffer);
+        channel.write_all(b"abcde");
+        assert_eq!(probe_read(&channel), b"abcde");
+        channel.write_all(b"fghij");
+        assert_eq!(channel.write.load(Ordering::Relaxed), 2);
+        assert_eq!(probe_read(&channel), b"fghij");
+    }
+
+    #[test]
+    fn drops_what_doesnt_fit() {
+        let mut buffer = [0; 8];
+        let channel = channel(&mut buffer);
+        // One byte stays free
+        channel.write_all(b"0123456789");
+        assert_eq!(probe_read(&channel), b"0123456");
+        channel.write_all(b"ab");
+        assert_eq!(probe_read(&channel), b"ab");
+    }
+
+    #[test]
+    fn layout_the_probe_expects() {
+        let mut buffer = [0; 8];
+        let header = Header::new(channel(&mut buffer));
+        let word = core::mem::size_of::<usize>();
+        assert_eq!(&header.id[..10], b"SEGGER RTT");
+        assert_eq!(core::mem::offset_of!(Header, up_channel), 16 + 2 * word);
+        assert_eq!(core::mem::size_of::<Channel>(), 6 * word);
+    }
+}
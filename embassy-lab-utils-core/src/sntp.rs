@@ -0,0 +1,96 @@
+//! SNTP replies, read without the network.
+
+/// Length of an SNTP packet without extensions.
+pub const PACKET_LEN: usize = 48;
+
+/// Seconds from the NTP epoch (1900) to the Unix epoch (1970).
+const NTP_UNIX_OFFSET_SECS: u64 = 2_208_988_800;
+
+/// Version 4, client mode, everything else may be zero.
+pub fn request() -> [u8; PACKET_LEN] {
+    let mut request = [0; PACKET_LEN];
+    request[0] = 0x23;
+    request
+}
+
+/// Transmit time of a server reply in milliseconds since the Unix epoch.
+/// `None` for anything but a server reply with a non-zero stratum and
+/// transmit timestamp, like a kiss-o'-death.
+pub fn parse_reply(reply: &[u8]) -> Option<u64> {
+    let reply: &[u8; PACKET_LEN] = reply.get(..PACKET_LEN)?.try_into().ok()?;
+    if reply[0] & 0x07 != 4 || reply[1] == 0 {
+        return None;
+    }
+
+    // Transmit timestamp, seconds and fraction since 1900
+    let secs = u32::from_be_bytes([reply[40], reply[41], reply[42], reply[43]]) as u64;
+    let fraction = u32::from_be_bytes([reply[44], reply[45], reply[46], reply[47]]) as u64;
+    if secs == 0 && fraction == 0 {
+        return None;
+    }
+
+    // The seconds wrap in February 2036. With the high bit set they count
+    // from 1900 and cover 1968 to 2036, with it clear from the wrap (RFC 4330)
+    let unix_secs = if secs & 0x8000_0000 != 0 {
+        // Before 1970 the server's clock isn't set
+        secs.checked_sub(NTP_UNIX_OFFSET_SECS)?
+    } else {
+        secs + (1 << 32) - NTP_UNIX_OFFSET_SECS
+    };
+    Some(unix_secs * 1000 + ((fraction * 1000) >> 32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Server reply, stratum 2, with the transmit timestamp.
+    fn reply(secs: u32, fraction: u32) -> [u8; PACKET_LEN] {
+        let mut reply = [0; PACKET_LEN];
+        reply[0] = 0x24;
+        reply[1] = 2;
+        reply[40..44].copy_from_slice(&secs.to_be_bytes());
+        reply[44..48].copy_from_slice(&fraction.to_be_bytes());
+        reply
+    }
+
+    #[test]
+    fn era_0() {
+        // 2025-05-20T14:03:09.5Z
+        let unix_secs = 1_747_749_789;
+        let secs = (unix_secs + NTP_UNIX_OFFSET_SECS) as u32;
+        assert_eq!(parse_reply(&reply(secs, 1 << 31)), Some(unix_secs * 1000 + 500));
+        // The last second before the wrap
+        assert_eq!(parse_reply(&reply(u32::MAX, 0)), Some((u32::MAX as u64 - NTP_UNIX_OFFSET_SECS) * 1000));
+    }
+
+    #[test]
+    fn era_1() {
+        // 2036-02-07T06:28:16Z, when the seconds wrap
+        let wrap = (1 << 32) - NTP_UNIX_OFFSET_SECS;
+        assert_eq!(wrap, 2_085_978_496);
+        assert_eq!(parse_reply(&reply(0, 1 << 31)), Some(wrap * 1000 + 500));
+        assert_eq!(parse_reply(&reply(3600, 0)), Some((wrap + 3600) * 1000));
+    }
+
+    #[test]
+    fn zero_transmit_timestamp() {
+        assert_eq!(parse_reply(&reply(0, 0)), None);
+    }
+
+    #[test]
+    fn before_the_unix_epoch() {
+        // 1968, the high bit set but the server's clock not
+        assert_eq!(parse_reply(&reply(0x8000_0000, 0)), None);
+    }
+
+    #[test]
+    fn not_a_server_reply() {
+        let mut kiss = reply(0xec00_0000, 0);
+        kiss[1] = 0;
+        assert_eq!(parse_reply(&kiss), None);
+        assert_eq!(parse_reply(&request()), None);
+        assert_eq!(parse_reply(&reply(0xec00_0000, 0)[..47]), None);
+        assert!(parse_reply(&reply(0xec00_0000, 0)).is_some());
+    }
+}
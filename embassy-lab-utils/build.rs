@@ -0,0 +1,29 @@
+//! This build script puts the public key of the firmware updates, see
+//! `ota`, where the crate includes it from. `OTA_PUBLIC_KEY` names the file
+//! with the key, 32 raw bytes as `tools/ota.py keygen` writes them. Without
+//! it the placeholder in `ota/key.pub` is built in, which nobody can sign
+//! updates for.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const PLACEHOLDER: &str = "../ota/key.pub";
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=OTA_PUBLIC_KEY");
+    let path = match env::var("OTA_PUBLIC_KEY") {
+        Ok(path) => PathBuf::from(path),
+        Err(_) => {
+            println!("cargo:warning=OTA_PUBLIC_KEY not set, building in the placeholder key of ota/key.pub");
+            PathBuf::from(PLACEHOLDER)
+        }
+    };
+    println!("cargo:rerun-if-changed={}", path.display());
+
+    let key = fs::read(&path).unwrap_or_else(|e| panic!("reading the OTA key {}: {}", path.display(), e));
+    assert!(key.len() == 32, "the OTA key {} isn't 32 bytes of an Ed25519 public key", path.display());
+
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::write(out.join("ota_key.pub"), key).unwrap();
+}
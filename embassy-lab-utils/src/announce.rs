@@ -0,0 +1,186 @@
+//! Address announcements between the boards.
+//!
+//! Every board broadcasts `Board: <name> <ip>` over UDP as soon as it is
+//! connected and then regularly, and remembers the addresses announced by the
+//! others, so peers can be found without hard-coding their addresses. A board
+//! that hears a new peer, or one that moved, announces itself right away, so
+//! a board that just started learns the others without waiting.
+//!
+//! Any host on the network can send an announcement. Don't take the address
+//! of a peer as proof of who sent something where that matters.
+//!
+//! The same datagram tells why the board last restarted, with a
+//! `Reset: <name> <cause> <uptime secs> <detail>` line, so the main board can
+//! report the crashes of the others.
+
+use core::cell::RefCell;
+use core::fmt::Write as _;
+
+use defmt::*;
+use embassy_futures::select::{select, Either};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address, Stack};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::{String, Vec};
+
+use crate::crash::{self, Report};
+use crate::wifi::{self, Connectivity};
+
+/// UDP port used for the announcements.
+pub const ANNOUNCE_PORT: u16 = 6001;
+
+/// How often a connected board announces its address.
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How often the link is checked until the first announcement can go out.
+const LINK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Number of peers remembered, one per board is plenty.
+const MAX_PEERS: usize = 4;
+
+type PeerName = String<16>;
+
+static PEERS: Mutex<CriticalSectionRawMutex, RefCell<Vec<(PeerName, Ipv4Address), MAX_PEERS>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+static RESETS: Mutex<CriticalSectionRawMutex, RefCell<Vec<(PeerName, Report), MAX_PEERS>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+/// Latest address announced by the board called `name`.
+pub fn peer(name: &str) -> Option<Ipv4Address> {
+    PEERS.lock(|peers| {
+        peers
+            .borrow()
+            .iter()
+            .find(|(peer, _)| peer.as_str() == name)
+            .map(|(_, ip)| *ip)
+    })
+}
+
+/// Like [`peer`], but falls back to `default` until `name` announced itself.
+pub fn peer_or(name: &str, default: Ipv4Address) -> Ipv4Address {
+    peer(name).unwrap_or(default)
+}
+
+/// Why the board called `name` last restarted, as it announced.
+pub fn last_reset(name: &str) -> Option<Report> {
+    RESETS.lock(|resets| {
+        resets
+            .borrow()
+            .iter()
+            .find(|(peer, _)| peer.as_str() == name)
+            .map(|(_, report)| report.clone())
+    })
+}
+
+fn remember_reset(name: &str, report: Report) {
+    let Ok(name) = PeerName::try_from(name) else {
+        return;
+    };
+
+    RESETS.lock(|resets| {
+        let mut resets = resets.borrow_mut();
+        if let Some(entry) = resets.iter_mut().find(|(peer, _)| *peer == name) {
+            entry.1 = report;
+        } else {
+            if report.cause != crash::Cause::PowerOn {
+                warn!("Peer {} restarted after {} {}", name.as_str(), report.cause, report.detail.as_str());
+            }
+            let _ = resets.push((name, report));
+        }
+    });
+}
+
+/// Returns whether the peer is new or moved.
+fn remember(name: &str, ip: Ipv4Address) -> bool {
+    let Ok(name) = PeerName::try_from(name) else {
+        return false;
+    };
+
+    PEERS.lock(|peers| {
+        let mut peers = peers.borrow_mut();
+        if let Some(entry) = peers.iter_mut().find(|(peer, _)| *peer == name) {
+            if entry.1 == ip {
+                return false;
+            }
+            info!("Peer {} moved to {}", name.as_str(), ip);
+            entry.1 = ip;
+            true
+        } else if peers.push((name.clone(), ip)).is_ok() {
+            info!("Peer {} is at {}", name.as_str(), ip);
+            true
+        } else {
+            false
+        }
+    })
+}
+
+/// Parses `Board: <name> <ip>`.
+fn parse(line: &str) -> Option<(&str, Ipv4Address)> {
+    let mut parts = line.trim().strip_prefix("Board:")?.split_whitespace();
+    let name = parts.next()?;
+    let ip = parts.next()?.parse().ok()?;
+    Some((name, ip))
+}
+
+/// This task announces this board and listens for the other boards.
+#[embassy_executor::task]
+async fn announce_task(stack: Stack<'static>, name: &'static str) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 256];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    unwrap!(socket.bind(ANNOUNCE_PORT));
+
+    let broadcast = IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::BROADCAST), ANNOUNCE_PORT);
+    let mut next_announcement = Instant::now();
+    let mut buf = [0; 256];
+
+    loop {
+        match select(Timer::at(next_announcement), socket.recv_from(&mut buf)).await {
+            Either::First(()) => {
+                let Some(Connectivity::Up(ip)) = wifi::CONNECTIVITY.try_get() else {
+                    next_announcement = Instant::now() + LINK_CHECK_INTERVAL;
+                    continue;
+                };
+                next_announcement = Instant::now() + ANNOUNCE_INTERVAL;
+
+                let mut message: String<192> = String::new();
+                let _ = writeln!(message, "Board: {} {}", name, ip);
+                let _ = crash::last().write_line(name, &mut message);
+                if let Err(e) = socket.send_to(message.as_bytes(), broadcast).await {
+                    warn!("Announcement failed: {:?}", e);
+                }
+            }
+            Either::Second(Ok((n, _))) => {
+                let Ok(line) = core::str::from_utf8(&buf[..n]) else {
+                    continue;
+                };
+                for line in line.lines() {
+                    if let Some((peer, ip)) = parse(line) {
+                        if peer != name && remember(peer, ip) {
+                            // Tell the new peer where we are
+                            next_announcement = Instant::now();
+                        }
+                    } else if let Some((peer, report)) = Report::parse_line(line) {
+                        if peer != name {
+                            remember_reset(peer, report);
+                        }
+                    } else {
+                        warn!("Unknown announcement: {}", line);
+                    }
+                }
+            }
+            Either::Second(Err(e)) => warn!("Announcement receive error: {:?}", e),
+        }
+    }
+}
+
+/// Starts announcing this board as `name` and collecting the other boards'
+/// addresses. Uses one UDP socket of the stack.
+pub fn start(spawner: &embassy_executor::Spawner, stack: Stack<'static>, name: &'static str) {
+    unwrap!(spawner.spawn(announce_task(stack, name)));
+}
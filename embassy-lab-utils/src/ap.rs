@@ -0,0 +1,51 @@
+//! Soft access point for running the lot without existing WiFi.
+//!
+//! The main board opens [`AP_SSID`] and hands out addresses with a small DHCP
+//! server. The other boards list [`AP_NETWORK`] after their usual network and
+//! join it when that one can't be reached.
+
+use cyw43::Control;
+use defmt::*;
+use embassy_net::{Ipv4Address, Ipv4Cidr, Stack, StaticConfigV4};
+use heapless::Vec;
+
+use crate::dhcp_server;
+use crate::net::Addressing;
+use crate::wifi::{Connectivity, Network, CONNECTIVITY};
+
+/// Network name of the access point.
+pub const AP_SSID: &str = "parking-lot";
+/// WPA2 passphrase of the access point.
+pub const AP_PASSWORD: &str = "parking123";
+/// The access point for the station boards.
+pub const AP_NETWORK: Network = Network::new(AP_SSID, AP_PASSWORD);
+
+/// WiFi channel of the access point.
+const AP_CHANNEL: u8 = 6;
+
+/// Address of the access point. It is the main board's usual address, so the
+/// boards find it even before it announced itself.
+pub const AP_IP: Ipv4Address = Ipv4Address::new(192, 168, 23, 155);
+/// Prefix length of the access point network.
+const AP_PREFIX_LEN: u8 = 24;
+
+/// Static addressing for the board running the access point.
+pub fn addressing() -> Addressing {
+    Addressing::Static(StaticConfigV4 {
+        address: Ipv4Cidr::new(AP_IP, AP_PREFIX_LEN),
+        gateway: None,
+        dns_servers: Vec::new(),
+    })
+}
+
+/// Opens the access point and starts its DHCP server. The stack has to use
+/// [`addressing`]. Connectivity is reported as up right away, since there is
+/// no link to lose.
+pub async fn start(spawner: &embassy_executor::Spawner, control: &mut Control<'static>, stack: Stack<'static>) {
+    control.start_ap_wpa2(AP_SSID, AP_PASSWORD, AP_CHANNEL).await;
+    info!("Access point {} started at {}", AP_SSID, AP_IP);
+
+    unwrap!(spawner.spawn(dhcp_server::dhcp_server_task(stack, AP_IP)));
+
+    CONNECTIVITY.sender().send(Connectivity::Up(AP_IP));
+}
@@ -0,0 +1,97 @@
+//! Why the board last restarted.
+//!
+//! The panic handler writes the message, the location and the uptime into a
+//! record in the `CRASH` RAM region of `memory.x`, which neither the startup
+//! code nor the bootloader touch, and restarts the board. The supervisor notes
+//! stalled tasks the same way and keeps the uptime in the record current, so
+//! a watchdog timeout is reported with the uptime too. After the restart
+//! [`last`] tells what happened, and the boards announce it to each other, see
+//! [`crate::announce::last_reset`].
+//!
+//! A record that doesn't check out, like the random RAM after power on, is
+//! reported as [`Cause::PowerOn`]. The record and its checks are in
+//! [`embassy_lab_utils_core::crash`].
+
+use core::cell::RefCell;
+use core::fmt::Write as _;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use defmt::*;
+use embassy_lab_utils_core::crash::{Ended, Record, Truncating};
+pub use embassy_lab_utils_core::crash::{Cause, Report};
+use embassy_rp::watchdog::ResetReason;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::Instant;
+use heapless::String;
+
+#[unsafe(link_section = ".crash")]
+static mut RECORD: MaybeUninit<Record> = MaybeUninit::uninit();
+
+fn store(record: Record) {
+    // SAFETY: the record is only accessed through volatile copies, and the
+    // panic handler doesn't return to code that could be in the middle of one
+    unsafe { (&raw mut RECORD).cast::<Record>().write_volatile(record) }
+}
+
+fn load() -> Record {
+    // SAFETY: any bit pattern is a record, `is_valid` tells the written ones
+    unsafe { (&raw const RECORD).cast::<Record>().read_volatile() }
+}
+
+static LAST: Mutex<CriticalSectionRawMutex, RefCell<Option<Report>>> = Mutex::new(RefCell::new(None));
+
+/// Why the board last restarted, [`Cause::PowerOn`] before [`init`].
+pub fn last() -> Report {
+    LAST.lock(|last| last.borrow().clone()).unwrap_or_else(Report::power_on)
+}
+
+/// Reads the record of the last run, `reason` being why the watchdog reset
+/// the board, if it did. Called by [`crate::supervisor::start`].
+pub(crate) fn init(reason: Option<ResetReason>) {
+    let watchdog = matches!(reason, Some(ResetReason::TimedOut));
+    let report = load().report(watchdog).unwrap_or_else(Report::power_on);
+
+    match report.cause {
+        Cause::PowerOn | Cause::Restart => info!("Started after {}", report.cause),
+        cause => warn!("Restarted after {} {}, up {}s before", cause, report.detail.as_str(), report.uptime_secs),
+    }
+    LAST.lock(|last| last.replace(Some(report)));
+    alive();
+}
+
+/// Keeps the uptime in the record current.
+pub(crate) fn alive() {
+    store(Record::new(Ended::Running, uptime_ms(), ""));
+}
+
+/// Notes that the task called `name` stalled.
+pub(crate) fn note_stall(name: &str) {
+    store(Record::new(Ended::Stall, uptime_ms(), name));
+}
+
+fn uptime_ms() -> u64 {
+    Instant::now().as_millis()
+}
+
+/// Set by the first panic, a panic while handling it only restarts.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+#[panic_handler]
+fn panic(info: &core::panic::PanicInfo) -> ! {
+    if !PANICKING.load(Ordering::Relaxed) {
+        PANICKING.store(true, Ordering::Relaxed);
+        error!("{}", Display2Format(info));
+
+        let mut detail = Truncating(String::new());
+        if let Some(location) = info.location() {
+            let file = location.file().rsplit('/').next().unwrap_or("");
+            let _ = core::write!(detail, "{}:{} ", file, location.line());
+        }
+        let _ = core::write!(detail, "{}", info.message());
+        store(Record::new(Ended::Panic, uptime_ms(), detail.0.trim_end()));
+    }
+
+    cortex_m::peripheral::SCB::sys_reset()
+}
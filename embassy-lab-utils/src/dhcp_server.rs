@@ -0,0 +1,53 @@
+//! DHCP server task for the access point, the protocol is in
+//! [`embassy_lab_utils_core::dhcp`].
+
+use defmt::*;
+use embassy_lab_utils_core::dhcp::{Answer, Server, REPLY_LEN};
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address, Stack};
+
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+
+/// This task hands out addresses to the clients of the access point.
+#[embassy_executor::task]
+pub(crate) async fn dhcp_server_task(stack: Stack<'static>, address: Ipv4Address) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 1024];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    unwrap!(socket.bind(SERVER_PORT));
+
+    // Clients have no address yet, so every reply is broadcast
+    let clients = IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::BROADCAST), CLIENT_PORT);
+    let mut server = Server::new(address);
+    let mut packet = [0; 576];
+    let mut out = [0; REPLY_LEN];
+
+    loop {
+        let n = match socket.recv_from(&mut packet).await {
+            Ok((n, _)) => n,
+            Err(e) => {
+                warn!("DHCP receive error: {:?}", e);
+                continue;
+            }
+        };
+
+        let len = match server.handle(&packet[..n], &mut out) {
+            Answer::None => continue,
+            Answer::Exhausted => {
+                warn!("DHCP pool exhausted");
+                continue;
+            }
+            Answer::Offer { len, .. } | Answer::Nak { len } => len,
+            Answer::Ack { mac, ip, len } => {
+                info!("DHCP lease {} to {:02x}", ip, mac);
+                len
+            }
+        };
+        if let Err(e) = socket.send_to(&out[..len], clients).await {
+            warn!("DHCP send error: {:?}", e);
+        }
+    }
+}
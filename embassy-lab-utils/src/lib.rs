@@ -0,0 +1,268 @@
+#![no_std]
+
+//! Shared WiFi and network setup for the lab boards.
+//!
+//! The chip is selected with the `rp2040` or `rp235xa` feature, the WiFi power
+//! mode used by [`init_wifi!`] with `power-save` or `power-performance` and the
+//! PIO block it drives the WiFi chip from with `wifi-pio1`. [`WifiBuilder`]
+//! takes all of these explicitly instead.
+//!
+//! The crate is the boards' defmt logger. With the `netlog` feature that is
+//! [`netlog`], which also streams the log over the network, otherwise
+//! `defmt-rtt`.
+
+use cyw43::{Control, NetDriver, PowerManagementMode};
+use cyw43_pio::PioSpi;
+use defmt::*;
+use embassy_net::StackResources;
+use embassy_rp::clocks::RoscRng;
+use embassy_executor::Spawner;
+use embassy_rp::dma::{self, AnyChannel};
+use embassy_rp::gpio::{self, Level, Output};
+use embassy_rp::interrupt::typelevel::Binding;
+use embassy_rp::peripherals::{PIO0, PIO1};
+#[cfg(feature = "rp235xa")]
+use embassy_rp::peripherals::PIO2;
+use embassy_rp::pio::{self, Pio, PioPin};
+use embassy_rp::Peripheral;
+use rand::RngCore as _;
+use static_cell::StaticCell;
+
+pub mod announce;
+pub mod ap;
+pub mod crash;
+mod dhcp_server;
+pub mod net;
+#[cfg(feature = "netlog")]
+pub mod netlog;
+pub mod ota;
+pub mod sntp;
+pub mod supervisor;
+pub mod wifi;
+
+pub use net::Addressing;
+
+#[cfg(not(feature = "netlog"))]
+use defmt_rtt as _;
+
+pub use cyw43;
+pub use cyw43_pio;
+pub use embassy_rp;
+
+#[cfg(not(any(feature = "rp2040", feature = "rp235xa")))]
+compile_error!("enable exactly one of the `rp2040` or `rp235xa` features");
+
+#[cfg(all(feature = "rp2040", feature = "rp235xa"))]
+compile_error!("the `rp2040` and `rp235xa` features are mutually exclusive");
+
+#[cfg(all(feature = "power-save", feature = "power-performance"))]
+compile_error!("the `power-save` and `power-performance` features are mutually exclusive");
+
+/// Power management mode selected by the `power-*` features.
+#[cfg(feature = "power-save")]
+pub const POWER_MODE: PowerManagementMode = PowerManagementMode::PowerSave;
+#[cfg(feature = "power-performance")]
+pub const POWER_MODE: PowerManagementMode = PowerManagementMode::Performance;
+#[cfg(not(any(feature = "power-save", feature = "power-performance")))]
+pub const POWER_MODE: PowerManagementMode = PowerManagementMode::None;
+
+/// SPI bus to the WiFi chip, running on state machine 0 of `PIO`.
+pub type WifiSpi<PIO> = PioSpi<'static, PIO, 0, AnyChannel>;
+
+/// PIO blocks that can drive the WiFi chip.
+///
+/// Embassy tasks can't be generic, so every PIO block gets its own driver task.
+pub trait WifiPio: pio::Instance {
+    fn spawn_runner(
+        spawner: &Spawner,
+        runner: cyw43::Runner<'static, Output<'static>, WifiSpi<Self>>,
+    );
+}
+
+macro_rules! wifi_pio {
+    ($pio:ident, $task:ident) => {
+        /// This task runs the wifi chip driver. This will need to run in an infinite loop.
+        #[embassy_executor::task]
+        async fn $task(runner: cyw43::Runner<'static, Output<'static>, WifiSpi<$pio>>) -> ! {
+            runner.run().await
+        }
+
+        impl WifiPio for $pio {
+            fn spawn_runner(
+                spawner: &Spawner,
+                runner: cyw43::Runner<'static, Output<'static>, WifiSpi<Self>>,
+            ) {
+                unwrap!(spawner.spawn($task(runner)));
+            }
+        }
+    };
+}
+
+wifi_pio!(PIO0, cyw43_pio0_task);
+wifi_pio!(PIO1, cyw43_pio1_task);
+#[cfg(feature = "rp235xa")]
+wifi_pio!(PIO2, cyw43_pio2_task);
+
+/// Sets up the CYW43 WiFi chip from explicit peripherals.
+///
+/// The Pico W and Pico 2 W wire the chip to PIN_23 (power), PIN_25 (chip
+/// select), PIN_24 (data) and PIN_29 (clock). The PIO block and DMA channel
+/// are free to choose, which keeps e.g. PIO0 available for the application.
+///
+/// ```ignore
+/// bind_interrupts!(struct Irqs {
+///     PIO1_IRQ_0 => InterruptHandler<PIO1>;
+/// });
+///
+/// let (net_device, control) = WifiBuilder::new(p.PIO1, Irqs, p.DMA_CH0, p.PIN_23, p.PIN_25, p.PIN_24, p.PIN_29)
+///     .power_management(PowerManagementMode::PowerSave)
+///     .init(&spawner)
+///     .await;
+/// ```
+pub struct WifiBuilder<PIO: WifiPio> {
+    pwr: Output<'static>,
+    spi: WifiSpi<PIO>,
+    power_mode: PowerManagementMode,
+}
+
+impl<PIO: WifiPio> WifiBuilder<PIO> {
+    pub fn new<DIO: PioPin, CLK: PioPin>(
+        pio: impl Peripheral<P = PIO> + 'static,
+        irqs: impl Binding<PIO::Interrupt, pio::InterruptHandler<PIO>>,
+        dma: impl Peripheral<P = impl dma::Channel> + 'static,
+        pwr: impl Peripheral<P = impl gpio::Pin> + 'static,
+        cs: impl Peripheral<P = impl gpio::Pin> + 'static,
+        dio: DIO,
+        clk: CLK,
+    ) -> Self {
+        let pwr = Output::new(pwr, Level::Low);
+        let cs = Output::new(cs, Level::High);
+        let mut pio = Pio::new(pio, irqs);
+        let spi = PioSpi::new(
+            &mut pio.common,
+            pio.sm0,
+            cyw43_pio::RM2_CLOCK_DIVIDER,
+            pio.irq0,
+            cs,
+            dio,
+            clk,
+            dma.into_ref().map_into::<AnyChannel>(),
+        );
+
+        Self {
+            pwr,
+            spi,
+            power_mode: POWER_MODE,
+        }
+    }
+
+    /// Power management mode of the WiFi chip, [`POWER_MODE`] by default.
+    pub fn power_management(mut self, mode: PowerManagementMode) -> Self {
+        self.power_mode = mode;
+        self
+    }
+
+    /// Starts the driver task and loads the firmware into the chip.
+    pub async fn init(self, spawner: &Spawner) -> (NetDriver<'static>, Control<'static>) {
+        let (net_device, mut control) = init_cy43w(self.pwr, self.spi, spawner).await;
+        init_controller(&mut control, self.power_mode).await;
+        (net_device, control)
+    }
+}
+
+#[doc(hidden)]
+#[cfg(not(feature = "wifi-pio1"))]
+#[macro_export]
+macro_rules! __wifi_pio {
+    ($p:expr) => {{
+        $crate::embassy_rp::bind_interrupts!(struct PioIrq {
+            PIO0_IRQ_0 => $crate::embassy_rp::pio::InterruptHandler<$crate::embassy_rp::peripherals::PIO0>;
+        });
+        ($p.PIO0, PioIrq)
+    }};
+}
+
+#[doc(hidden)]
+#[cfg(feature = "wifi-pio1")]
+#[macro_export]
+macro_rules! __wifi_pio {
+    ($p:expr) => {{
+        $crate::embassy_rp::bind_interrupts!(struct PioIrq {
+            PIO1_IRQ_0 => $crate::embassy_rp::pio::InterruptHandler<$crate::embassy_rp::peripherals::PIO1>;
+        });
+        ($p.PIO1, PioIrq)
+    }};
+}
+
+/// Sets up the WiFi chip with the on-board pins, DMA_CH2 and the PIO block and
+/// power mode selected by the crate features. Use [`WifiBuilder`] for anything
+/// else.
+#[macro_export]
+macro_rules! init_wifi {
+    ($spawner_ref:expr, $p:expr) => {
+        async {
+            let (pio, irqs) = $crate::__wifi_pio!($p);
+            $crate::WifiBuilder::new(pio, irqs, $p.DMA_CH2, $p.PIN_23, $p.PIN_25, $p.PIN_24, $p.PIN_29)
+                .init($spawner_ref)
+                .await
+        }
+    };
+}
+
+/// This task runs the network stack, used for processing network events.
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, cyw43::NetDriver<'static>>) -> ! {
+    runner.run().await
+}
+
+/// Initialize the CYW43 Wifi chip driver and spawn the task that runs the driver.
+///
+/// Returns a handle to the network device, control handle and a runner for driving the low level
+/// stack.
+pub async fn init_cy43w<PIO: WifiPio>(
+    pwr: Output<'static>,
+    spi: WifiSpi<PIO>,
+    spawner: &Spawner,
+) -> (NetDriver<'static>, Control<'static>) {
+    let fw = include_bytes!("../../cyw43-firmware/43439A0.bin");
+
+    static STATE: StaticCell<cyw43::State> = StaticCell::new();
+    let state = STATE.init(cyw43::State::new());
+    let (net_device, control, runner) = cyw43::new(state, pwr, spi, fw).await;
+    PIO::spawn_runner(spawner, runner);
+
+    (net_device, control)
+}
+
+/// Initialize the WiFi controller with the given power management mode.
+pub async fn init_controller(controller: &mut Control<'static>, mode: PowerManagementMode) {
+    let clm = include_bytes!("../../cyw43-firmware/43439A0_clm.bin");
+    controller.init(clm).await;
+    controller.set_power_management(mode).await;
+}
+
+/// Initialize the network stack with the given addressing mode.
+pub fn init_network_stack<const SOCK: usize>(
+    spawner: &embassy_executor::Spawner,
+    net_device: NetDriver<'static>,
+    resources: &'static StaticCell<StackResources<SOCK>>,
+    addressing: Addressing,
+) -> embassy_net::Stack<'static> {
+    // Generate random seed
+    let seed = RoscRng.next_u64();
+
+    let (stack, runner) = embassy_net::new(
+        net_device,
+        addressing.config(),
+        resources.init(StackResources::new()),
+        seed,
+    );
+
+    unwrap!(spawner.spawn(net_task(runner)));
+
+    if let Addressing::DhcpWithFallback { fallback, timeout, .. } = addressing {
+        unwrap!(spawner.spawn(net::fallback_task(stack, fallback, timeout)));
+    }
+
+    stack
+}
@@ -0,0 +1,70 @@
+//! IPv4 addressing for the network stack.
+//!
+//! Boards can use a fixed address, DHCP, or DHCP that falls back to a fixed
+//! address when no lease arrives in time. The fallback is kept until reboot,
+//! so a board that lost its DHCP server keeps a predictable address.
+
+use defmt::*;
+use embassy_net::{ConfigV4, DhcpConfig, Stack, StaticConfigV4};
+use embassy_time::{with_timeout, Duration};
+use heapless::String;
+
+#[derive(Clone, Debug)]
+pub enum Addressing {
+    /// Fixed address, no DHCP.
+    Static(StaticConfigV4),
+    /// DHCP, optionally sending a hostname to the DHCP server.
+    Dhcp { hostname: Option<&'static str> },
+    /// DHCP, switching to `fallback` if no lease arrives within `timeout` of
+    /// the link coming up.
+    DhcpWithFallback {
+        hostname: Option<&'static str>,
+        fallback: StaticConfigV4,
+        timeout: Duration,
+    },
+}
+
+impl Addressing {
+    /// DHCP without a hostname, what the boards used so far.
+    pub const fn dhcp() -> Self {
+        Self::Dhcp { hostname: None }
+    }
+
+    /// Initial stack configuration for this addressing mode.
+    pub fn config(&self) -> embassy_net::Config {
+        match self {
+            Self::Static(config) => embassy_net::Config::ipv4_static(config.clone()),
+            Self::Dhcp { hostname } | Self::DhcpWithFallback { hostname, .. } => {
+                embassy_net::Config::dhcpv4(dhcp_config(*hostname))
+            }
+        }
+    }
+}
+
+fn dhcp_config(hostname: Option<&'static str>) -> DhcpConfig {
+    let mut config = DhcpConfig::default();
+    if let Some(name) = hostname {
+        let mut host = String::new();
+        if host.push_str(name).is_err() {
+            warn!("Hostname {} is too long, not sending it", name);
+        } else {
+            config.hostname = Some(host);
+        }
+    }
+    config
+}
+
+/// This task switches to the static fallback address if DHCP doesn't answer in time.
+#[embassy_executor::task]
+pub(crate) async fn fallback_task(stack: Stack<'static>, fallback: StaticConfigV4, timeout: Duration) {
+    stack.wait_link_up().await;
+
+    if with_timeout(timeout, stack.wait_config_up()).await.is_err() {
+        warn!(
+            "No DHCP lease after {}s, using static address {}",
+            timeout.as_secs(),
+            fallback.address
+        );
+        stack.set_config_v4(ConfigV4::Static(fallback));
+    }
+}
@@ -0,0 +1,330 @@
+//! The defmt logger of the boards, mirrored to the network.
+//!
+//! Every frame goes to RTT like with `defmt-rtt`, for a debug probe, through
+//! the control block of [`embassy_lab_utils_core::rtt`]. Once [`start`] ran,
+//! the frames at or above a level are also kept in a buffer and streamed to a
+//! client of TCP port [`LOG_PORT`], still defmt encoded. `tools/netlog`
+//! decodes them with the boards' ELF files. The client can change the level
+//! with a `Level: <level>` line.
+//!
+//! The level of a frame is told by its format string, which `defmt.x` sorts
+//! by level between `__DEFMT_MARKER_*` symbols. Frames whose format string
+//! lies outside those ranges weren't logged at a level, like the ones of
+//! `defmt::println!`, and are mirrored whatever the level. While no client is
+//! connected the buffer keeps the newest frames.
+//!
+//! This module only exists with the `netlog` feature; without it the boards
+//! log to RTT through `defmt-rtt`.
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use defmt::*;
+use embassy_futures::select::{select, Either};
+use embassy_lab_utils_core::rtt;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::Stack;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embedded_io_async::Write as _;
+use heapless::{Deque, Vec};
+
+/// TCP port the log is streamed on.
+pub const LOG_PORT: u16 = 6003;
+
+/// Bytes of encoded frames kept for the client.
+const LOG_BUFFER: usize = 4096;
+
+/// Longest encoded frame mirrored, longer ones are left out.
+const MAX_FRAME: usize = 256;
+
+/// Size of the RTT buffer the probe reads from.
+const RTT_BUFFER: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "trace" => Some(Level::Trace),
+            "debug" => Some(Level::Debug),
+            "info" => Some(Level::Info),
+            "warn" => Some(Level::Warn),
+            "error" => Some(Level::Error),
+            _ => None,
+        }
+    }
+
+    /// Index of the first format string of this level, levelled frames from
+    /// there on are of this level or above.
+    fn first_index(self) -> u16 {
+        let marker = match self {
+            Level::Trace => &raw const __DEFMT_MARKER_TRACE_START,
+            Level::Debug => &raw const __DEFMT_MARKER_DEBUG_START,
+            Level::Info => &raw const __DEFMT_MARKER_INFO_START,
+            Level::Warn => &raw const __DEFMT_MARKER_WARN_START,
+            Level::Error => &raw const __DEFMT_MARKER_ERROR_START,
+        };
+        marker as usize as u16
+    }
+
+    /// Whether the frame with the format string `index` is mirrored at this
+    /// level. Frames without a level always are.
+    fn keeps(self, index: u16) -> bool {
+        let levelled = Level::Trace.first_index()..(&raw const __DEFMT_MARKER_ERROR_END) as usize as u16;
+        !levelled.contains(&index) || index >= self.first_index()
+    }
+}
+
+unsafe extern "C" {
+    static __DEFMT_MARKER_TRACE_START: u8;
+    static __DEFMT_MARKER_DEBUG_START: u8;
+    static __DEFMT_MARKER_INFO_START: u8;
+    static __DEFMT_MARKER_WARN_START: u8;
+    static __DEFMT_MARKER_ERROR_START: u8;
+    static __DEFMT_MARKER_ERROR_END: u8;
+}
+
+/// Lowest level mirrored, [`OFF`] until [`start`].
+static LEVEL: AtomicU8 = AtomicU8::new(OFF);
+const OFF: u8 = u8::MAX;
+
+static LOG: Mutex<CriticalSectionRawMutex, RefCell<Deque<u8, LOG_BUFFER>>> = Mutex::new(RefCell::new(Deque::new()));
+
+/// Signalled when frames were added to [`LOG`].
+static LOG_READY: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+fn level() -> Option<Level> {
+    [Level::Trace, Level::Debug, Level::Info, Level::Warn, Level::Error]
+        .into_iter()
+        .find(|level| *level as u8 == LEVEL.load(Ordering::Relaxed))
+}
+
+/// Adds an encoded frame, dropping the oldest ones to make room.
+fn push_frame(frame: &[u8]) {
+    // The encoder starts its first frame with a separator, every frame ends
+    // with one anyway
+    let start = frame.iter().position(|byte| *byte != 0).unwrap_or(frame.len());
+    let frame = &frame[start..];
+
+    LOG.lock(|log| {
+        let mut log = log.borrow_mut();
+        while log.capacity() - log.len() < frame.len() {
+            // Frames end with a zero
+            while let Some(byte) = log.pop_front() {
+                if byte == 0 {
+                    break;
+                }
+            }
+        }
+        for byte in frame {
+            let _ = log.push_back(*byte);
+        }
+    });
+    LOG_READY.signal(());
+}
+
+/// Frame being mirrored, encoded on its own so the frames below the level
+/// can be left out.
+struct Mirror {
+    encoder: defmt::Encoder,
+    state: MirrorState,
+    frame: Vec<u8, MAX_FRAME>,
+    overflow: bool,
+}
+
+enum MirrorState {
+    /// Waiting for the index of the format string, the first write.
+    Header,
+    Keep,
+    Skip,
+}
+
+impl Mirror {
+    const fn new() -> Self {
+        Mirror {
+            encoder: defmt::Encoder::new(),
+            state: MirrorState::Skip,
+            frame: Vec::new(),
+            overflow: false,
+        }
+    }
+
+    fn start(&mut self) {
+        self.state = MirrorState::Header;
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        if let MirrorState::Header = self.state {
+            let keep = match (level(), bytes) {
+                (Some(level), [low, high, ..]) => level.keeps(u16::from_le_bytes([*low, *high])),
+                _ => false,
+            };
+            if !keep {
+                self.state = MirrorState::Skip;
+                return;
+            }
+
+            self.state = MirrorState::Keep;
+            self.frame.clear();
+            self.overflow = false;
+            let Mirror { encoder, frame, overflow, .. } = self;
+            encoder.start_frame(|encoded| *overflow |= frame.extend_from_slice(encoded).is_err());
+        }
+
+        if let MirrorState::Keep = self.state {
+            let Mirror { encoder, frame, overflow, .. } = self;
+            encoder.write(bytes, |encoded| *overflow |= frame.extend_from_slice(encoded).is_err());
+        }
+    }
+
+    fn end(&mut self) {
+        if let MirrorState::Keep = self.state {
+            let Mirror { encoder, frame, overflow, .. } = self;
+            encoder.end_frame(|encoded| *overflow |= frame.extend_from_slice(encoded).is_err());
+            if !*overflow {
+                push_frame(frame);
+            }
+        }
+        self.state = MirrorState::Skip;
+    }
+}
+
+static mut RTT: [u8; RTT_BUFFER] = [0; RTT_BUFFER];
+
+// SAFETY: only the logger writes to the buffer, inside a critical section
+#[unsafe(no_mangle)]
+static _SEGGER_RTT: rtt::Header = rtt::Header::new(unsafe { rtt::Channel::new(b"defmt\0", &raw mut RTT as *mut u8, RTT_BUFFER) });
+
+fn rtt_write(bytes: &[u8]) {
+    _SEGGER_RTT.up_channel.write_all(bytes);
+}
+
+#[defmt::global_logger]
+struct Logger;
+
+static TAKEN: AtomicBool = AtomicBool::new(false);
+static mut RESTORE: critical_section::RestoreState = critical_section::RestoreState::invalid();
+static mut ENCODER: defmt::Encoder = defmt::Encoder::new();
+static mut MIRROR: Mirror = Mirror::new();
+
+// SAFETY: the statics above are only touched between `acquire` and
+// `release`, inside a critical section
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {
+        let restore = unsafe { critical_section::acquire() };
+        if TAKEN.load(Ordering::Relaxed) {
+            core::panic!("defmt logger taken reentrantly");
+        }
+        TAKEN.store(true, Ordering::Relaxed);
+
+        unsafe {
+            RESTORE = restore;
+            (*&raw mut ENCODER).start_frame(rtt_write);
+            (*&raw mut MIRROR).start();
+        }
+    }
+
+    unsafe fn flush() {}
+
+    unsafe fn release() {
+        unsafe {
+            (*&raw mut ENCODER).end_frame(rtt_write);
+            (*&raw mut MIRROR).end();
+            TAKEN.store(false, Ordering::Relaxed);
+            critical_section::release(RESTORE);
+        }
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        unsafe {
+            (*&raw mut ENCODER).write(bytes, rtt_write);
+            (*&raw mut MIRROR).write(bytes);
+        }
+    }
+}
+
+/// Streams the buffered frames to one client at a time.
+#[embassy_executor::task]
+async fn netlog_task(stack: Stack<'static>) -> ! {
+    let mut rx_buffer = [0; 64];
+    let mut tx_buffer = [0; 1024];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        if let Err(e) = socket.accept(LOG_PORT).await {
+            warn!("Log accept error: {:?}", e);
+            continue;
+        }
+
+        info!("Log client connected from {:?}", socket.remote_endpoint());
+        if let Err(e) = serve(&mut socket).await {
+            warn!("Log client lost: {:?}", e);
+        }
+        socket.abort();
+        let _ = socket.flush().await;
+    }
+}
+
+async fn serve(socket: &mut TcpSocket<'_>) -> Result<(), embassy_net::tcp::Error> {
+    let mut chunk = [0; 256];
+    let mut request = [0; 32];
+
+    loop {
+        // Send whatever is buffered, then wait for more or for a request
+        let len = LOG.lock(|log| {
+            let mut log = log.borrow_mut();
+            let mut len = 0;
+            while len < chunk.len() {
+                let Some(byte) = log.pop_front() else {
+                    break;
+                };
+                chunk[len] = byte;
+                len += 1;
+            }
+            len
+        });
+        if len > 0 {
+            socket.write_all(&chunk[..len]).await?;
+            continue;
+        }
+
+        match select(LOG_READY.wait(), socket.read(&mut request)).await {
+            Either::First(()) => {}
+            Either::Second(Ok(0)) => return Ok(()),
+            Either::Second(Ok(n)) => {
+                let level = core::str::from_utf8(&request[..n])
+                    .ok()
+                    .and_then(|line| line.trim().strip_prefix("Level:"))
+                    .and_then(|level| Level::parse(level.trim()));
+                match level {
+                    Some(level) => {
+                        set_level(level);
+                        info!("Log level set to {}", level);
+                    }
+                    None => warn!("Bad log request"),
+                }
+            }
+            Either::Second(Err(e)) => return Err(e),
+        }
+    }
+}
+
+/// Starts mirroring the frames at or above `level` and serving them on
+/// [`LOG_PORT`]. Uses one TCP socket of the stack.
+pub fn start(spawner: &embassy_executor::Spawner, stack: Stack<'static>, level: Level) {
+    set_level(level);
+    unwrap!(spawner.spawn(netlog_task(stack)));
+}
@@ -0,0 +1,357 @@
+//! Firmware updates over the network.
+//!
+//! Next to the running image the flash holds a second partition an update is
+//! written to, and the `bootloader` crate swaps the two on the next boot. The
+//! partitions are laid out in each board's `memory.x`.
+//!
+//! An update is sent to [`OTA_PORT`] as a [`Header`] followed by the image.
+//! The header names the board the image is for, a board that relays passes
+//! images for the others on. The image is only swapped in when it fits the
+//! partition and its SHA-512 is signed with the key built in, see `build.rs`.
+//! The sender gets a line with `Ok` or `Error: <reason>`.
+//!
+//! A swapped image runs on trial: unless the board calls [`healthy`] within
+//! [`CONFIRM_TIMEOUT`], it resets and the bootloader swaps the old image back.
+
+use core::cell::RefCell;
+use core::fmt::Write as _;
+
+use defmt::*;
+use embassy_boot_rp::{AlignedBuffer, BlockingFirmwareUpdater, FirmwareUpdaterConfig, FirmwareUpdaterError, State};
+use embassy_embedded_hal::flash::partition::BlockingPartition;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{IpAddress, IpEndpoint, Stack};
+use embassy_rp::flash::{Blocking, Flash, ERASE_SIZE, WRITE_SIZE};
+use embassy_rp::peripherals::FLASH;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration, Timer};
+use embedded_io_async::{Read, ReadExactError, Write};
+use heapless::String;
+use static_cell::StaticCell;
+
+use crate::announce;
+
+/// TCP port updates are sent to.
+pub const OTA_PORT: u16 = 6002;
+
+/// How long a new image has to call [`healthy`] before it is rolled back.
+pub const CONFIRM_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Flash size assumed by the boards' `memory.x`.
+pub const FLASH_SIZE: usize = 2 * 1024 * 1024;
+
+pub type BoardFlash = Flash<'static, FLASH, Blocking, FLASH_SIZE>;
+
+/// The flash, shared by the updater and whatever else the board keeps in it.
+pub type SharedFlash = Mutex<CriticalSectionRawMutex, RefCell<BoardFlash>>;
+
+type Partition = BlockingPartition<'static, CriticalSectionRawMutex, BoardFlash>;
+
+/// Key the update hashes are signed with, Ed25519, from `OTA_PUBLIC_KEY`.
+const PUBLIC_KEY: &[u8; 32] = include_bytes!(concat!(env!("OUT_DIR"), "/ota_key.pub"));
+
+/// Marks an update header, `OTA2`.
+const MAGIC: &[u8; 4] = b"OTA2";
+
+/// Longest board name in a header.
+const MAX_BOARD: usize = 8;
+
+/// Magic, board name, image length and signature.
+pub const HEADER_SIZE: usize = 4 + MAX_BOARD + 4 + 64;
+
+/// Raised by the board once a new image works.
+static HEALTHY: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+static SHARED_FLASH: StaticCell<SharedFlash> = StaticCell::new();
+
+// Partition bounds from `memory.x`, as offsets into the flash
+unsafe extern "C" {
+    static __bootloader_state_start: u32;
+    static __bootloader_state_end: u32;
+    static __bootloader_dfu_start: u32;
+    static __bootloader_dfu_end: u32;
+}
+
+/// What precedes an image.
+#[derive(Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Header {
+    /// Board the image is for, as it announces itself.
+    pub board: String<MAX_BOARD>,
+    pub len: u32,
+    /// Ed25519 signature of the image's SHA-512.
+    pub signature: [u8; 64],
+}
+
+impl Header {
+    /// Parses the header. The board name is padded with zeros and the length
+    /// is little endian.
+    pub fn parse(bytes: &[u8; HEADER_SIZE]) -> Option<Self> {
+        if bytes[..4] != *MAGIC {
+            return None;
+        }
+        let name = &bytes[4..4 + MAX_BOARD];
+        let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(MAX_BOARD)];
+        let board = String::try_from(core::str::from_utf8(name).ok()?).ok()?;
+
+        let rest = &bytes[4 + MAX_BOARD..];
+        let mut signature = [0; 64];
+        signature.copy_from_slice(&rest[4..]);
+
+        Some(Self {
+            board,
+            len: u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]),
+            signature,
+        })
+    }
+}
+
+/// How an update ended that went well.
+enum Outcome {
+    /// The image is ready to be swapped in.
+    Stored,
+    /// The image went to another board, which answered the sender itself.
+    Relayed,
+}
+
+#[derive(Debug, defmt::Format)]
+enum OtaError {
+    Io(embassy_net::tcp::Error),
+    Closed,
+    BadHeader,
+    /// The image is for another board and this one doesn't relay.
+    WrongBoard,
+    /// The image is for a board that hasn't announced itself.
+    UnknownBoard,
+    /// The board to relay to couldn't be reached.
+    Unreachable(embassy_net::tcp::ConnectError),
+    Update(FirmwareUpdaterError),
+    /// The image is larger than the partition it goes to.
+    TooLarge,
+    BadSignature,
+}
+
+impl OtaError {
+    fn as_str(&self) -> &'static str {
+        match self {
+            OtaError::Io(_) | OtaError::Closed => "transfer failed",
+            OtaError::BadHeader => "bad header",
+            OtaError::WrongBoard => "wrong board",
+            OtaError::UnknownBoard => "unknown board",
+            OtaError::Unreachable(_) => "board unreachable",
+            OtaError::Update(FirmwareUpdaterError::BadState) => "previous update not confirmed",
+            OtaError::Update(_) => "flash error",
+            OtaError::TooLarge => "image too large",
+            OtaError::BadSignature => "bad signature",
+        }
+    }
+}
+
+impl From<embassy_net::tcp::Error> for OtaError {
+    fn from(e: embassy_net::tcp::Error) -> Self {
+        OtaError::Io(e)
+    }
+}
+
+impl From<ReadExactError<embassy_net::tcp::Error>> for OtaError {
+    fn from(e: ReadExactError<embassy_net::tcp::Error>) -> Self {
+        match e {
+            ReadExactError::UnexpectedEof => OtaError::Closed,
+            ReadExactError::Other(e) => OtaError::Io(e),
+        }
+    }
+}
+
+impl From<FirmwareUpdaterError> for OtaError {
+    fn from(e: FirmwareUpdaterError) -> Self {
+        match e {
+            FirmwareUpdaterError::Signature(_) => OtaError::BadSignature,
+            e => OtaError::Update(e),
+        }
+    }
+}
+
+/// Takes the flash, for the updater and anything else the board keeps in it.
+pub fn init_flash(flash: FLASH) -> &'static SharedFlash {
+    SHARED_FLASH.init(Mutex::new(RefCell::new(BoardFlash::new_blocking(flash))))
+}
+
+/// Tells a new image that the board works, so it is kept.
+pub fn healthy() {
+    HEALTHY.signal(());
+}
+
+fn dfu_partition() -> core::ops::Range<u32> {
+    &raw const __bootloader_dfu_start as u32..&raw const __bootloader_dfu_end as u32
+}
+
+fn updater<'a>(flash: &'static SharedFlash, aligned: &'a mut [u8]) -> BlockingFirmwareUpdater<'a, Partition, Partition> {
+    let (state, dfu) = (
+        &raw const __bootloader_state_start as u32..&raw const __bootloader_state_end as u32,
+        dfu_partition(),
+    );
+    let config = FirmwareUpdaterConfig {
+        dfu: Partition::new(flash, dfu.start, dfu.end - dfu.start),
+        state: Partition::new(flash, state.start, state.end - state.start),
+    };
+    BlockingFirmwareUpdater::new(config, aligned)
+}
+
+/// This task rolls a new image back unless it turns out healthy in time.
+#[embassy_executor::task]
+async fn confirm_task(flash: &'static SharedFlash) {
+    if with_timeout(CONFIRM_TIMEOUT, HEALTHY.wait()).await.is_err() {
+        warn!("New firmware not confirmed, rolling back");
+        cortex_m::peripheral::SCB::sys_reset();
+    }
+
+    let mut aligned = AlignedBuffer([0; WRITE_SIZE]);
+    match updater(flash, &mut aligned.0).mark_booted() {
+        Ok(()) => info!("New firmware confirmed"),
+        Err(e) => warn!("Confirming the new firmware failed: {:?}", e),
+    }
+}
+
+/// This task takes the updates for this board and relays the others.
+#[embassy_executor::task]
+async fn ota_task(stack: Stack<'static>, flash: &'static SharedFlash, board: &'static str, relay: bool) -> ! {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+
+        if let Err(e) = socket.accept(OTA_PORT).await {
+            warn!("OTA accept error: {:?}", e);
+            continue;
+        }
+
+        let result = update(&mut socket, stack, flash, board, relay).await;
+        let mut reply: String<48> = String::new();
+        match &result {
+            Ok(Outcome::Stored) => {
+                let _ = core::writeln!(reply, "Ok");
+            }
+            Ok(Outcome::Relayed) => {}
+            Err(e) => {
+                warn!("Update failed: {}", e);
+                let _ = core::writeln!(reply, "Error: {}", e.as_str());
+            }
+        }
+        let _ = socket.write_all(reply.as_bytes()).await;
+        let _ = socket.flush().await;
+        socket.close();
+
+        if let Ok(Outcome::Stored) = result {
+            info!("Update stored, restarting");
+            Timer::after(Duration::from_millis(500)).await;
+            cortex_m::peripheral::SCB::sys_reset();
+        }
+    }
+}
+
+/// Reads one update from `socket` and stores or relays it.
+async fn update(
+    socket: &mut TcpSocket<'_>,
+    stack: Stack<'static>,
+    flash: &'static SharedFlash,
+    board: &str,
+    relay: bool,
+) -> Result<Outcome, OtaError> {
+    let mut head = [0; HEADER_SIZE];
+    socket.read_exact(&mut head).await?;
+    let header = Header::parse(&head).ok_or(OtaError::BadHeader)?;
+    info!("Update for {} with {} bytes", header.board.as_str(), header.len);
+
+    if header.board != board {
+        if !relay {
+            return Err(OtaError::WrongBoard);
+        }
+        forward(socket, stack, &head, &header).await?;
+        return Ok(Outcome::Relayed);
+    }
+
+    // The image is swapped into the active partition, one sector smaller
+    if header.len as usize > dfu_partition().len() - ERASE_SIZE {
+        return Err(OtaError::TooLarge);
+    }
+
+    // Every write erases the sector it starts in, so write whole sectors
+    let mut aligned = AlignedBuffer([0; WRITE_SIZE]);
+    let mut updater = updater(flash, &mut aligned.0);
+    let mut chunk = [0; ERASE_SIZE];
+    let mut offset = 0;
+    while offset < header.len as usize {
+        let n = (header.len as usize - offset).min(ERASE_SIZE);
+        chunk.fill(0xff);
+        socket.read_exact(&mut chunk[..n]).await?;
+        updater.write_firmware(offset, &chunk)?;
+        offset += n;
+    }
+
+    // Hashes the partition and checks the signature of the hash
+    updater.verify_and_mark_updated(PUBLIC_KEY, &header.signature, header.len)?;
+    Ok(Outcome::Stored)
+}
+
+/// Passes an update on to the board it is for and its answer back.
+async fn forward(
+    socket: &mut TcpSocket<'_>,
+    stack: Stack<'static>,
+    head: &[u8; HEADER_SIZE],
+    header: &Header,
+) -> Result<(), OtaError> {
+    let ip = announce::peer(&header.board).ok_or(OtaError::UnknownBoard)?;
+
+    let mut rx_buffer = [0; 64];
+    let mut tx_buffer = [0; 1024];
+    let mut peer = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    peer.set_timeout(Some(Duration::from_secs(30)));
+    peer.connect(IpEndpoint::new(IpAddress::Ipv4(ip), OTA_PORT))
+        .await
+        .map_err(OtaError::Unreachable)?;
+
+    peer.write_all(head).await?;
+    let mut chunk = [0; 1024];
+    let mut left = header.len as usize;
+    while left > 0 {
+        let n = left.min(chunk.len());
+        socket.read_exact(&mut chunk[..n]).await?;
+        peer.write_all(&chunk[..n]).await?;
+        left -= n;
+    }
+    peer.flush().await?;
+
+    // The board checks the image before it answers
+    let n = peer.read(&mut chunk).await?;
+    socket.write_all(&chunk[..n]).await?;
+    peer.close();
+    Ok(())
+}
+
+/// Starts taking updates for the board called `board` on [`OTA_PORT`],
+/// relaying the ones for the other boards if `relay` is set. Uses one TCP
+/// socket of the stack, two while relaying.
+///
+/// Right after an update the new image is on trial until [`healthy`].
+pub fn start(
+    spawner: &embassy_executor::Spawner,
+    stack: Stack<'static>,
+    flash: &'static SharedFlash,
+    board: &'static str,
+    relay: bool,
+) {
+    let mut aligned = AlignedBuffer([0; WRITE_SIZE]);
+    match updater(flash, &mut aligned.0).get_state() {
+        Ok(State::Swap) => {
+            info!("Running new firmware on trial");
+            unwrap!(spawner.spawn(confirm_task(flash)));
+        }
+        Ok(_) => {}
+        Err(e) => warn!("Reading the update state failed: {:?}", e),
+    }
+
+    unwrap!(spawner.spawn(ota_task(stack, flash, board, relay)));
+}
@@ -0,0 +1,199 @@
+//! Wall-clock time from SNTP.
+//!
+//! A task asks the configured server for the time once the board is connected
+//! and again every [`SYNC_INTERVAL`]. The answer is stored as the UTC time of
+//! an [`Instant`], so any instant since boot can be turned into UTC once the
+//! first sync succeeded. Until then [`now_utc`] returns `None` and boards
+//! report the clock as unsynced.
+
+use core::cell::Cell;
+use core::fmt;
+
+use defmt::*;
+use embassy_net::dns::DnsQueryType;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address, Stack};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_lab_utils_core::sntp::{parse_reply, request, PACKET_LEN};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+
+use crate::wifi;
+
+const NTP_PORT: u16 = 123;
+
+/// How often the clock is synced again after a success.
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How long to wait before trying again after a failure.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+/// How long to wait for the server to answer.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A point in time as milliseconds since the Unix epoch, UTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct UtcTime(u64);
+
+impl UtcTime {
+    pub const fn from_unix_millis(ms: u64) -> Self {
+        Self(ms)
+    }
+
+    pub const fn as_unix_millis(&self) -> u64 {
+        self.0
+    }
+
+    pub const fn as_unix_secs(&self) -> u64 {
+        self.0 / 1000
+    }
+
+    /// Year, month, day, hour, minute and second.
+    pub fn to_civil(&self) -> (u64, u8, u8, u8, u8, u8) {
+        let secs = self.as_unix_secs();
+        let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+        // Days to civil date, from Howard Hinnant's `civil_from_days`
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+        let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u8;
+        let year = yoe + era * 400 + (month <= 2) as u64;
+
+        (
+            year,
+            month,
+            day,
+            (secs_of_day / 3600) as u8,
+            (secs_of_day / 60 % 60) as u8,
+            (secs_of_day % 60) as u8,
+        )
+    }
+}
+
+/// ISO 8601, e.g. `2025-05-20T14:03:09Z`.
+impl fmt::Display for UtcTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day, hour, minute, second) = self.to_civil();
+        core::write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second)
+    }
+}
+
+impl Format for UtcTime {
+    fn format(&self, f: Formatter) {
+        let (year, month, day, hour, minute, second) = self.to_civil();
+        defmt::write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second)
+    }
+}
+
+/// UTC time of an instant, from the last successful sync.
+#[derive(Clone, Copy)]
+struct Sync {
+    at: Instant,
+    utc: UtcTime,
+}
+
+static SYNC: Mutex<CriticalSectionRawMutex, Cell<Option<Sync>>> = Mutex::new(Cell::new(None));
+
+/// Current UTC time, `None` until the first sync.
+pub fn now_utc() -> Option<UtcTime> {
+    to_utc(Instant::now())
+}
+
+/// UTC time of `at`, `None` until the first sync.
+pub fn to_utc(at: Instant) -> Option<UtcTime> {
+    let sync = SYNC.lock(|s| s.get())?;
+    let ms = if at >= sync.at {
+        sync.utc.0 + (at - sync.at).as_millis()
+    } else {
+        sync.utc.0.saturating_sub((sync.at - at).as_millis())
+    };
+    Some(UtcTime(ms))
+}
+
+pub fn is_synced() -> bool {
+    SYNC.lock(|s| s.get()).is_some()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum SntpError {
+    /// The server name didn't resolve.
+    Dns,
+    Send,
+    Timeout,
+    /// The answer wasn't a usable server reply.
+    BadReply,
+}
+
+/// This task keeps the clock synced.
+#[embassy_executor::task]
+async fn sntp_task(stack: Stack<'static>, server: &'static str) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 128];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 128];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+    unwrap!(socket.bind(0));
+
+    loop {
+        wifi::wait_connected().await;
+
+        let delay = match sync(stack, &mut socket, server).await {
+            Ok(utc) => {
+                info!("Clock synced to {}", utc);
+                SYNC_INTERVAL
+            }
+            Err(e) => {
+                warn!("SNTP sync with {} failed: {}", server, e);
+                RETRY_INTERVAL
+            }
+        };
+        Timer::after(delay).await;
+    }
+}
+
+/// Asks `server` for the time once and stores the answer.
+async fn sync(stack: Stack<'static>, socket: &mut UdpSocket<'_>, server: &str) -> Result<UtcTime, SntpError> {
+    let address = match server.parse::<Ipv4Address>() {
+        Ok(ip) => IpAddress::Ipv4(ip),
+        Err(_) => *stack
+            .dns_query(server, DnsQueryType::A)
+            .await
+            .map_err(|_| SntpError::Dns)?
+            .first()
+            .ok_or(SntpError::Dns)?,
+    };
+    let endpoint = IpEndpoint::new(address, NTP_PORT);
+
+    let sent = Instant::now();
+    socket.send_to(&request(), endpoint).await.map_err(|_| SntpError::Send)?;
+
+    let mut packet = [0; PACKET_LEN];
+    let n = loop {
+        let (n, meta) = with_timeout(REPLY_TIMEOUT, socket.recv_from(&mut packet))
+            .await
+            .map_err(|_| SntpError::Timeout)?
+            .map_err(|_| SntpError::BadReply)?;
+        // Skip late answers to an earlier request from another server
+        if meta.endpoint == endpoint {
+            break n;
+        }
+    };
+    let received = Instant::now();
+
+    let utc = UtcTime(parse_reply(&packet[..n]).ok_or(SntpError::BadReply)?);
+
+    // The server read its clock about halfway through the round trip
+    let at = sent + (received - sent) / 2;
+    SYNC.lock(|s| s.set(Some(Sync { at, utc })));
+
+    Ok(utc)
+}
+
+/// Starts keeping the clock synced with `server`, a host name or an IPv4
+/// address. Uses one UDP socket of the stack, and one more for DNS.
+pub fn start(spawner: &embassy_executor::Spawner, stack: Stack<'static>, server: &'static str) {
+    unwrap!(spawner.spawn(sntp_task(stack, server)));
+}
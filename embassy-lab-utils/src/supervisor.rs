@@ -0,0 +1,108 @@
+//! Hardware watchdog fed while the supervised tasks check in.
+//!
+//! Every task registered with [`register`] gets a [`Heartbeat`] and has to
+//! beat it within its timeout, so tasks that wait for events wait with a
+//! timeout. The supervisor feeds the RP watchdog while all of them keep up.
+//! When one falls behind, it notes the task in the crash record and restarts
+//! the board. The next run reports it through [`crate::crash::last`].
+//!
+//! A task that blocks the executor stops the supervisor too, then the watchdog
+//! runs out and restarts the board without naming a task.
+
+use core::cell::RefCell;
+
+use defmt::*;
+use embassy_rp::peripherals::WATCHDOG;
+use embassy_rp::watchdog::Watchdog;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Ticker};
+use heapless::Vec;
+
+use crate::crash;
+
+/// Most tasks supervised at once.
+pub const MAX_TASKS: usize = 12;
+
+/// How long the watchdog waits for the supervisor, within the RP2040's limit.
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(8);
+
+/// How often the heartbeats are checked and the watchdog fed.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Task {
+    name: &'static str,
+    timeout: Duration,
+    last_beat: Instant,
+}
+
+static TASKS: Mutex<CriticalSectionRawMutex, RefCell<Vec<Task, MAX_TASKS>>> = Mutex::new(RefCell::new(Vec::new()));
+
+/// Proof of life of one supervised task.
+pub struct Heartbeat(usize);
+
+impl Heartbeat {
+    pub fn beat(&self) {
+        TASKS.lock(|tasks| tasks.borrow_mut()[self.0].last_beat = Instant::now());
+    }
+}
+
+/// Supervises the task called `name`, which has to beat the returned
+/// heartbeat at least every `timeout`.
+pub fn register(name: &'static str, timeout: Duration) -> Heartbeat {
+    TASKS.lock(|tasks| {
+        let mut tasks = tasks.borrow_mut();
+        unwrap!(
+            tasks
+                .push(Task {
+                    name,
+                    timeout,
+                    last_beat: Instant::now(),
+                })
+                .ok(),
+            "too many supervised tasks"
+        );
+        Heartbeat(tasks.len() - 1)
+    })
+}
+
+/// This task feeds the watchdog while every supervised task checks in.
+#[embassy_executor::task]
+async fn supervisor_task(mut watchdog: Watchdog) -> ! {
+    let mut ticker = Ticker::every(CHECK_INTERVAL);
+
+    loop {
+        let now = Instant::now();
+        let stalled = TASKS.lock(|tasks| {
+            tasks
+                .borrow()
+                .iter()
+                .find(|task| now.saturating_duration_since(task.last_beat) > task.timeout)
+                .map(|task| task.name)
+        });
+
+        if let Some(name) = stalled {
+            error!("Task {} stopped checking in, restarting", name);
+            crash::note_stall(name);
+            watchdog.trigger_reset();
+        }
+
+        crash::alive();
+        watchdog.feed();
+        ticker.next().await;
+    }
+}
+
+/// Arms the watchdog, reads the crash record of the last run and starts
+/// supervising. Call it right after the peripherals are set up, the
+/// bootloader leaves the watchdog running.
+pub fn start(spawner: &embassy_executor::Spawner, watchdog: WATCHDOG) {
+    let mut watchdog = Watchdog::new(watchdog);
+
+    // Tell why the last run ended
+    crash::init(watchdog.reset_reason());
+
+    watchdog.pause_on_debug(true);
+    watchdog.start(WATCHDOG_TIMEOUT);
+    unwrap!(spawner.spawn(supervisor_task(watchdog)));
+}
@@ -0,0 +1,178 @@
+//! WiFi connection manager.
+//!
+//! Joins the first reachable of the configured networks with retry and
+//! backoff, waits for DHCP, then keeps watching the link and rejoins when it
+//! drops. Every change is published as a
+//! [`Connectivity`] value that application tasks can await.
+
+use cyw43::{Control, JoinOptions};
+use defmt::*;
+use embassy_net::{Ipv4Address, Stack};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::watch::{Receiver, Watch};
+use embassy_time::{with_timeout, Duration, Timer};
+
+/// Number of tasks that may hold a connectivity receiver at the same time.
+pub const MAX_LISTENERS: usize = 4;
+
+/// Shortest and longest wait between two join attempts.
+const JOIN_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const JOIN_BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+/// How long to wait for DHCP after joining before trying again.
+const DHCP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often the link is checked once the board is connected.
+const LINK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Connectivity {
+    /// Not joined, or joined but without an address yet.
+    Down,
+    /// Joined and configured with the given address.
+    Up(Ipv4Address),
+}
+
+/// WiFi network the board may join.
+#[derive(Clone, Copy, Debug, defmt::Format)]
+pub struct Network {
+    pub ssid: &'static str,
+    pub password: &'static str,
+}
+
+impl Network {
+    pub const fn new(ssid: &'static str, password: &'static str) -> Self {
+        Self { ssid, password }
+    }
+}
+
+/// Latest connectivity state, see [`receiver`] and [`wait_connected`].
+pub static CONNECTIVITY: Watch<CriticalSectionRawMutex, Connectivity, MAX_LISTENERS> =
+    Watch::new_with(Connectivity::Down);
+
+/// Owns the WiFi controller and keeps the board connected.
+pub struct WifiManager {
+    control: Control<'static>,
+    stack: Stack<'static>,
+    networks: &'static [Network],
+}
+
+impl WifiManager {
+    pub fn new(
+        control: Control<'static>,
+        stack: Stack<'static>,
+        networks: &'static [Network],
+    ) -> Self {
+        Self {
+            control,
+            stack,
+            networks,
+        }
+    }
+
+    /// Joins one of the networks and waits for an address, trying them in
+    /// order and waiting an increasing delay after every full round.
+    pub async fn connect(&mut self) -> Ipv4Address {
+        let mut backoff = JOIN_BACKOFF_MIN;
+
+        loop {
+            for network in self.networks {
+                if let Some(ip) = self.try_network(network).await {
+                    return ip;
+                }
+            }
+
+            Timer::after(backoff).await;
+            backoff = (backoff * 2).min(JOIN_BACKOFF_MAX);
+        }
+    }
+
+    async fn try_network(&mut self, network: &Network) -> Option<Ipv4Address> {
+        match self
+            .control
+            .join(network.ssid, JoinOptions::new(network.password.as_bytes()))
+            .await
+        {
+            Ok(_) => {
+                info!("Successfully joined WiFi network: {}", network.ssid);
+
+                info!("Waiting for DHCP...");
+                match with_timeout(DHCP_TIMEOUT, self.wait_address()).await {
+                    Ok(ip) => {
+                        info!("Assigned IP address: {}", ip);
+                        CONNECTIVITY.sender().send(Connectivity::Up(ip));
+                        Some(ip)
+                    }
+                    Err(_) => {
+                        warn!("No address after {}s, trying the next network", DHCP_TIMEOUT.as_secs());
+                        self.control.leave().await;
+                        None
+                    }
+                }
+            }
+            Err(err) => {
+                info!("Joining {} failed with status={}", network.ssid, err.status);
+                None
+            }
+        }
+    }
+
+    /// Watches the link and rejoins whenever it is lost. Never returns.
+    pub async fn run(mut self) -> ! {
+        loop {
+            if !self.stack.is_link_up() || !self.stack.is_config_up() {
+                warn!("WiFi link lost, rejoining");
+                CONNECTIVITY.sender().send(Connectivity::Down);
+                self.control.leave().await;
+                self.connect().await;
+            }
+
+            Timer::after(LINK_POLL_INTERVAL).await;
+        }
+    }
+
+    async fn wait_address(&self) -> Ipv4Address {
+        loop {
+            if self.stack.is_config_up() {
+                if let Some(ip_config) = self.stack.config_v4() {
+                    return ip_config.address.address();
+                }
+            }
+            Timer::after_millis(100).await;
+        }
+    }
+}
+
+/// This task connects the board and keeps it connected.
+#[embassy_executor::task]
+async fn wifi_task(mut manager: WifiManager) -> ! {
+    manager.connect().await;
+    manager.run().await
+}
+
+/// Hands the controller to a [`WifiManager`] running in its own task. Use
+/// [`wait_connected`] or a [`receiver`] to follow the connection.
+pub fn start(
+    spawner: &embassy_executor::Spawner,
+    control: Control<'static>,
+    stack: Stack<'static>,
+    networks: &'static [Network],
+) {
+    let manager = WifiManager::new(control, stack, networks);
+    unwrap!(spawner.spawn(wifi_task(manager)));
+}
+
+/// Returns a receiver for tasks that want to react to every connectivity
+/// change, or `None` if [`MAX_LISTENERS`] receivers are already taken.
+pub fn receiver() -> Option<Receiver<'static, CriticalSectionRawMutex, Connectivity, MAX_LISTENERS>> {
+    CONNECTIVITY.receiver()
+}
+
+/// Waits until the board is connected and returns its address.
+pub async fn wait_connected() -> Ipv4Address {
+    let mut receiver = unwrap!(receiver());
+    match receiver.get_and(|c| matches!(c, Connectivity::Up(_))).await {
+        Connectivity::Up(ip) => ip,
+        Connectivity::Down => unreachable!(),
+    }
+}
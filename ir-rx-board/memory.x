@@ -1,5 +1,30 @@
 MEMORY {
-    BOOT2 : ORIGIN = 0x10000000, LENGTH = 0x100
-    FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
-    RAM   : ORIGIN = 0x20000000, LENGTH = 264K
+    /*
+     * The firmware runs from the ACTIVE partition of the bootloader, see
+     * `bootloader/memory-rp2040.x`, which has to be flashed first.
+     */
+    BOOT2            : ORIGIN = 0x10000000, LENGTH = 0x100
+    BOOTLOADER_STATE : ORIGIN = 0x10006000, LENGTH = 4K
+    FLASH            : ORIGIN = 0x10007000, LENGTH = 1008K
+    DFU              : ORIGIN = 0x10103000, LENGTH = 1012K
+    /*
+     * The first 1K of RAM keeps the crash record over a restart, the
+     * bootloader leaves it alone too.
+     */
+    CRASH            : ORIGIN = 0x20000000, LENGTH = 1K
+    RAM              : ORIGIN = 0x20000400, LENGTH = 263K
 }
+
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(BOOT2);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(BOOT2);
+
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(BOOT2);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(BOOT2);
+
+SECTIONS {
+    /* Crash record kept over a restart, see `embassy_lab_utils::crash` */
+    .crash (NOLOAD) : ALIGN(4)
+    {
+        KEEP(*(.crash .crash.*));
+    } > CRASH
+} INSERT AFTER .uninit;
@@ -1,71 +1,103 @@
 #![no_std]
 #![no_main]
 
+use core::fmt::Write as FmtWrite;
+
 use defmt::*;
 use embassy_executor::Spawner;
-use embassy_rp::gpio::{Input, Pull};
-use embassy_time::{Duration, Instant, Timer};
+use embassy_rp::gpio::{Input, Level, Pull};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
 use embassy_net::StackResources;
 use embassy_net::tcp::TcpSocket;
-use cyw43::JoinOptions;
-use embassy_net::IpAddress;
+use embassy_net::{IpAddress, Ipv4Address};
+use embassy_lab_utils::wifi::Network;
+#[cfg(feature = "netlog")]
+use embassy_lab_utils::netlog;
+use embassy_lab_utils::{announce, ap, ota, supervisor, Addressing};
 use embassy_net::IpEndpoint;
+use heapless::String;
 use static_cell::StaticCell;
 use embedded_io_async::Write;
 
-use {defmt_rtt as _, panic_probe as _};
-
 mod irqs;
 
-const SOCK: usize = 4;
+const SOCK: usize = 6;
 static RESOURCES: StaticCell<StackResources<SOCK>> = StaticCell::<StackResources<SOCK>>::new();
 const WIFI_NETWORK: &str = "desk";
 const WIFI_PASSWORD: &str = "testing123";
+/// Our usual network first, then the main board's access point.
+const NETWORKS: &[Network] = &[Network::new(WIFI_NETWORK, WIFI_PASSWORD), ap::AP_NETWORK];
+
+/// Name sent to the DHCP server.
+const HOSTNAME: &str = "parking-ir";
+/// Lowest level streamed on the log port for `tools/netlog`, `None` keeps
+/// the log on RTT.
+#[cfg(feature = "netlog")]
+const NET_LOG: Option<netlog::Level> = Some(netlog::Level::Info);
+/// Main board address used until the main board announces itself.
+const MAIN_IP: Ipv4Address = Ipv4Address::new(192, 168, 23, 155);
 
 const MAX_PULSES: usize = 70;
+/// Longest pulse of a frame, longer ones end it. The NEC start pulse is 9ms.
+const MAX_PULSE: Duration = Duration::from_millis(20);
+/// How long the receive loop may go without checking in, a command can take
+/// a connect and a send timeout.
+const IR_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often the receive loop checks in while no remote is used.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a key of the remote asks the main board for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+enum Key {
+    Open,
+    Lock,
+    Unlock,
+    /// Lock or unlock, whichever the main board finds undoes the current state.
+    ToggleLock,
+}
+
+/// Keys of the remote by NEC command. A remote with a single lock button
+/// binds it to [`Key::ToggleLock`].
+const KEYS: &[(u8, Key)] = &[(0x45, Key::Open), (0x46, Key::Lock), (0x47, Key::Unlock)];
 
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
     let peripherals = embassy_rp::init(Default::default());
+
+    // Restart the board whenever the receive loop hangs
+    supervisor::start(&spawner, peripherals.WATCHDOG);
+
     let mut ir_sensor = Input::new(peripherals.PIN_15, Pull::None);
 
     // Init WiFi driver
-    let (net_device, mut control) = embassy_lab_utils::init_wifi!(&spawner, peripherals).await;
+    let (net_device, control) = embassy_lab_utils::init_wifi!(&spawner, peripherals).await;
 
-    // Default config for dynamic IP address
-    let config = embassy_net::Config::dhcpv4(Default::default());
+    // Dynamic IP address, the log client finds it in the announcements
+    let addressing = Addressing::Dhcp { hostname: Some(HOSTNAME) };
 
     // Init network stack
-    let stack = embassy_lab_utils::init_network_stack(&spawner, net_device, &RESOURCES, config);
+    let stack = embassy_lab_utils::init_network_stack(&spawner, net_device, &RESOURCES, addressing);
 
-    // Connect to WiFi
-    loop {
-        match control
-            .join(WIFI_NETWORK, JoinOptions::new(WIFI_PASSWORD.as_bytes()))
-            .await
-        {
-            Ok(_) => {
-                info!("Successfully joined WiFi network: {}", WIFI_NETWORK);
-
-                // Wait until the network stack is configured
-                loop {
-                    if stack.is_config_up() {
-                        if let Some(ip_config) = stack.config_v4() {
-                            info!("Assigned IP address: {}", ip_config.address.address());
-                            break;
-                        }
-                    }
-                    Timer::after(Duration::from_millis(100)).await;
-                }
-                break;
-            }
-            Err(err) => {
-                info!("Join failed with status={}", err.status);
-                Timer::after(Duration::from_secs(1)).await;
-            }
-        }
+    // Stream the log to `tools/netlog`
+    #[cfg(feature = "netlog")]
+    if let Some(level) = NET_LOG {
+        netlog::start(&spawner, stack, level);
     }
 
+    // Take firmware updates relayed by the main board
+    let flash = ota::init_flash(peripherals.FLASH);
+    ota::start(&spawner, stack, flash, "ir", false);
+
+    // Connect to WiFi, and keep reconnecting if the link drops
+    embassy_lab_utils::wifi::start(&spawner, control, stack, NETWORKS);
+    embassy_lab_utils::wifi::wait_connected().await;
+
+    // Tell the other boards where we are and learn where the main board is
+    announce::start(&spawner, stack, "ir");
+
+    // Connected, keep this firmware
+    ota::healthy();
+
     info!("Press a button on the remote...");
 
     let mut tx_buffer = [0; 128];
@@ -73,55 +105,59 @@ async fn main(spawner: Spawner) {
     let mut connected = false; // Track connection status
     let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
     socket.set_timeout(Some(Duration::from_secs(5)));
+    let heartbeat = supervisor::register("ir", IR_TIMEOUT);
 
     loop {
+        heartbeat.beat();
+
         // Wait for falling edge to begin
-        ir_sensor.wait_for_falling_edge().await;
+        if with_timeout(HEARTBEAT_INTERVAL, ir_sensor.wait_for_falling_edge()).await.is_err() {
+            continue;
+        }
 
         let mut pulses: [u32; MAX_PULSES] = [0; MAX_PULSES];
         let mut count = 0;
         let mut start_time = Instant::now();
 
+        // Alternate LOW and HIGH pulses until the frame is full or a pulse
+        // runs too long
+        let mut level = Level::Low;
         while count < MAX_PULSES {
-            // Wait for LOW pulse
-            while ir_sensor.is_low() {}
-            let now = Instant::now();
-            pulses[count] = now.duration_since(start_time).as_micros() as u32;
-            count += 1;
-            if count >= MAX_PULSES {
+            let Some(now) = wait_while(&ir_sensor, level) else {
                 break;
-            }
-            start_time = now;
-
-            // Wait for HIGH pulse
-            while ir_sensor.is_high() {}
-            let now = Instant::now();
+            };
             pulses[count] = now.duration_since(start_time).as_micros() as u32;
             count += 1;
-            if count >= MAX_PULSES {
-                break;
-            }
             start_time = now;
+            level = match level {
+                Level::Low => Level::High,
+                Level::High => Level::Low,
+            };
         }
 
         match decode_nec(&pulses[..count]) {
             Some((addr, cmd)) => {
                 info!("✅ NEC Command: 0x{:02X} (Address: 0x{:02X})", cmd, addr);
 
-                // Determine the data to send based on the command
-                let data_to_send = if cmd == 0x45 {
-                    "100"
-                } else if cmd == 0x46 {
-                    "90"
-                } else {
+                let Some(&(_, key)) = KEYS.iter().find(|(code, _)| *code == cmd) else {
                     warn!("Unknown command: 0x{:02X}", cmd);
                     continue; // Skip sending for unknown commands
                 };
 
+                // The main board checks open requests against the remote's
+                // address
+                let mut data_to_send: String<16> = String::new();
+                let _ = match key {
+                    Key::Open => core::write!(data_to_send, "100 ir-{:02x}\n", addr),
+                    Key::Lock => core::write!(data_to_send, "91\n"),
+                    Key::Unlock => core::write!(data_to_send, "92\n"),
+                    Key::ToggleLock => core::write!(data_to_send, "90\n"),
+                };
+
                 // Reconnect if not connected
                 if !connected {
                     if let Err(e) = socket
-                        .connect(IpEndpoint::new(IpAddress::v4(192, 168, 23, 155), 6000))
+                        .connect(IpEndpoint::new(IpAddress::Ipv4(announce::peer_or("main", MAIN_IP)), 6000))
                         .await
                     {
                         warn!("Failed to connect to server: {:?}", e);
@@ -138,14 +174,7 @@ async fn main(spawner: Spawner) {
                     warn!("Failed to send data: {:?}", e);
                     connected = false; // Mark as disconnected if sending fails
                 } else {
-                    info!("Sent data: {}", data_to_send);
-                }
-
-                // Close the socket if the command is "0x47"
-                if cmd == 0x47 {
-                    socket.close();
-                    connected = false;
-                    info!("Socket closed after sending command 0x47");
+                    info!("Sent data: {}", data_to_send.as_str());
                 }
             }
             None => warn!("Invalid NEC signal"),
@@ -155,6 +184,18 @@ async fn main(spawner: Spawner) {
     }
 }
 
+/// Busy-waits while the sensor reads `level`, returns when it changed or
+/// `None` when it stayed longer than [`MAX_PULSE`].
+fn wait_while(sensor: &Input<'_>, level: Level) -> Option<Instant> {
+    let deadline = Instant::now() + MAX_PULSE;
+    while sensor.get_level() == level {
+        if Instant::now() > deadline {
+            return None;
+        }
+    }
+    Some(Instant::now())
+}
+
 fn decode_nec(pulses: &[u32]) -> Option<(u8, u8)> {
     if pulses.len() < 66 {
         return None;
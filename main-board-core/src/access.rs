@@ -0,0 +1,324 @@
+//! Access policy for open requests from the remotes.
+//!
+//! Every remote is known by an ID, `ir-<hex address>` after the address in
+//! its codes, and may open the barrier on the days and hours of its
+//! schedules. Holidays replace the weekday: on a holiday only the
+//! schedules that include [`Days::HOLIDAY`] apply. Without the time only
+//! remotes that may enter around the clock are let in.
+//!
+//! The policy only looks at the local time it is given, so it can be exercised
+//! on the host.
+
+/// Address of a remote named `ir-<hex address>`, as the IR board names them.
+pub fn remote_address(id: &str) -> Option<u8> {
+    let hex = id.strip_prefix("ir-")?;
+    if hex.len() != 2 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u8::from_str_radix(hex, 16).ok()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    const ALL: [Weekday; 7] = [
+        Weekday::Monday,
+        Weekday::Tuesday,
+        Weekday::Wednesday,
+        Weekday::Thursday,
+        Weekday::Friday,
+        Weekday::Saturday,
+        Weekday::Sunday,
+    ];
+
+    /// Weekday of a day counted from 1970-01-01, which was a Thursday.
+    pub fn from_days_since_epoch(days: u64) -> Self {
+        Self::ALL[((days + 3) % 7) as usize]
+    }
+}
+
+/// Set of weekdays, plus holidays as a day of their own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Days(u8);
+
+impl Days {
+    pub const WEEKDAYS: Days = Days(0b001_1111);
+    pub const HOLIDAY: Days = Days(0b1000_0000);
+    /// Every weekday and holidays.
+    pub const ALWAYS: Days = Days(0b1111_1111);
+
+    pub const fn day(weekday: Weekday) -> Days {
+        Days(1 << weekday as u8)
+    }
+
+    pub const fn and(self, other: Days) -> Days {
+        Days(self.0 | other.0)
+    }
+
+    pub const fn contains(self, other: Days) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+/// A calendar date, year 0 matches every year.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Date {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Date {
+    pub const fn new(year: u16, month: u8, day: u8) -> Self {
+        Self { year, month, day }
+    }
+
+    /// A date that comes back every year.
+    pub const fn yearly(month: u8, day: u8) -> Self {
+        Self::new(0, month, day)
+    }
+
+    fn matches(&self, date: &Date) -> bool {
+        (self.year == 0 || self.year == date.year) && self.month == date.month && self.day == date.day
+    }
+}
+
+/// Local date and time of an open request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct LocalTime {
+    pub date: Date,
+    pub weekday: Weekday,
+    /// Minutes since midnight.
+    pub minute: u16,
+}
+
+/// Days and hours a remote may open the barrier.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Schedule {
+    pub days: Days,
+    /// First minute of the day, since midnight.
+    pub from: u16,
+    /// Minute the access ends, exclusive. Up to `24 * 60`.
+    pub until: u16,
+}
+
+impl Schedule {
+    /// Every day around the clock, holidays included.
+    pub const ALWAYS: Schedule = Schedule::hours(Days::ALWAYS, 0, 24);
+
+    /// From `from` o'clock until `until` o'clock on `days`.
+    pub const fn hours(days: Days, from: u16, until: u16) -> Self {
+        Self {
+            days,
+            from: from * 60,
+            until: until * 60,
+        }
+    }
+
+    fn is_always(&self) -> bool {
+        self.days.contains(Days::ALWAYS) && self.from == 0 && self.until >= 24 * 60
+    }
+
+    fn allows(&self, day: Days, minute: u16) -> bool {
+        self.days.contains(day) && (self.from..self.until).contains(&minute)
+    }
+}
+
+/// One remote and when it may enter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub id: &'static str,
+    pub schedules: &'static [Schedule],
+}
+
+impl Rule {
+    pub const fn new(id: &'static str, schedules: &'static [Schedule]) -> Self {
+        Self { id, schedules }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum DenyReason {
+    /// The remote isn't in the policy.
+    UnknownRemote,
+    OutsideSchedule,
+    /// The time isn't known and the remote may not enter around the clock.
+    Unsynced,
+}
+
+impl DenyReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DenyReason::UnknownRemote => "unknown_remote",
+            DenyReason::OutsideSchedule => "outside_schedule",
+            DenyReason::Unsynced => "clock_unsynced",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Policy {
+    pub rules: &'static [Rule],
+    pub holidays: &'static [Date],
+}
+
+impl Policy {
+    pub fn is_holiday(&self, date: &Date) -> bool {
+        self.holidays.iter().any(|h| h.matches(date))
+    }
+
+    /// Decides whether remote `id` may open the barrier at `now`, `None` if
+    /// the time isn't known.
+    pub fn evaluate(&self, id: &str, now: Option<LocalTime>) -> Result<(), DenyReason> {
+        let rule = self
+            .rules
+            .iter()
+            .find(|rule| rule.id == id)
+            .ok_or(DenyReason::UnknownRemote)?;
+
+        match now {
+            Some(now) => {
+                let day = if self.is_holiday(&now.date) {
+                    Days::HOLIDAY
+                } else {
+                    Days::day(now.weekday)
+                };
+                if rule.schedules.iter().any(|s| s.allows(day, now.minute)) {
+                    Ok(())
+                } else {
+                    Err(DenyReason::OutsideSchedule)
+                }
+            }
+            None if rule.schedules.iter().any(Schedule::is_always) => Ok(()),
+            None => Err(DenyReason::Unsynced),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: Policy = Policy {
+        rules: &[
+            Rule::new("ir-00", &[Schedule::ALWAYS]),
+            Rule::new(
+                "ir-01",
+                &[
+                    Schedule::hours(Days::WEEKDAYS, 7, 19),
+                    Schedule::hours(Days::day(Weekday::Saturday).and(Days::HOLIDAY), 8, 12),
+                ],
+            ),
+        ],
+        holidays: &[Date::yearly(12, 25), Date::new(2026, 4, 6)],
+    };
+
+    fn at(date: Date, weekday: Weekday, hour: u16, minute: u16) -> Option<LocalTime> {
+        Some(LocalTime {
+            date,
+            weekday,
+            minute: hour * 60 + minute,
+        })
+    }
+
+    /// A plain Wednesday.
+    fn wednesday(hour: u16, minute: u16) -> Option<LocalTime> {
+        at(Date::new(2026, 10, 14), Weekday::Wednesday, hour, minute)
+    }
+
+    #[test]
+    fn weekday_from_days() {
+        assert_eq!(Weekday::from_days_since_epoch(0), Weekday::Thursday);
+        assert_eq!(Weekday::from_days_since_epoch(4), Weekday::Monday);
+        // 2026-10-19
+        assert_eq!(Weekday::from_days_since_epoch(20_745), Weekday::Monday);
+    }
+
+    #[test]
+    fn remote_addresses() {
+        assert_eq!(remote_address("ir-00"), Some(0));
+        assert_eq!(remote_address("ir-a7"), Some(0xa7));
+        assert_eq!(remote_address("ir-FF"), Some(0xff));
+        assert_eq!(remote_address("ir-1"), None);
+        assert_eq!(remote_address("ir-+1"), None);
+        assert_eq!(remote_address("ir-100"), None);
+        assert_eq!(remote_address("unknown"), None);
+    }
+
+    #[test]
+    fn unknown_remote() {
+        assert_eq!(POLICY.evaluate("ir-02", wednesday(12, 0)), Err(DenyReason::UnknownRemote));
+        assert_eq!(POLICY.evaluate("ir-02", None), Err(DenyReason::UnknownRemote));
+    }
+
+    #[test]
+    fn always() {
+        assert_eq!(POLICY.evaluate("ir-00", wednesday(3, 0)), Ok(()));
+        assert_eq!(POLICY.evaluate("ir-00", at(Date::new(2026, 12, 25), Weekday::Friday, 23, 59)), Ok(()));
+        assert_eq!(POLICY.evaluate("ir-00", None), Ok(()));
+    }
+
+    #[test]
+    fn schedule_bounds() {
+        assert_eq!(POLICY.evaluate("ir-01", wednesday(6, 59)), Err(DenyReason::OutsideSchedule));
+        assert_eq!(POLICY.evaluate("ir-01", wednesday(7, 0)), Ok(()));
+        assert_eq!(POLICY.evaluate("ir-01", wednesday(18, 59)), Ok(()));
+        assert_eq!(POLICY.evaluate("ir-01", wednesday(19, 0)), Err(DenyReason::OutsideSchedule));
+    }
+
+    #[test]
+    fn weekend() {
+        let saturday = Date::new(2026, 10, 17);
+        assert_eq!(POLICY.evaluate("ir-01", at(saturday, Weekday::Saturday, 9, 0)), Ok(()));
+        assert_eq!(
+            POLICY.evaluate("ir-01", at(saturday, Weekday::Saturday, 13, 0)),
+            Err(DenyReason::OutsideSchedule)
+        );
+        assert_eq!(
+            POLICY.evaluate("ir-01", at(Date::new(2026, 10, 18), Weekday::Sunday, 9, 0)),
+            Err(DenyReason::OutsideSchedule)
+        );
+    }
+
+    #[test]
+    fn holidays_replace_the_weekday() {
+        // A yearly holiday on a Friday only has the holiday hours
+        let christmas = Date::new(2026, 12, 25);
+        assert!(POLICY.is_holiday(&christmas));
+        assert_eq!(POLICY.evaluate("ir-01", at(christmas, Weekday::Friday, 9, 0)), Ok(()));
+        assert_eq!(
+            POLICY.evaluate("ir-01", at(christmas, Weekday::Friday, 14, 0)),
+            Err(DenyReason::OutsideSchedule)
+        );
+
+        // A holiday of one year only
+        let easter_monday = Date::new(2026, 4, 6);
+        assert_eq!(
+            POLICY.evaluate("ir-01", at(easter_monday, Weekday::Monday, 14, 0)),
+            Err(DenyReason::OutsideSchedule)
+        );
+        assert!(!POLICY.is_holiday(&Date::new(2027, 4, 6)));
+    }
+
+    #[test]
+    fn unsynced_clock_only_lets_always_in() {
+        assert_eq!(POLICY.evaluate("ir-01", None), Err(DenyReason::Unsynced));
+    }
+
+    #[test]
+    fn days() {
+        assert!(Days::WEEKDAYS.contains(Days::day(Weekday::Friday)));
+        assert!(!Days::WEEKDAYS.contains(Days::day(Weekday::Saturday)));
+        assert!(!Days::WEEKDAYS.contains(Days::HOLIDAY));
+        assert!(Days::ALWAYS.contains(Days::HOLIDAY.and(Days::day(Weekday::Sunday))));
+    }
+}
@@ -0,0 +1,216 @@
+//! State of the parking barrier, as the barrier task keeps it and as reported
+//! to the other boards.
+
+use heapless::String;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum BarrierState {
+    Open,
+    Closed,
+    /// Closed and refusing open commands until unlocked.
+    Locked,
+    /// Held open with both LEDs flashing until an operator clears the
+    /// emergency, whether locked or not.
+    Emergency,
+}
+
+impl BarrierState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BarrierState::Open => "Open",
+            BarrierState::Closed => "Closed",
+            BarrierState::Locked => "Locked",
+            BarrierState::Emergency => "Emergency",
+        }
+    }
+
+    /// Encodes the state as `Barrier: <state>`, e.g. `Barrier: Locked`.
+    pub fn encode(&self) -> String<32> {
+        let mut out = String::new();
+        let _ = out.push_str("Barrier: ");
+        let _ = out.push_str(self.as_str());
+        out
+    }
+}
+
+/// Requests to the barrier, from the IR remote, the command port, HTTP or
+/// MQTT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum BarrierCommand {
+    /// Open for a moment to let one car through, unless locked.
+    Open,
+    /// Lock if unlocked and the other way round, for a remote with a single
+    /// lock button. A repeated toggle undoes itself, other clients use
+    /// [`BarrierCommand::Lock`] and [`BarrierCommand::Unlock`].
+    ToggleLock,
+    Lock,
+    Unlock,
+    /// Back to closed or locked after an emergency. Emergencies start through
+    /// their own signal, ahead of the queued commands.
+    ClearEmergency,
+}
+
+/// Latch word of an emergency that isn't cleared yet, `EMER`.
+pub const EMERGENCY_LATCH: u32 = 0x5245_4d45;
+
+/// What an open command does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Opening {
+    /// Open, then close on its own.
+    Open,
+    /// Refused while locked.
+    Locked,
+    /// Already held open for an emergency.
+    HeldOpen,
+}
+
+/// Lock and emergency of the barrier, as the barrier task keeps them.
+///
+/// An emergency holds the barrier open whether locked or not. Locking and
+/// unlocking still count during it and take effect once it is cleared. The
+/// emergency is kept in a latch word that survives a restart, so the
+/// barrier opens again after one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Control {
+    locked: bool,
+    emergency: bool,
+}
+
+impl Control {
+    pub const fn new() -> Self {
+        Self {
+            locked: false,
+            emergency: false,
+        }
+    }
+
+    /// Starts from the latch word left by the last run, any value but
+    /// [`EMERGENCY_LATCH`] counts as no emergency.
+    pub fn restore(latch: u32) -> Self {
+        Self {
+            locked: false,
+            emergency: latch == EMERGENCY_LATCH,
+        }
+    }
+
+    /// Latch word to keep over a restart.
+    pub fn latch(&self) -> u32 {
+        if self.emergency { EMERGENCY_LATCH } else { 0 }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    pub fn is_emergency(&self) -> bool {
+        self.emergency
+    }
+
+    /// State of the barrier while it isn't open for a car.
+    pub fn state(&self) -> BarrierState {
+        if self.emergency {
+            BarrierState::Emergency
+        } else if self.locked {
+            BarrierState::Locked
+        } else {
+            BarrierState::Closed
+        }
+    }
+
+    /// Returns whether the emergency is new, the barrier is held open then.
+    pub fn start_emergency(&mut self) -> bool {
+        !core::mem::replace(&mut self.emergency, true)
+    }
+
+    /// Returns whether there was an emergency, the barrier closes then.
+    pub fn clear_emergency(&mut self) -> bool {
+        core::mem::replace(&mut self.emergency, false)
+    }
+
+    pub fn open(&self) -> Opening {
+        if self.emergency {
+            Opening::HeldOpen
+        } else if self.locked {
+            Opening::Locked
+        } else {
+            Opening::Open
+        }
+    }
+
+    /// Applies `Lock`, `Unlock` or `ToggleLock`, returns the new lock state
+    /// if it changed. Other commands change nothing.
+    pub fn set_lock(&mut self, command: BarrierCommand) -> Option<bool> {
+        let lock = match command {
+            BarrierCommand::ToggleLock => !self.locked,
+            BarrierCommand::Lock => true,
+            BarrierCommand::Unlock => false,
+            BarrierCommand::Open | BarrierCommand::ClearEmergency => return None,
+        };
+        if lock == self.locked {
+            return None;
+        }
+        self.locked = lock;
+        Some(lock)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_and_unlock() {
+        let mut control = Control::new();
+        assert_eq!((control.state(), control.open()), (BarrierState::Closed, Opening::Open));
+        assert_eq!(control.set_lock(BarrierCommand::Lock), Some(true));
+        assert_eq!(control.set_lock(BarrierCommand::Lock), None);
+        assert_eq!((control.state(), control.open()), (BarrierState::Locked, Opening::Locked));
+        assert_eq!(control.set_lock(BarrierCommand::ToggleLock), Some(false));
+        assert_eq!(control.set_lock(BarrierCommand::Unlock), None);
+        assert_eq!(control.set_lock(BarrierCommand::Open), None);
+        assert_eq!(control.state(), BarrierState::Closed);
+    }
+
+    #[test]
+    fn emergency_set_and_clear() {
+        let mut control = Control::new();
+        assert!(!control.clear_emergency());
+        assert!(control.start_emergency());
+        assert!(!control.start_emergency());
+        assert_eq!((control.state(), control.open()), (BarrierState::Emergency, Opening::HeldOpen));
+        assert_eq!(control.latch(), EMERGENCY_LATCH);
+
+        assert!(control.clear_emergency());
+        assert_eq!((control.state(), control.open()), (BarrierState::Closed, Opening::Open));
+        assert_eq!(control.latch(), 0);
+    }
+
+    #[test]
+    fn emergency_overrides_the_lock() {
+        let mut control = Control::new();
+        control.set_lock(BarrierCommand::Lock);
+        control.start_emergency();
+        assert_eq!((control.state(), control.open()), (BarrierState::Emergency, Opening::HeldOpen));
+
+        // Unlocking counts, but the barrier stays held open
+        assert_eq!(control.set_lock(BarrierCommand::Unlock), Some(false));
+        assert_eq!(control.state(), BarrierState::Emergency);
+        assert_eq!(control.set_lock(BarrierCommand::Lock), Some(true));
+
+        control.clear_emergency();
+        assert_eq!((control.state(), control.open()), (BarrierState::Locked, Opening::Locked));
+    }
+
+    #[test]
+    fn emergency_survives_a_restart() {
+        let mut control = Control::new();
+        control.start_emergency();
+        let restored = Control::restore(control.latch());
+        assert!(restored.is_emergency());
+        assert!(!restored.is_locked());
+
+        // Random RAM after power on
+        assert!(!Control::restore(0xdead_beef).is_emergency());
+        assert!(!Control::restore(0).is_emergency());
+    }
+}
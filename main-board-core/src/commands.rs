@@ -0,0 +1,374 @@
+//! Commands taken on TCP port 6000, one per line.
+//!
+//! The IR board sends `100 <remote>` to open, checked against the access
+//! policy, `91` to lock, `92` to unlock and `90` to toggle the lock against
+//! the state the main board knows, and never reads replies. A bare `100`
+//! comes from an unknown remote. These are only taken from the IR board's
+//! address. Other clients lock and find out the state of the barrier with
+//!
+//! - `Lock: <key>` and `Unlock: <key>`, answered with the state they left the barrier in,
+//!   `Ok Locked` or `Ok Closed`. During an emergency they answer
+//!   `Ok Emergency`: the barrier stays open and the lock takes effect once
+//!   the emergency is cleared,
+//! - `State`, answered with `Ok Open`, `Ok Closed`, `Ok Locked` or
+//!   `Ok Emergency`,
+//!
+//! which can be repeated safely. Reservations are managed with
+//!
+//! - `Reserve: <spot> <holder> <minutes> <key>`, from now on,
+//! - `Reserve: <spot> <holder> <start> <end> <key>`, UTC seconds since the
+//!   Unix epoch,
+//! - `Cancel: <spot> <key>`, dropping every reservation of the spot,
+//!
+//! the exit terminal pays for and lets out the car that left a spot with
+//!
+//! - `Pay: <spot> <key>`, answered with `Ok <fee in cents>`,
+//! - `Exit: <spot> <key>`, opening the barrier once paid or within the grace
+//!   period,
+//!
+//! and operators start and clear an emergency with `Emergency: on <key>` and
+//! `Emergency: off <key>`, the second answered with the state the barrier
+//! returns to. Every command but `State` and the IR board's ends with the
+//! operator key.
+//!
+//! Commands are answered with `Ok` or `Error: <reason>`. A command may
+//! arrive in several pieces, it is only taken once its newline did.
+
+use heapless::{String, Vec};
+
+use crate::barrier::BarrierCommand;
+use crate::reservations::Holder;
+
+/// Plate or remote ID.
+pub type RemoteId = Holder;
+
+/// Longest operator key taken.
+pub const MAX_KEY: usize = 32;
+
+/// Operator key given with a command, checked by the main board.
+pub type OperatorKey = String<MAX_KEY>;
+
+/// Longest command line taken.
+pub const MAX_LINE: usize = 128;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Window {
+    /// From now on for this many minutes.
+    Minutes(u32),
+    /// UTC seconds since the Unix epoch, the end is exclusive.
+    Between { start: u64, end: u64 },
+}
+
+impl Window {
+    /// Start and end in UTC seconds, `now` being the current time.
+    pub fn resolve(&self, now: u64) -> (u64, u64) {
+        match *self {
+            Window::Minutes(minutes) => (now, now + minutes as u64 * 60),
+            Window::Between { start, end } => (start, end),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Open request from a remote, `None` if it didn't say which.
+    RemoteOpen(Option<RemoteId>),
+    /// Lock button of a remote.
+    Barrier(BarrierCommand),
+    /// Lock with `true`, unlock with `false`.
+    Lock(bool, OperatorKey),
+    State,
+    Reserve {
+        spot: u8,
+        holder: Holder,
+        window: Window,
+        key: OperatorKey,
+    },
+    CancelReservation(u8, OperatorKey),
+    Pay(u8, OperatorKey),
+    Exit(u8, OperatorKey),
+    /// Start an emergency with `true`, clear it with `false`.
+    Emergency(bool, OperatorKey),
+}
+
+impl Command {
+    /// Whether the sender waits for `Ok` or `Error`.
+    pub fn expects_reply(&self) -> bool {
+        !self.is_remote_key()
+    }
+
+    /// Whether the IR board sends this for a key of a remote.
+    pub fn is_remote_key(&self) -> bool {
+        matches!(self, Command::RemoteOpen(_) | Command::Barrier(_))
+    }
+
+    /// The operator key the command came with, the ones without don't need
+    /// it.
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            Command::RemoteOpen(_) | Command::Barrier(_) | Command::State => None,
+            Command::Lock(_, key)
+            | Command::Reserve { key, .. }
+            | Command::CancelReservation(_, key)
+            | Command::Pay(_, key)
+            | Command::Exit(_, key)
+            | Command::Emergency(_, key) => Some(key),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ParseError {
+    Unknown,
+    /// A known command with missing or bad arguments, answered with an error.
+    Malformed,
+}
+
+pub fn parse(line: &str) -> Result<Command, ParseError> {
+    let line = line.trim();
+    let mut words = line.split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+        (Some("90"), None, _) => return Ok(Command::Barrier(BarrierCommand::ToggleLock)),
+        (Some("91"), None, _) => return Ok(Command::Barrier(BarrierCommand::Lock)),
+        (Some("92"), None, _) => return Ok(Command::Barrier(BarrierCommand::Unlock)),
+        (Some("100"), remote, None) => {
+            let remote = remote
+                .map(|remote| RemoteId::try_from(remote).map_err(|_| ParseError::Malformed))
+                .transpose()?;
+            return Ok(Command::RemoteOpen(remote));
+        }
+        _ => {}
+    }
+
+    // `State` may leave out the colon
+    let (kind, args) = line.split_once(':').unwrap_or((line, ""));
+    if kind == "State" {
+        return match args.trim() {
+            "" => Ok(Command::State),
+            _ => Err(ParseError::Malformed),
+        };
+    }
+    if !matches!(kind, "Lock" | "Unlock" | "Reserve" | "Cancel" | "Pay" | "Exit" | "Emergency") {
+        return Err(ParseError::Unknown);
+    }
+
+    // The others end with the operator key
+    let mut words: Vec<&str, 6> = Vec::new();
+    for word in args.split_whitespace() {
+        words.push(word).map_err(|_| ParseError::Malformed)?;
+    }
+    let key = words.pop().ok_or(ParseError::Malformed)?;
+    let key = OperatorKey::try_from(key).map_err(|_| ParseError::Malformed)?;
+    let mut args = words.into_iter();
+    let command = match kind {
+        "Lock" => Command::Lock(true, key),
+        "Unlock" => Command::Lock(false, key),
+        "Reserve" => {
+            let spot = number(args.next())?;
+            let holder = args.next().ok_or(ParseError::Malformed)?;
+            let holder = Holder::try_from(holder).map_err(|_| ParseError::Malformed)?;
+            let first = number(args.next())?;
+            let window = match args.next() {
+                Some(end) => Window::Between {
+                    start: first,
+                    end: number(Some(end))?,
+                },
+                None => Window::Minutes(u32::try_from(first).map_err(|_| ParseError::Malformed)?),
+            };
+            Command::Reserve { spot, holder, window, key }
+        }
+        "Cancel" => Command::CancelReservation(number(args.next())?, key),
+        "Pay" => Command::Pay(number(args.next())?, key),
+        "Exit" => Command::Exit(number(args.next())?, key),
+        _ => {
+            let on = match args.next() {
+                Some("on") => true,
+                Some("off") => false,
+                _ => return Err(ParseError::Malformed),
+            };
+            Command::Emergency(on, key)
+        }
+    };
+
+    if args.next().is_some() {
+        return Err(ParseError::Malformed);
+    }
+    Ok(command)
+}
+
+fn number<T: core::str::FromStr>(arg: Option<&str>) -> Result<T, ParseError> {
+    arg.and_then(|arg| arg.parse().ok()).ok_or(ParseError::Malformed)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum LineError {
+    /// Longer than [`MAX_LINE`], the rest up to its newline is dropped.
+    TooLong,
+    NotText,
+}
+
+/// Collects the bytes of one connection into lines.
+pub struct LineBuffer {
+    line: Vec<u8, MAX_LINE>,
+    too_long: bool,
+}
+
+impl LineBuffer {
+    pub const fn new() -> Self {
+        Self {
+            line: Vec::new(),
+            too_long: false,
+        }
+    }
+
+    /// Takes the next byte received, returns the line it ended.
+    pub fn push(&mut self, byte: u8) -> Option<Result<String<MAX_LINE>, LineError>> {
+        if byte != b'\n' {
+            self.too_long |= self.line.push(byte).is_err();
+            return None;
+        }
+
+        let line = core::mem::take(&mut self.line);
+        if core::mem::take(&mut self.too_long) {
+            return Some(Err(LineError::TooLong));
+        }
+        Some(String::from_utf8(line).map_err(|_| LineError::NotText))
+    }
+}
+
+impl Default for LineBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(buffer: &mut LineBuffer, bytes: &[u8]) -> std::vec::Vec<Result<std::string::String, LineError>> {
+        bytes
+            .iter()
+            .filter_map(|byte| buffer.push(*byte))
+            .map(|line| line.map(|line| line.as_str().into()))
+            .collect()
+    }
+
+    #[test]
+    fn commands_end_with_the_key() {
+        assert_eq!(parse("Pay: 2 secret-key"), Ok(Command::Pay(2, OperatorKey::try_from("secret-key").unwrap())));
+        assert_eq!(parse("Pay: 2"), Err(ParseError::Malformed));
+        assert_eq!(parse("Lock"), Err(ParseError::Malformed));
+        assert_eq!(parse("Lock: secret-key").unwrap().key(), Some("secret-key"));
+        assert_eq!(parse("State").unwrap().key(), None);
+        assert_eq!(parse("100 ir-1a").unwrap().key(), None);
+    }
+
+    fn key() -> OperatorKey {
+        OperatorKey::try_from("secret-key").unwrap()
+    }
+
+    #[test]
+    fn ir_board_commands() {
+        assert_eq!(parse("90"), Ok(Command::Barrier(BarrierCommand::ToggleLock)));
+        assert_eq!(parse("91"), Ok(Command::Barrier(BarrierCommand::Lock)));
+        assert_eq!(parse("92\r"), Ok(Command::Barrier(BarrierCommand::Unlock)));
+        assert_eq!(parse("100"), Ok(Command::RemoteOpen(None)));
+        assert_eq!(parse("100 ir-1a"), Ok(Command::RemoteOpen(Some(RemoteId::try_from("ir-1a").unwrap()))));
+        assert!(parse("100 ir-1a").unwrap().is_remote_key());
+        assert!(!parse("100").unwrap().expects_reply());
+    }
+
+    #[test]
+    fn every_command() {
+        let cases = [
+            ("State", Command::State),
+            ("State:", Command::State),
+            ("Lock: secret-key", Command::Lock(true, key())),
+            ("Unlock: secret-key", Command::Lock(false, key())),
+            (
+                "Reserve: 3 AB-123 90 secret-key",
+                Command::Reserve {
+                    spot: 3,
+                    holder: Holder::try_from("AB-123").unwrap(),
+                    window: Window::Minutes(90),
+                    key: key(),
+                },
+            ),
+            (
+                "Reserve: 3 AB-123 1750000000 1750003600 secret-key",
+                Command::Reserve {
+                    spot: 3,
+                    holder: Holder::try_from("AB-123").unwrap(),
+                    window: Window::Between {
+                        start: 1_750_000_000,
+                        end: 1_750_003_600,
+                    },
+                    key: key(),
+                },
+            ),
+            ("Cancel: 3 secret-key", Command::CancelReservation(3, key())),
+            ("Pay: 2 secret-key", Command::Pay(2, key())),
+            ("Exit: 2 secret-key", Command::Exit(2, key())),
+            ("Emergency: on secret-key", Command::Emergency(true, key())),
+            ("Emergency: off secret-key", Command::Emergency(false, key())),
+        ];
+        for (line, command) in cases {
+            assert_eq!(parse(line), Ok(command.clone()), "{}", line);
+            assert!(command.expects_reply() && !command.is_remote_key());
+        }
+    }
+
+    #[test]
+    fn malformed_commands() {
+        for line in [
+            "State: now",
+            "Lock:",
+            "Unlock: secret-key extra",
+            "Reserve: 3 AB-123 secret-key",
+            "Reserve: x AB-123 90 secret-key",
+            "Reserve: 3 AB-123 5000000000 secret-key",
+            "Reserve: 3 AB-123 1 2 3 secret-key",
+            "Cancel: secret-key",
+            "Pay: 256 secret-key",
+            "Pay: -1 secret-key",
+            "Exit: 2 2 secret-key",
+            "Emergency: secret-key",
+            "Emergency: maybe secret-key",
+            "100 a-remote-id-far-too-long-for-the-holder-field",
+            "Pay: 2 a-key-that-is-much-longer-than-thirty-two-chars",
+        ] {
+            assert_eq!(parse(line), Err(ParseError::Malformed), "{}", line);
+        }
+    }
+
+    #[test]
+    fn unknown_commands() {
+        for line in ["", "   ", "Open: secret-key", "lock: secret-key", "93", "90 91", "100 ir-1a extra", "Barrier: Locked", "Statement"] {
+            assert_eq!(parse(line), Err(ParseError::Unknown), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn reservation_windows() {
+        assert_eq!(Window::Minutes(90).resolve(1000), (1000, 1000 + 5400));
+        assert_eq!(Window::Between { start: 5, end: 9 }.resolve(1000), (5, 9));
+    }
+
+    #[test]
+    fn lines_over_several_reads() {
+        let mut buffer = LineBuffer::new();
+        assert_eq!(lines(&mut buffer, b"Sta"), []);
+        assert_eq!(lines(&mut buffer, b"te\nPay: 2\r\nExi"), [Ok("State".into()), Ok("Pay: 2\r".into())]);
+        assert_eq!(lines(&mut buffer, b"t: 2\n"), [Ok("Exit: 2".into())]);
+    }
+
+    #[test]
+    fn overlong_and_binary_lines() {
+        let mut buffer = LineBuffer::new();
+        let long = [b'x'; MAX_LINE + 1];
+        assert_eq!(lines(&mut buffer, &long), []);
+        assert_eq!(lines(&mut buffer, b"\nState\n"), [Err(LineError::TooLong), Ok("State".into())]);
+        assert_eq!(lines(&mut buffer, b"\xff\n"), [Err(LineError::NotText)]);
+    }
+}
@@ -0,0 +1,608 @@
+//! Persistent log of the lot events in a flash ring buffer.
+//!
+//! Every event is a fixed size record in a reserved flash region. Records are
+//! appended in order and the sector holding the oldest ones is erased when the
+//! log wraps around. The sequence number keeps counting across reboots and the
+//! boot number tells the runs apart, since events before the first clock sync
+//! only have a time relative to boot.
+//!
+//! The log is generic over the flash, so the encoding and the ring handling
+//! can be exercised on the host with a RAM backed [`NorFlash`].
+
+use core::fmt::{self, Write};
+
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::access::DenyReason;
+
+/// Size of one record in flash.
+pub const RECORD_SIZE: usize = 16;
+
+/// Record slot that has never been written.
+const ERASED: [u8; RECORD_SIZE] = [0xff; RECORD_SIZE];
+
+/// Set in the stored kind when the time is UTC.
+const UTC_FLAG: u8 = 0x80;
+
+/// Set in the stored kind of an access decision when the remote ID has no
+/// address, the argument holds the address otherwise.
+const NO_ADDRESS: u8 = 0x40;
+
+/// When an event happened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Timestamp {
+    /// Milliseconds since boot, before the clock was synced.
+    Uptime(u64),
+    /// Milliseconds since the Unix epoch.
+    Utc(u64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Peer {
+    Wifi,
+    Ir,
+    Display,
+}
+
+impl Peer {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Peer::Wifi => "wifi",
+            Peer::Ir => "ir",
+            Peer::Display => "display",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Event {
+    SpotOccupied(u8),
+    SpotFreed(u8),
+    BarrierOpened,
+    BarrierClosed,
+    BarrierLocked,
+    BarrierUnlocked,
+    /// An open command arrived while the barrier was locked.
+    CommandRejected,
+    LinkLost(Peer),
+    /// A remote passed the access policy, with the address of its
+    /// `ir-<address>` ID.
+    AccessGranted(Option<u8>),
+    AccessDenied(DenyReason, Option<u8>),
+    /// The car that left this spot paid.
+    FeePaid(u8),
+    /// The barrier was kept closed, the car that left this spot didn't pay.
+    ExitUnpaid(u8),
+    /// The barrier was opened and held for an emergency.
+    EmergencyStarted,
+    EmergencyCleared,
+    /// The car that left this spot was let out without charging it, the
+    /// clock wasn't synced to work out its fee.
+    ExitUncharged(u8),
+}
+
+impl Event {
+    fn encode(&self) -> (u8, u8) {
+        match *self {
+            Event::SpotOccupied(spot) => (1, spot),
+            Event::SpotFreed(spot) => (2, spot),
+            Event::BarrierOpened => (3, 0),
+            Event::BarrierClosed => (4, 0),
+            Event::BarrierLocked => (5, 0),
+            Event::BarrierUnlocked => (6, 0),
+            Event::CommandRejected => (7, 0),
+            Event::LinkLost(peer) => (8, peer as u8),
+            Event::AccessGranted(remote) => with_address(9, remote),
+            Event::AccessDenied(reason, remote) => with_address(
+                match reason {
+                    DenyReason::UnknownRemote => 10,
+                    DenyReason::OutsideSchedule => 16,
+                    DenyReason::Unsynced => 17,
+                },
+                remote,
+            ),
+            Event::FeePaid(spot) => (11, spot),
+            Event::ExitUnpaid(spot) => (12, spot),
+            Event::EmergencyStarted => (13, 0),
+            Event::EmergencyCleared => (14, 0),
+            Event::ExitUncharged(spot) => (15, spot),
+        }
+    }
+
+    fn decode(kind: u8, arg: u8) -> Option<Self> {
+        let remote = (kind & NO_ADDRESS == 0).then_some(arg);
+        Some(match kind {
+            1 => Event::SpotOccupied(arg),
+            2 => Event::SpotFreed(arg),
+            3 => Event::BarrierOpened,
+            4 => Event::BarrierClosed,
+            5 => Event::BarrierLocked,
+            6 => Event::BarrierUnlocked,
+            7 => Event::CommandRejected,
+            8 => Event::LinkLost(match arg {
+                0 => Peer::Wifi,
+                1 => Peer::Ir,
+                2 => Peer::Display,
+                _ => return None,
+            }),
+            11 => Event::FeePaid(arg),
+            12 => Event::ExitUnpaid(arg),
+            13 => Event::EmergencyStarted,
+            14 => Event::EmergencyCleared,
+            15 => Event::ExitUncharged(arg),
+            // The access decisions, with or without an address
+            _ => match kind & !NO_ADDRESS {
+                9 => Event::AccessGranted(remote),
+                10 => Event::AccessDenied(DenyReason::UnknownRemote, remote),
+                16 => Event::AccessDenied(DenyReason::OutsideSchedule, remote),
+                17 => Event::AccessDenied(DenyReason::Unsynced, remote),
+                _ => return None,
+            },
+        })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::SpotOccupied(_) => "spot_occupied",
+            Event::SpotFreed(_) => "spot_freed",
+            Event::BarrierOpened => "barrier_opened",
+            Event::BarrierClosed => "barrier_closed",
+            Event::BarrierLocked => "barrier_locked",
+            Event::BarrierUnlocked => "barrier_unlocked",
+            Event::CommandRejected => "command_rejected",
+            Event::LinkLost(_) => "link_lost",
+            Event::AccessGranted(_) => "access_granted",
+            Event::AccessDenied(..) => "access_denied",
+            Event::FeePaid(_) => "fee_paid",
+            Event::ExitUnpaid(_) => "exit_unpaid",
+            Event::EmergencyStarted => "emergency_started",
+            Event::EmergencyCleared => "emergency_cleared",
+            Event::ExitUncharged(_) => "exit_uncharged",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Record {
+    /// Position of the record in the log, never reused.
+    pub seq: u32,
+    /// Number of the run that wrote the record.
+    pub boot: u16,
+    pub time: Timestamp,
+    pub event: Event,
+}
+
+impl Record {
+    /// `seq`, `boot`, kind, argument, 48 bit time, checksum, little endian.
+    /// The top bit of the kind marks a UTC time.
+    pub fn encode(&self) -> [u8; RECORD_SIZE] {
+        let (kind, arg) = self.event.encode();
+        let (kind, time_ms) = match self.time {
+            Timestamp::Uptime(ms) => (kind, ms),
+            Timestamp::Utc(ms) => (kind | UTC_FLAG, ms),
+        };
+        let mut out = [0; RECORD_SIZE];
+        out[0..4].copy_from_slice(&self.seq.to_le_bytes());
+        out[4..6].copy_from_slice(&self.boot.to_le_bytes());
+        out[6] = kind;
+        out[7] = arg;
+        out[8..14].copy_from_slice(&time_ms.to_le_bytes()[..6]);
+        let check = checksum(&out[..14]);
+        out[14..16].copy_from_slice(&check.to_le_bytes());
+        out
+    }
+
+    /// Returns `None` for erased, torn or unknown records.
+    pub fn decode(bytes: &[u8; RECORD_SIZE]) -> Option<Self> {
+        if checksum(&bytes[..14]).to_le_bytes() != bytes[14..16] || *bytes == ERASED {
+            return None;
+        }
+        let mut time = [0; 8];
+        time[..6].copy_from_slice(&bytes[8..14]);
+        let time_ms = u64::from_le_bytes(time);
+        Some(Self {
+            seq: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            boot: u16::from_le_bytes([bytes[4], bytes[5]]),
+            time: match bytes[6] & UTC_FLAG {
+                0 => Timestamp::Uptime(time_ms),
+                _ => Timestamp::Utc(time_ms),
+            },
+            event: Event::decode(bytes[6] & !UTC_FLAG, bytes[7])?,
+        })
+    }
+
+    pub const CSV_HEADER: &'static str = "seq,boot,uptime_ms,utc_ms,event,detail\n";
+
+    /// One CSV line, with only one of the two times filled in. The detail is
+    /// the spot number, the lost peer or the remote of an access decision,
+    /// followed by why access was denied.
+    pub fn write_csv(&self, out: &mut impl Write) -> fmt::Result {
+        write!(out, "{},{},", self.seq, self.boot)?;
+        match self.time {
+            Timestamp::Uptime(ms) => write!(out, "{},,", ms)?,
+            Timestamp::Utc(ms) => write!(out, ",{},", ms)?,
+        }
+        write!(out, "{},", self.event.name())?;
+        match self.event {
+            Event::SpotOccupied(spot)
+            | Event::SpotFreed(spot)
+            | Event::FeePaid(spot)
+            | Event::ExitUnpaid(spot)
+            | Event::ExitUncharged(spot) => write!(out, "{}", spot)?,
+            Event::LinkLost(peer) => out.write_str(peer.as_str())?,
+            Event::AccessGranted(remote) => write_remote(out, remote)?,
+            Event::AccessDenied(reason, remote) => {
+                if remote.is_some() {
+                    write_remote(out, remote)?;
+                    out.write_char(' ')?;
+                }
+                out.write_str(reason.as_str())?
+            }
+            _ => {}
+        }
+        out.write_char('\n')
+    }
+
+    /// One JSON object, without a separator.
+    pub fn write_json(&self, out: &mut impl Write) -> fmt::Result {
+        let (field, ms) = match self.time {
+            Timestamp::Uptime(ms) => ("uptime_ms", ms),
+            Timestamp::Utc(ms) => ("utc_ms", ms),
+        };
+        write!(
+            out,
+            "{{\"seq\":{},\"boot\":{},\"{}\":{},\"event\":\"{}\"",
+            self.seq,
+            self.boot,
+            field,
+            ms,
+            self.event.name()
+        )?;
+        match self.event {
+            Event::SpotOccupied(spot)
+            | Event::SpotFreed(spot)
+            | Event::FeePaid(spot)
+            | Event::ExitUnpaid(spot)
+            | Event::ExitUncharged(spot) => write!(out, ",\"spot\":{}", spot)?,
+            Event::LinkLost(peer) => write!(out, ",\"peer\":\"{}\"", peer.as_str())?,
+            Event::AccessGranted(remote) => json_remote(out, remote)?,
+            Event::AccessDenied(reason, remote) => {
+                json_remote(out, remote)?;
+                write!(out, ",\"reason\":\"{}\"", reason.as_str())?
+            }
+            _ => {}
+        }
+        out.write_char('}')
+    }
+}
+
+/// Kind and argument of an access decision about `remote`.
+fn with_address(kind: u8, remote: Option<u8>) -> (u8, u8) {
+    match remote {
+        Some(address) => (kind, address),
+        None => (kind | NO_ADDRESS, 0),
+    }
+}
+
+/// The remote ID, `ir-<address>`, nothing without an address.
+fn write_remote(out: &mut impl Write, remote: Option<u8>) -> fmt::Result {
+    match remote {
+        Some(address) => write!(out, "ir-{:02x}", address),
+        None => Ok(()),
+    }
+}
+
+fn json_remote(out: &mut impl Write, remote: Option<u8>) -> fmt::Result {
+    match remote {
+        Some(address) => write!(out, ",\"remote\":\"ir-{:02x}\"", address),
+        None => out.write_str(",\"remote\":null"),
+    }
+}
+
+/// Fletcher-16, enough to spot a record torn by a power loss.
+pub(crate) fn checksum(bytes: &[u8]) -> u16 {
+    let (mut a, mut b) = (0u16, 0u16);
+    for byte in bytes {
+        a = (a + *byte as u16) % 255;
+        b = (b + a) % 255;
+    }
+    (b << 8) | a
+}
+
+/// Position of a reader in the log, see [`EventLog::read`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Cursor {
+    slot: u32,
+    /// Slots still to read.
+    left: u32,
+}
+
+pub struct EventLog<F: NorFlash> {
+    flash: F,
+    /// Start of the log region, sector aligned.
+    base: u32,
+    slots: u32,
+    /// Slot the next record goes to, the oldest record follows it.
+    next_slot: u32,
+    next_seq: u32,
+    boot: u16,
+}
+
+impl<F: NorFlash> EventLog<F> {
+    /// Opens the log in the `size` bytes at `base` and continues after its
+    /// newest record. Both have to be multiples of the erase size.
+    pub fn mount(mut flash: F, base: u32, size: u32) -> Result<Self, F::Error> {
+        let slots = size / RECORD_SIZE as u32;
+        let mut newest: Option<(u32, Record)> = None;
+
+        for slot in 0..slots {
+            let mut bytes = [0; RECORD_SIZE];
+            flash.read(base + slot * RECORD_SIZE as u32, &mut bytes)?;
+            if let Some(record) = Record::decode(&bytes) {
+                if newest.is_none_or(|(_, n)| record.seq > n.seq) {
+                    newest = Some((slot, record));
+                }
+            }
+        }
+
+        let (next_slot, next_seq, boot) = match newest {
+            Some((slot, record)) => ((slot + 1) % slots, record.seq.wrapping_add(1), record.boot.wrapping_add(1)),
+            None => (0, 0, 0),
+        };
+
+        Ok(Self {
+            flash,
+            base,
+            slots,
+            next_slot,
+            next_seq,
+            boot,
+        })
+    }
+
+    /// Number of the current run.
+    pub fn boot(&self) -> u16 {
+        self.boot
+    }
+
+    /// Number of record slots, the most records the log holds.
+    pub fn slots(&self) -> u32 {
+        self.slots
+    }
+
+    /// Appends an event, erasing the oldest sector first when the log wraps.
+    pub fn append(&mut self, time: Timestamp, event: Event) -> Result<Record, F::Error> {
+        let per_sector = (F::ERASE_SIZE / RECORD_SIZE) as u32;
+
+        // Skip what a power loss left behind, it can't be written over
+        loop {
+            if self.next_slot % per_sector == 0 {
+                let sector = self.base + self.next_slot * RECORD_SIZE as u32;
+                self.flash.erase(sector, sector + F::ERASE_SIZE as u32)?;
+                break;
+            }
+            let mut bytes = [0; RECORD_SIZE];
+            self.flash.read(self.slot_offset(self.next_slot), &mut bytes)?;
+            if bytes == ERASED {
+                break;
+            }
+            self.next_slot = (self.next_slot + 1) % self.slots;
+        }
+
+        let record = Record {
+            seq: self.next_seq,
+            boot: self.boot,
+            time,
+            event,
+        };
+        self.flash.write(self.slot_offset(self.next_slot), &record.encode())?;
+
+        self.next_slot = (self.next_slot + 1) % self.slots;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        Ok(record)
+    }
+
+    /// A reader starting at the oldest record.
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            slot: self.next_slot,
+            left: self.slots,
+        }
+    }
+
+    /// The record at `cursor`, skipping empty slots, `None` once every slot
+    /// was read. The log can be appended to between the calls: new records
+    /// go behind the cursor and aren't read, while the oldest ones it hasn't
+    /// reached yet may be erased by then.
+    pub fn read(&mut self, cursor: &mut Cursor) -> Result<Option<Record>, F::Error> {
+        while cursor.left > 0 {
+            let mut bytes = [0; RECORD_SIZE];
+            self.flash.read(self.slot_offset(cursor.slot), &mut bytes)?;
+            cursor.slot = (cursor.slot + 1) % self.slots;
+            cursor.left -= 1;
+            if let Some(record) = Record::decode(&bytes) {
+                return Ok(Some(record));
+            }
+        }
+        Ok(None)
+    }
+
+    fn slot_offset(&self, slot: u32) -> u32 {
+        self.base + slot * RECORD_SIZE as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ram_flash::RamFlash;
+
+    /// Two sectors of four records.
+    type SmallFlash = RamFlash<128, 64>;
+
+    fn record(seq: u32, time: Timestamp, event: Event) -> Record {
+        Record { seq, boot: 3, time, event }
+    }
+
+    fn all(log: &mut EventLog<SmallFlash>) -> Vec<Record> {
+        let mut cursor = log.cursor();
+        let mut records = Vec::new();
+        while let Some(record) = log.read(&mut cursor).unwrap() {
+            records.push(record);
+        }
+        records
+    }
+
+    fn seqs(log: &mut EventLog<SmallFlash>) -> Vec<u32> {
+        all(log).iter().map(|r| r.seq).collect()
+    }
+
+    #[test]
+    fn every_event_round_trips() {
+        let events = [
+            Event::SpotOccupied(1),
+            Event::SpotFreed(4),
+            Event::BarrierOpened,
+            Event::BarrierClosed,
+            Event::BarrierLocked,
+            Event::BarrierUnlocked,
+            Event::CommandRejected,
+            Event::LinkLost(Peer::Wifi),
+            Event::LinkLost(Peer::Ir),
+            Event::LinkLost(Peer::Display),
+            Event::AccessGranted(Some(0)),
+            Event::AccessGranted(Some(0xff)),
+            Event::AccessGranted(None),
+            Event::AccessDenied(DenyReason::UnknownRemote, Some(0x12)),
+            Event::AccessDenied(DenyReason::UnknownRemote, None),
+            Event::AccessDenied(DenyReason::OutsideSchedule, Some(1)),
+            Event::AccessDenied(DenyReason::OutsideSchedule, None),
+            Event::AccessDenied(DenyReason::Unsynced, Some(1)),
+            Event::FeePaid(2),
+            Event::ExitUnpaid(3),
+            Event::EmergencyStarted,
+            Event::EmergencyCleared,
+            Event::ExitUncharged(4),
+        ];
+        for event in events {
+            for time in [Timestamp::Uptime(1234), Timestamp::Utc(1_750_000_000_000)] {
+                let record = record(0x0102_0304, time, event);
+                assert_eq!(Record::decode(&record.encode()), Some(record));
+            }
+        }
+    }
+
+    #[test]
+    fn time_keeps_48_bits() {
+        let record = record(1, Timestamp::Utc((1 << 48) - 1), Event::BarrierOpened);
+        assert_eq!(Record::decode(&record.encode()), Some(record));
+    }
+
+    #[test]
+    fn erased_torn_and_unknown_records_are_skipped() {
+        assert_eq!(Record::decode(&ERASED), None);
+
+        let bytes = record(7, Timestamp::Uptime(10), Event::SpotFreed(2)).encode();
+        for i in 0..RECORD_SIZE {
+            let mut torn = bytes;
+            torn[i] ^= 0x04;
+            assert_eq!(Record::decode(&torn), None, "byte {i}");
+        }
+
+        // Unknown kinds, and the no-address flag on an event without a remote
+        for kind in [0, 18, 99, 2 | NO_ADDRESS] {
+            let mut unknown = bytes;
+            unknown[6] = kind;
+            let check = checksum(&unknown[..14]);
+            unknown[14..16].copy_from_slice(&check.to_le_bytes());
+            assert_eq!(Record::decode(&unknown), None, "kind {kind}");
+        }
+    }
+
+    #[test]
+    fn csv_and_json() {
+        let denied = record(5, Timestamp::Utc(1000), Event::AccessDenied(DenyReason::OutsideSchedule, Some(0x1a)));
+        let mut csv = String::new();
+        denied.write_csv(&mut csv).unwrap();
+        assert_eq!(csv, "5,3,,1000,access_denied,ir-1a outside_schedule\n");
+
+        let unnamed = record(5, Timestamp::Utc(1000), Event::AccessDenied(DenyReason::UnknownRemote, None));
+        let mut csv = String::new();
+        unnamed.write_csv(&mut csv).unwrap();
+        assert_eq!(csv, "5,3,,1000,access_denied,unknown_remote\n");
+
+        let mut json = String::new();
+        denied.write_json(&mut json).unwrap();
+        assert_eq!(
+            json,
+            r#"{"seq":5,"boot":3,"utc_ms":1000,"event":"access_denied","remote":"ir-1a","reason":"outside_schedule"}"#
+        );
+
+        let freed = record(6, Timestamp::Uptime(20), Event::SpotFreed(2));
+        let mut json = String::new();
+        freed.write_json(&mut json).unwrap();
+        assert_eq!(json, r#"{"seq":6,"boot":3,"uptime_ms":20,"event":"spot_freed","spot":2}"#);
+        serde_json::from_str::<serde_json::Value>(&json).unwrap();
+    }
+
+    #[test]
+    fn mount_continues_after_the_newest_record() {
+        let mut log = EventLog::mount(SmallFlash::new(), 0, 128).unwrap();
+        assert_eq!((log.boot(), log.slots()), (0, 8));
+        for spot in 1..=3 {
+            log.append(Timestamp::Uptime(spot as u64), Event::SpotOccupied(spot)).unwrap();
+        }
+
+        let mut log = EventLog::mount(log.flash, 0, 128).unwrap();
+        assert_eq!(log.boot(), 1);
+        let record = log.append(Timestamp::Uptime(0), Event::BarrierOpened).unwrap();
+        assert_eq!((record.seq, record.boot), (3, 1));
+        assert_eq!(seqs(&mut log), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn ring_wraps_around_a_sector_at_a_time() {
+        let mut log = EventLog::mount(SmallFlash::new(), 0, 128).unwrap();
+        for n in 0..18 {
+            log.append(Timestamp::Uptime(n), Event::BarrierOpened).unwrap();
+        }
+        // Writing record 16 erased the sector of records 8 to 11
+        assert_eq!(seqs(&mut log), [12, 13, 14, 15, 16, 17]);
+        assert_eq!(log.flash.erases, 5);
+
+        let mut log = EventLog::mount(log.flash, 0, 128).unwrap();
+        let record = log.append(Timestamp::Uptime(0), Event::BarrierClosed).unwrap();
+        assert_eq!(record.seq, 18);
+        assert_eq!(seqs(&mut log), [12, 13, 14, 15, 16, 17, 18]);
+    }
+
+    #[test]
+    fn append_skips_a_torn_slot() {
+        let mut log = EventLog::mount(SmallFlash::new(), 0, 128).unwrap();
+        log.append(Timestamp::Uptime(0), Event::BarrierOpened).unwrap();
+        // A power loss halfway through the next record
+        log.flash.bytes[RECORD_SIZE..RECORD_SIZE + 4].fill(0);
+
+        let mut log = EventLog::mount(log.flash, 0, 128).unwrap();
+        log.append(Timestamp::Uptime(0), Event::BarrierClosed).unwrap();
+        assert_eq!(seqs(&mut log), [0, 1]);
+        assert_eq!(log.flash.bytes[2 * RECORD_SIZE + 6], 4);
+    }
+
+    #[test]
+    fn cursor_isnt_disturbed_by_appends() {
+        let mut log = EventLog::mount(SmallFlash::new(), 0, 128).unwrap();
+        for n in 0..3 {
+            log.append(Timestamp::Uptime(n), Event::BarrierOpened).unwrap();
+        }
+        let mut cursor = log.cursor();
+        assert_eq!(log.read(&mut cursor).unwrap().map(|r| r.seq), Some(0));
+
+        log.append(Timestamp::Uptime(3), Event::BarrierClosed).unwrap();
+        let mut rest = Vec::new();
+        while let Some(record) = log.read(&mut cursor).unwrap() {
+            rest.push(record.seq);
+        }
+        assert_eq!(rest, [1, 2]);
+    }
+}
@@ -0,0 +1,269 @@
+//! Whether the barrier may open.
+//!
+//! Every open request, whether from a remote, the exit terminal or an
+//! operator and whatever it came over, is checked here right before the
+//! barrier is told to open. A remote has to pass the access policy, an
+//! operator has shown the operator key by then. A car that left its spot and
+//! owes a fee holds the barrier closed for everyone for a while, since any
+//! opening would let it out; only its own exit, once paid, opens it.
+//!
+//! A remote only lets a car in while there is a spot for it: a free one that
+//! isn't reserved, or one reserved for that remote. Plates can't be told at
+//! the gate, so a car with a reservation for its plate is let in by an
+//! operator once the lot is otherwise full.
+//!
+//! A stay longer than the grace period can't be charged before the clock is
+//! synced. Rather than keeping the car in, its exit is let through and
+//! reported as uncharged.
+//!
+//! The checks only look at what they are given, so they can be exercised on
+//! the host.
+
+use crate::access::{DenyReason, LocalTime, Policy};
+
+/// A car that left its spot and hasn't passed the exit yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Waiting {
+    /// 1-based spot number.
+    pub spot: u8,
+    pub paid: bool,
+    /// What the stay costs, `None` when it can't be told without the clock.
+    pub fee: Option<u32>,
+    /// Seconds since the car left its spot.
+    pub left_secs: u64,
+}
+
+impl Waiting {
+    /// Whether the car may only leave by paying first.
+    pub fn owes(&self) -> bool {
+        !self.paid && self.fee.is_some_and(|fee| fee > 0)
+    }
+}
+
+/// Who asks for the barrier to open.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Opener<'a> {
+    /// A remote, by its ID.
+    Remote(&'a str),
+    /// `POST /barrier/open` or the MQTT `open` command, with the operator key.
+    Operator,
+    /// The exit terminal, for the car that left this spot.
+    Exit(u8),
+}
+
+/// What an opening lets through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Pass {
+    /// No car waiting to leave is involved.
+    Open,
+    /// The car that left this spot, paid or free.
+    Exit(u8),
+    /// The car that left this spot, without knowing what it owes.
+    ExitUncharged(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Refusal {
+    /// The remote didn't pass the access policy.
+    Access(DenyReason),
+    /// The car that left this spot didn't pay.
+    Unpaid(u8),
+    /// No car left the spot the exit asked for.
+    NoDeparture,
+    /// Every free spot is reserved for somebody else.
+    Full,
+}
+
+impl Refusal {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Refusal::Access(reason) => reason.as_str(),
+            Refusal::Unpaid(_) => "unpaid",
+            Refusal::NoDeparture => "no departure",
+            Refusal::Full => "lot full",
+        }
+    }
+}
+
+/// What the barrier is checked against.
+#[derive(Clone, Copy, Debug)]
+pub struct Gate<'a> {
+    pub policy: &'a Policy,
+    /// Local time, `None` until the clock is synced.
+    pub now: Option<LocalTime>,
+    pub waiting: &'a [Waiting],
+    /// How long a car that left its spot unpaid holds the barrier closed for
+    /// the others.
+    pub exit_window_secs: u64,
+    /// Free spots that aren't reserved.
+    pub free_spots: usize,
+    /// Holders of the running reservations whose spot is still free.
+    pub held_for: &'a [&'a str],
+}
+
+impl Gate<'_> {
+    /// Decides whether `opener` may open the barrier.
+    pub fn check(&self, opener: &Opener) -> Result<Pass, Refusal> {
+        if let Opener::Remote(id) = *opener {
+            self.policy.evaluate(id, self.now).map_err(Refusal::Access)?;
+        }
+
+        if let Opener::Exit(spot) = *opener {
+            let waiting = self
+                .waiting
+                .iter()
+                .find(|w| w.spot == spot)
+                .ok_or(Refusal::NoDeparture)?;
+            if waiting.owes() {
+                return Err(Refusal::Unpaid(spot));
+            }
+            return Ok(if waiting.fee.is_none() && !waiting.paid {
+                Pass::ExitUncharged(spot)
+            } else {
+                Pass::Exit(spot)
+            });
+        }
+
+        if let Some(waiting) = self
+            .waiting
+            .iter()
+            .find(|w| w.owes() && w.left_secs <= self.exit_window_secs)
+        {
+            return Err(Refusal::Unpaid(waiting.spot));
+        }
+
+        if let Opener::Remote(id) = *opener {
+            if self.free_spots == 0 && !self.held_for.contains(&id) {
+                return Err(Refusal::Full);
+            }
+        }
+        Ok(Pass::Open)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::access::{Date, Days, Rule, Schedule, Weekday};
+
+    const WINDOW: u64 = 600;
+
+    const POLICY: Policy = Policy {
+        rules: &[
+            Rule::new("ir-00", &[Schedule::ALWAYS]),
+            Rule::new("ir-01", &[Schedule::hours(Days::WEEKDAYS, 7, 19)]),
+        ],
+        holidays: &[],
+    };
+
+    /// A Sunday night.
+    const NOW: LocalTime = LocalTime {
+        date: Date::new(2026, 10, 18),
+        weekday: Weekday::Sunday,
+        minute: 22 * 60,
+    };
+
+    fn waiting(spot: u8, paid: bool, fee: Option<u32>) -> Waiting {
+        Waiting {
+            spot,
+            paid,
+            fee,
+            left_secs: 60,
+        }
+    }
+
+    fn gate(waiting: &[Waiting]) -> Gate<'_> {
+        Gate {
+            policy: &POLICY,
+            now: Some(NOW),
+            waiting,
+            exit_window_secs: WINDOW,
+            free_spots: 1,
+            held_for: &[],
+        }
+    }
+
+    fn check(waiting: &[Waiting], opener: Opener) -> Result<Pass, Refusal> {
+        gate(waiting).check(&opener)
+    }
+
+    #[test]
+    fn opens_without_cars_leaving() {
+        assert_eq!(check(&[], Opener::Remote("ir-00")), Ok(Pass::Open));
+        assert_eq!(check(&[], Opener::Operator), Ok(Pass::Open));
+        assert_eq!(check(&[], Opener::Exit(1)), Err(Refusal::NoDeparture));
+    }
+
+    #[test]
+    fn remotes_need_the_policy() {
+        assert_eq!(check(&[], Opener::Remote("ir-01")), Err(Refusal::Access(DenyReason::OutsideSchedule)));
+        assert_eq!(check(&[], Opener::Remote("ir-02")), Err(Refusal::Access(DenyReason::UnknownRemote)));
+
+        // Access comes first, an unpaid car is only reported to those let in
+        let unpaid = [waiting(1, false, Some(200))];
+        assert_eq!(check(&unpaid, Opener::Remote("ir-01")), Err(Refusal::Access(DenyReason::OutsideSchedule)));
+        assert_eq!(check(&unpaid, Opener::Remote("ir-00")), Err(Refusal::Unpaid(1)));
+    }
+
+    #[test]
+    fn exit_needs_payment() {
+        let unpaid = [waiting(1, false, Some(200))];
+        assert_eq!(check(&unpaid, Opener::Exit(1)), Err(Refusal::Unpaid(1)));
+        assert_eq!(check(&unpaid, Opener::Exit(2)), Err(Refusal::NoDeparture));
+
+        let paid = [waiting(1, true, Some(200))];
+        assert_eq!(check(&paid, Opener::Exit(1)), Ok(Pass::Exit(1)));
+
+        let free = [waiting(1, false, Some(0))];
+        assert_eq!(check(&free, Opener::Exit(1)), Ok(Pass::Exit(1)));
+    }
+
+    #[test]
+    fn unknown_fee_lets_the_car_out() {
+        let unsynced = [waiting(3, false, None)];
+        assert_eq!(check(&unsynced, Opener::Exit(3)), Ok(Pass::ExitUncharged(3)));
+        assert_eq!(check(&unsynced, Opener::Operator), Ok(Pass::Open));
+
+        let paid = [waiting(3, true, None)];
+        assert_eq!(check(&paid, Opener::Exit(3)), Ok(Pass::Exit(3)));
+    }
+
+    #[test]
+    fn unpaid_car_holds_every_other_opening() {
+        let list = [waiting(1, true, Some(200)), waiting(2, false, Some(400))];
+        assert_eq!(check(&list, Opener::Remote("ir-00")), Err(Refusal::Unpaid(2)));
+        assert_eq!(check(&list, Opener::Operator), Err(Refusal::Unpaid(2)));
+        // The paid car still gets out
+        assert_eq!(check(&list, Opener::Exit(1)), Ok(Pass::Exit(1)));
+    }
+
+    #[test]
+    fn unpaid_car_only_holds_the_barrier_for_the_window() {
+        let mut late = waiting(2, false, Some(400));
+        late.left_secs = WINDOW;
+        assert_eq!(check(&[late], Opener::Operator), Err(Refusal::Unpaid(2)));
+        late.left_secs = WINDOW + 1;
+        assert_eq!(check(&[late], Opener::Operator), Ok(Pass::Open));
+        // Its own exit still has to be paid
+        assert_eq!(check(&[late], Opener::Exit(2)), Err(Refusal::Unpaid(2)));
+    }
+
+    #[test]
+    fn full_lot_only_lets_holders_in() {
+        let full = Gate { free_spots: 0, ..gate(&[]) };
+        assert_eq!(full.check(&Opener::Remote("ir-00")), Err(Refusal::Full));
+        assert_eq!(full.check(&Opener::Operator), Ok(Pass::Open));
+
+        let held = Gate { held_for: &["ab-123", "ir-00"], ..full };
+        assert_eq!(held.check(&Opener::Remote("ir-00")), Ok(Pass::Open));
+        // A reservation doesn't get around the schedule
+        assert_eq!(
+            Gate { held_for: &["ir-01"], ..full }.check(&Opener::Remote("ir-01")),
+            Err(Refusal::Access(DenyReason::OutsideSchedule))
+        );
+
+        // Leaving doesn't need a spot
+        let leaving = [waiting(2, true, Some(200))];
+        assert_eq!(Gate { free_spots: 0, ..gate(&leaving) }.check(&Opener::Exit(2)), Ok(Pass::Exit(2)));
+    }
+}
@@ -0,0 +1,511 @@
+//! Minimal HTTP/1.1 status and control API.
+//!
+//! `GET /` serves a small dashboard, `GET /status` the lot as JSON,
+//! `GET /sessions` the last parking sessions, `GET /reservations` the spot
+//! reservations, `GET /events.csv` and `/events.json` the event log and
+//! `GET /config` the settings. `POST /barrier/open`, `/barrier/lock` and
+//! `/barrier/unlock` queue a barrier command, `POST /config/<key>/<value>`
+//! changes a setting and `POST /emergency` and `/emergency/clear` start and
+//! clear an emergency, each with the operator key as the bearer token of the
+//! `Authorization` header. Parsing, routing and the responses don't touch the
+//! network and are tested on the host. Every connection carries one request.
+
+use core::fmt::{self, Write};
+
+use heapless::String;
+
+use crate::barrier::{BarrierCommand, BarrierState};
+use crate::occupancy::SpotState;
+use crate::sessions::{SpotStats, Stats};
+use crate::settings::Key;
+
+pub const HTTP_PORT: u16 = 80;
+
+/// The dashboard page, it polls `/status` and posts the barrier commands.
+pub const DASHBOARD: &str = include_str!("dashboard.html");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Method {
+    Get,
+    Post,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Request<'a> {
+    pub method: Method,
+    /// Request path without the query string.
+    pub path: &'a str,
+    /// Token of an `Authorization: Bearer <token>` header.
+    pub token: Option<&'a str>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ParseError {
+    /// The header isn't complete yet, read more.
+    Incomplete,
+    Malformed,
+    UnsupportedMethod,
+}
+
+/// Parses the request line once the whole header has arrived. Headers other
+/// than `Authorization` and the body are ignored, none of the routes needs
+/// them.
+pub fn parse_request(buf: &[u8]) -> Result<Request<'_>, ParseError> {
+    let end = buf
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .ok_or(ParseError::Incomplete)?;
+    let head = core::str::from_utf8(&buf[..end]).map_err(|_| ParseError::Malformed)?;
+
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let method = match parts.next() {
+        Some("GET") => Method::Get,
+        Some("POST") => Method::Post,
+        Some("") | None => return Err(ParseError::Malformed),
+        Some(_) => return Err(ParseError::UnsupportedMethod),
+    };
+    let target = parts.next().ok_or(ParseError::Malformed)?;
+    let version = parts.next().ok_or(ParseError::Malformed)?;
+    if !version.starts_with("HTTP/1.") || parts.next().is_some() {
+        return Err(ParseError::Malformed);
+    }
+
+    let path = target.split('?').next().unwrap_or_default();
+    if !path.starts_with('/') {
+        return Err(ParseError::Malformed);
+    }
+
+    let token = lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if !name.trim().eq_ignore_ascii_case("authorization") {
+            return None;
+        }
+        let (scheme, token) = value.trim().split_once(' ')?;
+        scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+    });
+
+    Ok(Request { method, path, token })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum EventFormat {
+    Csv,
+    Json,
+}
+
+impl EventFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            EventFormat::Csv => "text/csv",
+            EventFormat::Json => "application/json",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Route {
+    Dashboard,
+    Status,
+    Sessions,
+    Reservations,
+    Events(EventFormat),
+    Barrier(BarrierCommand),
+    /// Start an emergency with `true`, clear it with `false`.
+    Emergency(bool),
+    Config,
+    SetConfig(Key, i64),
+    /// A setting with a value that isn't a number.
+    BadValue,
+    NotFound,
+    MethodNotAllowed,
+}
+
+pub fn route(request: &Request) -> Route {
+    match (request.method, request.path) {
+        (Method::Get, "/") => Route::Dashboard,
+        (Method::Get, "/status") => Route::Status,
+        (Method::Get, "/sessions") => Route::Sessions,
+        (Method::Get, "/reservations") => Route::Reservations,
+        (Method::Get, "/events.csv") => Route::Events(EventFormat::Csv),
+        (Method::Get, "/events.json") => Route::Events(EventFormat::Json),
+        (Method::Post, "/barrier/open") => Route::Barrier(BarrierCommand::Open),
+        (Method::Post, "/barrier/lock") => Route::Barrier(BarrierCommand::Lock),
+        (Method::Post, "/barrier/unlock") => Route::Barrier(BarrierCommand::Unlock),
+        (Method::Post, "/emergency") => Route::Emergency(true),
+        (Method::Post, "/emergency/clear") => Route::Emergency(false),
+        (Method::Get, "/config") => Route::Config,
+        (Method::Post, path) if path.starts_with("/config/") => config_route(&path["/config/".len()..]),
+        (
+            _,
+            "/" | "/status" | "/sessions" | "/reservations" | "/events.csv" | "/events.json"
+            | "/barrier/open" | "/barrier/lock" | "/barrier/unlock" | "/emergency" | "/emergency/clear"
+            | "/config",
+        ) => {
+            Route::MethodNotAllowed
+        }
+        _ => Route::NotFound,
+    }
+}
+
+/// Routes `<key>/<value>` of `POST /config/<key>/<value>`.
+fn config_route(rest: &str) -> Route {
+    let Some((key, value)) = rest.split_once('/') else {
+        return Route::NotFound;
+    };
+    let Some(key) = Key::parse(key) else {
+        return Route::NotFound;
+    };
+    match value.parse() {
+        Ok(value) => Route::SetConfig(key, value),
+        Err(_) => Route::BadValue,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum StatusCode {
+    Ok,
+    Accepted,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    Conflict,
+    InternalServerError,
+    ServiceUnavailable,
+}
+
+impl StatusCode {
+    fn line(&self) -> &'static str {
+        match self {
+            StatusCode::Ok => "200 OK",
+            StatusCode::Accepted => "202 Accepted",
+            StatusCode::BadRequest => "400 Bad Request",
+            StatusCode::Unauthorized => "401 Unauthorized",
+            StatusCode::Forbidden => "403 Forbidden",
+            StatusCode::NotFound => "404 Not Found",
+            StatusCode::MethodNotAllowed => "405 Method Not Allowed",
+            StatusCode::Conflict => "409 Conflict",
+            StatusCode::InternalServerError => "500 Internal Server Error",
+            StatusCode::ServiceUnavailable => "503 Service Unavailable",
+        }
+    }
+}
+
+/// Status line and headers of a response with a `len` byte body. Without a
+/// length the body ends when the connection is closed.
+pub fn response_head(status: StatusCode, content_type: &str, len: Option<usize>) -> String<160> {
+    let mut out = String::new();
+    let _ = write!(out, "HTTP/1.1 {}\r\nContent-Type: {}\r\n", status.line(), content_type);
+    if let Some(len) = len {
+        let _ = write!(out, "Content-Length: {}\r\n", len);
+    }
+    let _ = out.push_str("Connection: close\r\n\r\n");
+    out
+}
+
+/// Why a board last restarted, see `embassy_lab_utils::crash`.
+pub struct BoardReset<'a> {
+    pub board: &'a str,
+    pub cause: &'a str,
+    /// How long the run before the restart lasted.
+    pub uptime_secs: u32,
+    pub detail: &'a str,
+}
+
+/// Everything `GET /status` reports.
+pub struct StatusReport<'a> {
+    pub spots: &'a [SpotState],
+    /// Spots held by a reservation right now, in spot order.
+    pub reserved: &'a [bool],
+    pub barrier: BarrierState,
+    /// Times the barrier opened since boot.
+    pub openings: u32,
+    /// Open commands refused because the barrier was locked.
+    pub denied_openings: u32,
+    /// Seconds since the IR board was last heard from.
+    pub ir_last_seen_secs: Option<u64>,
+    pub display_fault: bool,
+    /// Why the boards last restarted, those that told.
+    pub resets: &'a [BoardReset<'a>],
+    /// Milliseconds since the Unix epoch, `None` until the clock is synced.
+    pub utc_ms: Option<u64>,
+    pub stats: Stats,
+    /// Finished sessions of every spot, in spot order.
+    pub spot_stats: &'a [SpotStats],
+    /// How long the car on every spot has been parked, in spot order.
+    pub parked_secs: &'a [Option<u64>],
+}
+
+impl StatusReport<'_> {
+    /// Longest report, with every reset detail at its longest and every
+    /// character of it escaped.
+    pub const MAX_JSON: usize = 2048;
+
+    /// The report as one JSON object. Fails when `out` runs out of room
+    /// rather than leaving the JSON cut off.
+    pub fn write_json(&self, out: &mut impl Write) -> fmt::Result {
+        out.write_str("{\"spots\":[")?;
+        let mut free = 0;
+        for (i, (spot, reserved)) in self.spots.iter().zip(self.reserved).enumerate() {
+            if i > 0 {
+                out.write_char(',')?;
+            }
+            out.write_str(match (spot, reserved) {
+                (SpotState::Occupied, _) => "\"occupied\"",
+                (SpotState::Free, true) => "\"reserved\"",
+                (SpotState::Free, false) => {
+                    free += 1;
+                    "\"free\""
+                }
+            })?;
+        }
+        write!(
+            out,
+            "],\"free\":{},\"barrier\":\"{}\",\"locked\":{},\"counters\":{{\"openings\":{},\"denied_openings\":{}}},",
+            free,
+            self.barrier.as_str(),
+            self.barrier == BarrierState::Locked,
+            self.openings,
+            self.denied_openings
+        )?;
+        write!(out, "\"ir_last_seen_secs\":{},", Nullable(self.ir_last_seen_secs))?;
+        write!(out, "\"display_fault\":{},", self.display_fault)?;
+        out.write_str("\"resets\":[")?;
+        for (i, reset) in self.resets.iter().enumerate() {
+            write!(
+                out,
+                "{}{{\"board\":\"{}\",\"cause\":\"{}\",\"uptime_secs\":{},\"detail\":\"{}\"}}",
+                if i > 0 { "," } else { "" },
+                JsonStr(reset.board),
+                reset.cause,
+                reset.uptime_secs,
+                JsonStr(reset.detail)
+            )?;
+        }
+        out.write_str("],")?;
+        match self.utc_ms {
+            Some(ms) => write!(out, "\"clock\":\"synced\",\"utc_ms\":{},", ms)?,
+            None => write!(out, "\"clock\":\"unsynced\",\"utc_ms\":null,")?,
+        }
+
+        let stats = &self.stats;
+        write!(
+            out,
+            "\"sessions\":{{\"total\":{},\"average_dwell_secs\":{},\"turnover_per_hour\":{},\"occupied\":{},\"peak_occupied\":{}}},\"spot_sessions\":[",
+            stats.sessions,
+            Nullable(stats.average_dwell_secs),
+            stats.turnover_per_hour,
+            stats.occupied,
+            stats.peak_occupied
+        )?;
+        for (i, (spot, parked)) in self.spot_stats.iter().zip(self.parked_secs).enumerate() {
+            write!(
+                out,
+                "{}{{\"sessions\":{},\"average_dwell_secs\":{},\"parked_secs\":{}}}",
+                if i > 0 { "," } else { "" },
+                spot.sessions,
+                Nullable(spot.average_dwell_secs()),
+                Nullable(*parked)
+            )?;
+        }
+        out.write_str("]}")
+    }
+}
+
+/// Writes the inside of a JSON string, control characters become spaces.
+struct JsonStr<'a>(&'a str);
+
+impl fmt::Display for JsonStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                c if c.is_control() => f.write_char(' ')?,
+                c => f.write_char(c)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes a number, or `null` for `None`.
+struct Nullable(Option<u64>);
+
+impl fmt::Display for Nullable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(n) => write!(f, "{}", n),
+            None => f.write_str("null"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::occupancy::SPOT_COUNT;
+
+    fn get(path: &str) -> Request<'_> {
+        Request { method: Method::Get, path, token: None }
+    }
+
+    fn post(path: &str) -> Request<'_> {
+        Request { method: Method::Post, path, token: None }
+    }
+
+    #[test]
+    fn waits_for_the_whole_header() {
+        assert_eq!(parse_request(b""), Err(ParseError::Incomplete));
+        assert_eq!(parse_request(b"GET /status HTTP/1.1\r\nHost: lot\r\n"), Err(ParseError::Incomplete));
+    }
+
+    #[test]
+    fn parses_the_request_line() {
+        let request = parse_request(b"GET /status?pretty=1 HTTP/1.1\r\nHost: lot\r\n\r\n").unwrap();
+        assert_eq!(request, get("/status"));
+
+        let request = parse_request(b"POST /barrier/open HTTP/1.0\r\n\r\nbody is ignored").unwrap();
+        assert_eq!(request, post("/barrier/open"));
+    }
+
+    #[test]
+    fn rejects_bad_request_lines() {
+        assert_eq!(parse_request(b"DELETE / HTTP/1.1\r\n\r\n"), Err(ParseError::UnsupportedMethod));
+        assert_eq!(parse_request(b"\r\n\r\n"), Err(ParseError::Malformed));
+        assert_eq!(parse_request(b"GET /\r\n\r\n"), Err(ParseError::Malformed));
+        assert_eq!(parse_request(b"GET / HTTP/2\r\n\r\n"), Err(ParseError::Malformed));
+        assert_eq!(parse_request(b"GET / HTTP/1.1 extra\r\n\r\n"), Err(ParseError::Malformed));
+        assert_eq!(parse_request(b"GET status HTTP/1.1\r\n\r\n"), Err(ParseError::Malformed));
+        assert_eq!(parse_request(b"GET /\xff HTTP/1.1\r\n\r\n"), Err(ParseError::Malformed));
+    }
+
+    #[test]
+    fn takes_the_bearer_token() {
+        let request = parse_request(b"POST /emergency HTTP/1.1\r\nHost: lot\r\nauthorization: bearer  s3cret \r\n\r\n");
+        assert_eq!(request.unwrap().token, Some("s3cret"));
+
+        let request = parse_request(b"POST /emergency HTTP/1.1\r\nAuthorization: Basic dXNlcg==\r\n\r\n");
+        assert_eq!(request.unwrap().token, None);
+
+        // The request line isn't a header
+        let request = parse_request(b"POST /Authorization:Bearer HTTP/1.1\r\n\r\n");
+        assert_eq!(request.unwrap().token, None);
+    }
+
+    #[test]
+    fn routes_the_pages_and_commands() {
+        assert_eq!(route(&get("/")), Route::Dashboard);
+        assert_eq!(route(&get("/status")), Route::Status);
+        assert_eq!(route(&get("/sessions")), Route::Sessions);
+        assert_eq!(route(&get("/reservations")), Route::Reservations);
+        assert_eq!(route(&get("/events.csv")), Route::Events(EventFormat::Csv));
+        assert_eq!(route(&get("/events.json")), Route::Events(EventFormat::Json));
+        assert_eq!(route(&get("/config")), Route::Config);
+        assert_eq!(route(&post("/barrier/open")), Route::Barrier(BarrierCommand::Open));
+        assert_eq!(route(&post("/barrier/lock")), Route::Barrier(BarrierCommand::Lock));
+        assert_eq!(route(&post("/barrier/unlock")), Route::Barrier(BarrierCommand::Unlock));
+        assert_eq!(route(&post("/emergency")), Route::Emergency(true));
+        assert_eq!(route(&post("/emergency/clear")), Route::Emergency(false));
+    }
+
+    #[test]
+    fn routes_the_settings() {
+        assert_eq!(route(&post("/config/grace_minutes/30")), Route::SetConfig(Key::GraceMinutes, 30));
+        assert_eq!(route(&post("/config/utc_offset_minutes/-60")), Route::SetConfig(Key::UtcOffsetMinutes, -60));
+        assert_eq!(route(&post("/config/grace_minutes/half")), Route::BadValue);
+        assert_eq!(route(&post("/config/grace_minutes")), Route::NotFound);
+        assert_eq!(route(&post("/config/colour/3")), Route::NotFound);
+    }
+
+    #[test]
+    fn tells_wrong_methods_from_unknown_paths() {
+        assert_eq!(route(&get("/barrier/open")), Route::MethodNotAllowed);
+        assert_eq!(route(&get("/emergency")), Route::MethodNotAllowed);
+        assert_eq!(route(&post("/status")), Route::MethodNotAllowed);
+        assert_eq!(route(&post("/config")), Route::MethodNotAllowed);
+        assert_eq!(route(&get("/barrier/close")), Route::NotFound);
+        assert_eq!(route(&get("/config/grace_minutes/30")), Route::NotFound);
+    }
+
+    #[test]
+    fn response_head_has_the_length() {
+        assert_eq!(
+            response_head(StatusCode::Ok, "text/plain", Some(3)).as_str(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 3\r\nConnection: close\r\n\r\n"
+        );
+        assert_eq!(
+            response_head(StatusCode::InternalServerError, "text/plain", None).as_str(),
+            "HTTP/1.1 500 Internal Server Error\r\nContent-Type: text/plain\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn status_report_is_json() {
+        let report = StatusReport {
+            spots: &[SpotState::Occupied, SpotState::Free, SpotState::Free, SpotState::Free],
+            reserved: &[false, true, false, false],
+            barrier: BarrierState::Locked,
+            openings: 3,
+            denied_openings: 1,
+            ir_last_seen_secs: None,
+            display_fault: false,
+            resets: &[BoardReset { board: "main", cause: "panic", uptime_secs: 60, detail: "said \"no\"\n" }],
+            utc_ms: Some(1_760_000_000_000),
+            stats: Stats::default(),
+            spot_stats: &[SpotStats::default(); SPOT_COUNT],
+            parked_secs: &[Some(90), None, None, None],
+        };
+        let mut out: String<{ StatusReport::MAX_JSON }> = String::new();
+        report.write_json(&mut out).unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(json["spots"], serde_json::json!(["occupied", "reserved", "free", "free"]));
+        assert_eq!(json["free"], 2);
+        assert_eq!(json["locked"], true);
+        assert_eq!(json["ir_last_seen_secs"], serde_json::Value::Null);
+        assert_eq!(json["resets"][0]["detail"], "said \"no\" ");
+        assert_eq!(json["spot_sessions"][0]["parked_secs"], 90);
+    }
+
+    #[test]
+    fn longest_status_report_fits() {
+        // Quotes are escaped, so they take twice the room
+        let detail = "\"".repeat(96);
+        let reset = |board| BoardReset { board, cause: "watchdog", uptime_secs: u32::MAX, detail: &detail };
+        let resets = [reset("main"), reset("display"), reset("ir")];
+        let stats = Stats {
+            sessions: u32::MAX,
+            average_dwell_secs: Some(u64::MAX),
+            turnover_per_hour: u32::MAX,
+            occupied: u8::MAX,
+            peak_occupied: u8::MAX,
+        };
+        let spot_stats = SpotStats { sessions: u32::MAX, total_dwell_secs: u64::MAX };
+        let report = StatusReport {
+            spots: &[SpotState::Occupied; SPOT_COUNT],
+            reserved: &[false; SPOT_COUNT],
+            barrier: BarrierState::Emergency,
+            openings: u32::MAX,
+            denied_openings: u32::MAX,
+            ir_last_seen_secs: Some(u64::MAX),
+            display_fault: true,
+            resets: &resets,
+            utc_ms: Some(u64::MAX),
+            stats,
+            spot_stats: &[spot_stats; SPOT_COUNT],
+            parked_secs: &[Some(u64::MAX); SPOT_COUNT],
+        };
+
+        let mut out: String<{ StatusReport::MAX_JSON }> = String::new();
+        report.write_json(&mut out).unwrap();
+        serde_json::from_str::<serde_json::Value>(&out).unwrap();
+
+        // A buffer too small fails instead of cutting the JSON off
+        let mut short: String<1024> = String::new();
+        assert!(report.write_json(&mut short).is_err());
+    }
+}
@@ -0,0 +1,24 @@
+#![cfg_attr(not(test), no_std)]
+
+//! What the main board decides and encodes, apart from the hardware and the
+//! network: the lot and its sessions, the barrier states, the command port and
+//! HTTP parsing, the MQTT codec, the access policy, the tariff, what the barrier
+//! opens for and the flash formats of the event log and the reservations.
+//!
+//! Nothing here needs the board, so it builds for the host as well and is
+//! tested there.
+
+pub mod access;
+pub mod barrier;
+pub mod commands;
+pub mod event_log;
+pub mod gate;
+pub mod http;
+pub mod mqtt;
+pub mod occupancy;
+#[cfg(test)]
+mod ram_flash;
+pub mod reservations;
+pub mod sessions;
+pub mod settings;
+pub mod tariff;
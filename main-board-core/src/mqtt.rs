@@ -0,0 +1,501 @@
+//! Minimal MQTT 3.1.1 client codec.
+//!
+//! Only what the lot needs: CONNECT, PUBLISH with QoS 0 or 1, SUBSCRIBE and
+//! PINGREQ going out, and CONNACK, SUBACK, PUBLISH, PUBACK and PINGRESP coming
+//! in. Encoding and decoding don't touch the network, so they can be exercised
+//! on the host.
+//!
+//! The barrier commands arrive on the command topic as `open <key>`,
+//! `lock <key>` and `unlock <key>`, with the operator key.
+//!
+//! The state is published with QoS 1, [`Unacked`] tracks the packet IDs the
+//! broker hasn't acknowledged yet.
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+use crate::barrier::BarrierCommand;
+
+pub const MQTT_PORT: u16 = 1883;
+
+/// Largest packet sent or accepted, topics and payloads of the lot are short.
+pub const MAX_PACKET: usize = 256;
+
+/// Packet ID of the subscription, publishes use the others.
+pub const SUBSCRIBE_ID: u16 = 1;
+
+pub type Buffer = Vec<u8, MAX_PACKET>;
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum QoS {
+    AtMostOnce = 0,
+    AtLeastOnce = 1,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Packet<'a> {
+    /// Answer to CONNECT, `0` means accepted.
+    ConnAck { return_code: u8 },
+    Publish {
+        topic: &'a str,
+        payload: &'a [u8],
+        /// Set for QoS 1 and 2, the broker expects it acknowledged.
+        packet_id: Option<u16>,
+    },
+    PubAck { packet_id: u16 },
+    SubAck { packet_id: u16 },
+    PingResp,
+    /// Anything else the broker may send, it is skipped.
+    Other,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum DecodeError {
+    /// The packet isn't complete yet, read more.
+    Incomplete,
+    Malformed,
+    /// The packet doesn't fit [`MAX_PACKET`].
+    TooLarge,
+}
+
+pub fn connect(client_id: &str, keep_alive_secs: u16) -> Option<Buffer> {
+    let mut body = Buffer::new();
+    push_str(&mut body, "MQTT")?;
+    // Protocol level 4 (3.1.1), clean session
+    body.extend_from_slice(&[4, 0x02]).ok()?;
+    body.extend_from_slice(&keep_alive_secs.to_be_bytes()).ok()?;
+    push_str(&mut body, client_id)?;
+    finish(CONNECT << 4, &body)
+}
+
+/// `packet_id` is only sent for QoS 1.
+pub fn publish(topic: &str, payload: &[u8], qos: QoS, retain: bool, packet_id: u16) -> Option<Buffer> {
+    let mut body = Buffer::new();
+    push_str(&mut body, topic)?;
+    if qos == QoS::AtLeastOnce {
+        body.extend_from_slice(&packet_id.to_be_bytes()).ok()?;
+    }
+    body.extend_from_slice(payload).ok()?;
+    finish((PUBLISH << 4) | ((qos as u8) << 1) | retain as u8, &body)
+}
+
+pub fn subscribe(topic: &str, qos: QoS, packet_id: u16) -> Option<Buffer> {
+    let mut body = Buffer::new();
+    body.extend_from_slice(&packet_id.to_be_bytes()).ok()?;
+    push_str(&mut body, topic)?;
+    body.push(qos as u8).ok()?;
+    finish((SUBSCRIBE << 4) | 0x02, &body)
+}
+
+pub fn puback(packet_id: u16) -> Option<Buffer> {
+    finish(PUBACK << 4, &packet_id.to_be_bytes())
+}
+
+pub fn pingreq() -> Option<Buffer> {
+    finish(PINGREQ << 4, &[])
+}
+
+/// Decodes the first packet in `buf` and returns it with its length.
+pub fn decode(buf: &[u8]) -> Result<(Packet<'_>, usize), DecodeError> {
+    let (&header, rest) = buf.split_first().ok_or(DecodeError::Incomplete)?;
+
+    // Remaining length, 7 bits per byte, at most 4 bytes
+    let mut len = 0usize;
+    let mut len_bytes = 0;
+    loop {
+        let byte = *rest.get(len_bytes).ok_or(DecodeError::Incomplete)?;
+        len |= ((byte & 0x7f) as usize) << (7 * len_bytes);
+        len_bytes += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        if len_bytes == 4 {
+            return Err(DecodeError::Malformed);
+        }
+    }
+
+    let total = 1 + len_bytes + len;
+    if total > MAX_PACKET {
+        return Err(DecodeError::TooLarge);
+    }
+    let body = buf.get(1 + len_bytes..total).ok_or(DecodeError::Incomplete)?;
+
+    let packet = match header >> 4 {
+        CONNACK => Packet::ConnAck {
+            return_code: *body.get(1).ok_or(DecodeError::Malformed)?,
+        },
+        PUBLISH => {
+            let qos = (header >> 1) & 0x03;
+            let topic_len = u16_at(body, 0)? as usize;
+            let topic = body.get(2..2 + topic_len).ok_or(DecodeError::Malformed)?;
+            let topic = core::str::from_utf8(topic).map_err(|_| DecodeError::Malformed)?;
+            let mut offset = 2 + topic_len;
+            let packet_id = if qos > 0 {
+                offset += 2;
+                Some(u16_at(body, offset - 2)?)
+            } else {
+                None
+            };
+            Packet::Publish {
+                topic,
+                payload: body.get(offset..).ok_or(DecodeError::Malformed)?,
+                packet_id,
+            }
+        }
+        PUBACK => Packet::PubAck {
+            packet_id: u16_at(body, 0)?,
+        },
+        SUBACK => Packet::SubAck {
+            packet_id: u16_at(body, 0)?,
+        },
+        PINGRESP => Packet::PingResp,
+        _ => Packet::Other,
+    };
+
+    Ok((packet, total))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum PublishError {
+    /// `N` publishes wait for their PUBACK, wait for one first.
+    Full,
+    /// The packet doesn't fit [`MAX_PACKET`].
+    TooLarge,
+}
+
+/// Publishes sent with QoS 1 whose PUBACK hasn't arrived, at most `N`.
+///
+/// The session is clean, so a publish lost with its connection isn't sent
+/// again from here; the caller publishes the whole state again after a
+/// reconnect instead.
+pub struct Unacked<const N: usize> {
+    last_id: u16,
+    sent: Vec<(u16, Instant), N>,
+}
+
+impl<const N: usize> Unacked<N> {
+    pub const fn new() -> Self {
+        Self {
+            last_id: SUBSCRIBE_ID,
+            sent: Vec::new(),
+        }
+    }
+
+    /// Encodes a QoS 1 publish with a packet ID not in flight.
+    pub fn publish(&mut self, topic: &str, payload: &[u8], retain: bool, now: Instant) -> Result<Buffer, PublishError> {
+        if self.sent.is_full() {
+            return Err(PublishError::Full);
+        }
+        let mut id = self.last_id;
+        loop {
+            id = id.wrapping_add(1);
+            if id != 0 && id != SUBSCRIBE_ID && !self.sent.iter().any(|(sent, _)| *sent == id) {
+                break;
+            }
+        }
+
+        let packet = publish(topic, payload, QoS::AtLeastOnce, retain, id).ok_or(PublishError::TooLarge)?;
+        self.last_id = id;
+        let _ = self.sent.push((id, now));
+        Ok(packet)
+    }
+
+    /// Takes the PUBACK for `packet_id`, returns whether it was in flight.
+    pub fn acked(&mut self, packet_id: u16) -> bool {
+        match self.sent.iter().position(|(id, _)| *id == packet_id) {
+            Some(i) => {
+                self.sent.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether a publish has waited longer than `timeout` for its PUBACK.
+    pub fn overdue(&self, now: Instant, timeout: Duration) -> bool {
+        self.sent.iter().any(|(_, at)| now.saturating_duration_since(*at) > timeout)
+    }
+
+    pub fn len(&self) -> usize {
+        self.sent.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sent.is_empty()
+    }
+}
+
+impl<const N: usize> Default for Unacked<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Barrier command and operator key of a message on the command topic.
+pub fn parse_command(payload: &[u8]) -> Option<(BarrierCommand, &str)> {
+    let payload = core::str::from_utf8(payload).ok()?;
+    let (command, key) = payload.trim().split_once(' ')?;
+    let command = match command {
+        "open" => BarrierCommand::Open,
+        "lock" => BarrierCommand::Lock,
+        "unlock" => BarrierCommand::Unlock,
+        _ => return None,
+    };
+    Some((command, key.trim()))
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> Result<u16, DecodeError> {
+    let b = bytes.get(offset..offset + 2).ok_or(DecodeError::Malformed)?;
+    Ok(u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn push_str(out: &mut Buffer, s: &str) -> Option<()> {
+    out.extend_from_slice(&(s.len() as u16).to_be_bytes()).ok()?;
+    out.extend_from_slice(s.as_bytes()).ok()
+}
+
+/// Prepends the fixed header to `body`.
+fn finish(header: u8, body: &[u8]) -> Option<Buffer> {
+    let mut out = Buffer::new();
+    out.push(header).ok()?;
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte).ok()?;
+        if len == 0 {
+            break;
+        }
+    }
+    out.extend_from_slice(body).ok()?;
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connect() {
+        let packet = super::connect("parking-lot1", 60).unwrap();
+        let mut expected = vec![0x10, 24, 0, 4, b'M', b'Q', b'T', b'T', 4, 0x02, 0, 60, 0, 12];
+        expected.extend_from_slice(b"parking-lot1");
+        assert_eq!(packet.as_slice(), expected.as_slice());
+    }
+
+    #[test]
+    fn publish_at_most_once() {
+        let packet = publish("a/b", b"free", QoS::AtMostOnce, true, 7).unwrap();
+        assert_eq!(packet.as_slice(), b"\x31\x09\x00\x03a/bfree");
+    }
+
+    #[test]
+    fn publish_at_least_once() {
+        let packet = publish("a/b", b"free", QoS::AtLeastOnce, false, 0x0102).unwrap();
+        assert_eq!(packet.as_slice(), b"\x32\x0b\x00\x03a/b\x01\x02free");
+    }
+
+    #[test]
+    fn subscribe() {
+        let packet = super::subscribe("parking/lot1/command", QoS::AtLeastOnce, 1).unwrap();
+        let mut expected = vec![0x82, 25, 0, 1, 0, 20];
+        expected.extend_from_slice(b"parking/lot1/command");
+        expected.push(1);
+        assert_eq!(packet.as_slice(), expected.as_slice());
+    }
+
+    #[test]
+    fn small_packets() {
+        assert_eq!(puback(0x1234).unwrap().as_slice(), [0x40, 2, 0x12, 0x34]);
+        assert_eq!(pingreq().unwrap().as_slice(), [0xc0, 0]);
+    }
+
+    #[test]
+    fn remaining_length_takes_two_bytes_from_128() {
+        let payload = [b'x'; 200];
+        let packet = publish("t", &payload, QoS::AtMostOnce, false, 0).unwrap();
+        // 3 bytes of topic and 200 of payload, 203 = 0x4b + 1 * 128
+        assert_eq!(packet[..3], [0x30, 0xcb, 0x01]);
+        assert_eq!(packet.len(), 3 + 203);
+
+        let (decoded, len) = decode(&packet).unwrap();
+        assert_eq!(len, packet.len());
+        assert_eq!(
+            decoded,
+            Packet::Publish {
+                topic: "t",
+                payload: &payload,
+                packet_id: None
+            }
+        );
+
+        // Exactly 127 still fits one byte
+        let packet = publish("t", &payload[..124], QoS::AtMostOnce, false, 0).unwrap();
+        assert_eq!(packet[..2], [0x30, 127]);
+    }
+
+    #[test]
+    fn too_large_to_encode() {
+        assert_eq!(publish("t", &[0; MAX_PACKET], QoS::AtMostOnce, false, 0), None);
+    }
+
+    #[test]
+    fn decode_acks() {
+        assert_eq!(decode(&[0x20, 2, 0, 0]), Ok((Packet::ConnAck { return_code: 0 }, 4)));
+        assert_eq!(decode(&[0x20, 2, 0, 5]), Ok((Packet::ConnAck { return_code: 5 }, 4)));
+        assert_eq!(decode(&[0x40, 2, 0, 9]), Ok((Packet::PubAck { packet_id: 9 }, 4)));
+        assert_eq!(decode(&[0x90, 3, 0, 1, 1]), Ok((Packet::SubAck { packet_id: 1 }, 5)));
+        assert_eq!(decode(&[0xd0, 0]), Ok((Packet::PingResp, 2)));
+        assert_eq!(decode(&[0xe0, 0]), Ok((Packet::Other, 2)));
+    }
+
+    #[test]
+    fn decode_publish_with_packet_id() {
+        let bytes = b"\x32\x0b\x00\x03a/b\x00\x2aopen!";
+        assert_eq!(
+            decode(bytes),
+            Ok((
+                Packet::Publish {
+                    topic: "a/b",
+                    payload: b"open",
+                    packet_id: Some(42)
+                },
+                13
+            ))
+        );
+    }
+
+    #[test]
+    fn decode_incomplete_and_bad() {
+        assert_eq!(decode(&[]), Err(DecodeError::Incomplete));
+        assert_eq!(decode(&[0x30]), Err(DecodeError::Incomplete));
+        assert_eq!(decode(&[0x30, 0x80]), Err(DecodeError::Incomplete));
+        assert_eq!(decode(&[0x30, 5, 0, 3, b'a']), Err(DecodeError::Incomplete));
+        assert_eq!(decode(&[0x30, 0x80, 0x80, 0x80, 0x80, 1]), Err(DecodeError::Malformed));
+        assert_eq!(decode(&[0x30, 0x80, 0x02]), Err(DecodeError::TooLarge));
+        // The topic runs past the packet
+        assert_eq!(decode(&[0x30, 3, 0, 9, b'a']), Err(DecodeError::Malformed));
+        assert_eq!(decode(&[0x30, 4, 0, 2, 0xff, 0xfe]), Err(DecodeError::Malformed));
+    }
+
+    #[test]
+    fn unacked_publishes() {
+        let at = Instant::from_secs(100);
+        let mut unacked = Unacked::<2>::new();
+        let first = unacked.publish("a/b", b"free", true, at).unwrap();
+        assert_eq!(first.as_slice(), b"\x33\x0b\x00\x03a/b\x00\x02free");
+        let second = unacked.publish("a/b", b"occupied", true, at).unwrap();
+        assert_eq!(second[7..9], [0, 3]);
+        assert_eq!(unacked.publish("a/b", b"free", true, at), Err(PublishError::Full));
+
+        assert!(!unacked.overdue(at + Duration::from_secs(10), Duration::from_secs(10)));
+        assert!(unacked.overdue(at + Duration::from_secs(11), Duration::from_secs(10)));
+        assert!(unacked.acked(2));
+        assert!(!unacked.acked(2));
+        assert!(!unacked.acked(9));
+        assert_eq!(unacked.len(), 1);
+
+        let third = unacked.publish("a/b", b"free", true, at).unwrap();
+        assert_eq!(third[7..9], [0, 4]);
+        assert!(unacked.acked(3));
+        assert_eq!(unacked.publish("t", &[0; MAX_PACKET], true, at), Err(PublishError::TooLarge));
+        assert!(unacked.acked(4) && unacked.is_empty());
+    }
+
+    #[test]
+    fn packet_ids_skip_zero_the_subscription_and_those_in_flight() {
+        let at = Instant::from_secs(0);
+        let mut unacked = Unacked::<2>::new();
+        unacked.last_id = u16::MAX - 1;
+        let packet = unacked.publish("t", b"", false, at).unwrap();
+        assert_eq!(packet[5..7], [0xff, 0xff]);
+        // Wraps past 0 and the subscription
+        let packet = unacked.publish("t", b"", false, at).unwrap();
+        assert_eq!(packet[5..7], [0, 2]);
+
+        assert!(unacked.acked(0xffff));
+        unacked.last_id = 1;
+        let packet = unacked.publish("t", b"", false, at).unwrap();
+        assert_eq!(packet[5..7], [0, 3]);
+    }
+
+    /// Publishes with QoS 1 to the broker at `MQTT_TEST_BROKER`, like
+    /// `127.0.0.1:1883` for a local mosquitto, and checks the PUBACK and the
+    /// message coming back. Skipped without it.
+    #[test]
+    fn publish_to_broker() {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+        use std::time::Duration as StdDuration;
+
+        let Ok(broker) = std::env::var("MQTT_TEST_BROKER") else {
+            return;
+        };
+        let mut stream = TcpStream::connect(&broker).unwrap();
+        stream.set_read_timeout(Some(StdDuration::from_secs(5))).unwrap();
+        let topic = std::format!("parking/test-{}/spot/1", std::process::id());
+
+        // Reads the next packet as its type, packet ID and payload
+        let mut rx = std::vec::Vec::new();
+        let mut next = |stream: &mut TcpStream| -> (u8, Option<u16>, std::vec::Vec<u8>) {
+            loop {
+                if let Ok((packet, n)) = decode(&rx) {
+                    let kind = rx[0] >> 4;
+                    let packet = match packet {
+                        Packet::Publish { payload, packet_id, .. } => (kind, packet_id, payload.to_vec()),
+                        Packet::PubAck { packet_id } | Packet::SubAck { packet_id } => (kind, Some(packet_id), std::vec![]),
+                        Packet::ConnAck { return_code } => (kind, None, std::vec![return_code]),
+                        _ => (kind, None, std::vec![]),
+                    };
+                    rx.drain(..n);
+                    return packet;
+                }
+                let mut buf = [0; 256];
+                let n = stream.read(&mut buf).unwrap();
+                assert!(n > 0, "broker closed the connection");
+                rx.extend_from_slice(&buf[..n]);
+            }
+        };
+
+        stream.write_all(&super::connect("parking-test", 60).unwrap()).unwrap();
+        assert_eq!(next(&mut stream), (CONNACK, None, std::vec![0]));
+        stream.write_all(&super::subscribe(&topic, QoS::AtLeastOnce, SUBSCRIBE_ID).unwrap()).unwrap();
+        assert_eq!(next(&mut stream), (SUBACK, Some(SUBSCRIBE_ID), std::vec![]));
+
+        let mut unacked = Unacked::<4>::new();
+        stream.write_all(&unacked.publish(&topic, b"occupied", false, Instant::from_secs(0)).unwrap()).unwrap();
+        // The PUBACK and our own message may come in either order
+        let mut got = [next(&mut stream), next(&mut stream)];
+        got.sort();
+        let (kind, delivered_id, payload) = got[0].clone();
+        assert_eq!((kind, payload.as_slice()), (PUBLISH, &b"occupied"[..]));
+        assert_eq!(got[1], (PUBACK, Some(2), std::vec![]));
+        assert!(unacked.acked(2) && unacked.is_empty());
+
+        // Delivered with QoS 1 too
+        let delivered_id = delivered_id.unwrap();
+        stream.write_all(&puback(delivered_id).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(parse_command(b"open secret"), Some((BarrierCommand::Open, "secret")));
+        assert_eq!(parse_command(b"lock secret\n"), Some((BarrierCommand::Lock, "secret")));
+        assert_eq!(parse_command(b"unlock  secret"), Some((BarrierCommand::Unlock, "secret")));
+        assert_eq!(parse_command(b"open"), None);
+        assert_eq!(parse_command(b"toggle secret"), None);
+        assert_eq!(parse_command(b"open \xff"), None);
+    }
+}
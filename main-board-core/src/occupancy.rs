@@ -0,0 +1,279 @@
+//! Central occupancy model for the parking lot.
+//!
+//! The spot sensors tell us which spots are taken and the barrier tells us when
+//! a car passed the gate. This module combines both sources into one
+//! authoritative view of the lot and flags the cases where they disagree, for
+//! example a car that entered but never took a spot.
+//!
+//! The gate has no direction sensor, so a passage is matched against recent
+//! spot changes: a car leaving frees its spot before opening the barrier, so a
+//! passage that follows a freed spot is an exit and any other passage is an
+//! entry.
+
+use embassy_time::{Duration, Instant};
+use heapless::{Deque, String};
+
+/// Number of parking spots handled by the main board.
+pub const SPOT_COUNT: usize = 4;
+
+/// How long a car may take between the gate and a spot (in either direction)
+/// before the mismatch is reported.
+pub const PARKING_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Maximum number of cars tracked between the gate and a spot.
+const MAX_PENDING: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum SpotState {
+    Free,
+    Occupied,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Inconsistency {
+    /// A car entered through the gate but no spot was taken in time.
+    EntryWithoutSpot,
+    /// A spot was freed but no car left through the gate in time.
+    DepartureWithoutExit,
+    /// A spot became occupied without a matching gate entry.
+    SpotWithoutEntry(u8),
+}
+
+/// Authoritative state of the lot, published to the display instead of deltas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Snapshot {
+    pub spots: [SpotState; SPOT_COUNT],
+    /// Spots held by a reservation right now.
+    pub reserved: [bool; SPOT_COUNT],
+}
+
+impl Snapshot {
+    /// Marks the spots held by a reservation.
+    pub fn with_reserved(self, reserved: [bool; SPOT_COUNT]) -> Self {
+        Self { reserved, ..self }
+    }
+
+    /// Whether spot `index` (0-based) is free and not held for anybody.
+    pub fn is_available(&self, index: usize) -> bool {
+        self.spots[index] == SpotState::Free && !self.reserved[index]
+    }
+
+    /// Counts the spots that can be offered, reserved spots aren't.
+    pub fn free_spaces(&self) -> usize {
+        (0..SPOT_COUNT).filter(|i| self.is_available(*i)).count()
+    }
+
+    /// Encodes the snapshot as `Lot: <states>`, with one `1` (occupied), `0`
+    /// (free) or `R` (free but reserved) per spot in spot order, e.g.
+    /// `Lot: 01R0`.
+    pub fn encode(&self) -> String<32> {
+        let mut out = String::new();
+        let _ = out.push_str("Lot: ");
+        for (spot, reserved) in self.spots.iter().zip(self.reserved) {
+            let _ = out.push(match (spot, reserved) {
+                (SpotState::Occupied, _) => '1',
+                (SpotState::Free, true) => 'R',
+                (SpotState::Free, false) => '0',
+            });
+        }
+        out
+    }
+}
+
+pub struct Occupancy {
+    /// Last reading of every spot, `None` until its sensor reported once.
+    spots: [Option<SpotState>; SPOT_COUNT],
+    /// Gate passages that are waiting for a spot to be taken.
+    pending_entries: Deque<Instant, MAX_PENDING>,
+    /// Freed spots that are waiting for a gate passage.
+    pending_departures: Deque<Instant, MAX_PENDING>,
+}
+
+impl Occupancy {
+    pub const fn new() -> Self {
+        Self {
+            spots: [None; SPOT_COUNT],
+            pending_entries: Deque::new(),
+            pending_departures: Deque::new(),
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            spots: self.spots.map(|s| s.unwrap_or(SpotState::Free)),
+            reserved: [false; SPOT_COUNT],
+        }
+    }
+
+    /// Records the latest reading of spot `spot_no` (1-based).
+    ///
+    /// Returns `Ok(true)` if the state changed, or the inconsistency found
+    /// while matching the change against the gate events. The first reading of
+    /// a spot is taken as is, since cars parked before boot never passed the
+    /// gate while we were watching.
+    pub fn update_spot(
+        &mut self,
+        spot_no: u8,
+        state: SpotState,
+        now: Instant,
+    ) -> Result<bool, Inconsistency> {
+        let index = match (spot_no as usize).checked_sub(1) {
+            Some(index) if index < SPOT_COUNT => index,
+            _ => return Ok(false),
+        };
+
+        let previous = self.spots[index].replace(state);
+        if previous == Some(state) {
+            return Ok(false);
+        }
+        if previous.is_none() {
+            return Ok(true);
+        }
+
+        match state {
+            SpotState::Occupied => {
+                if self.pending_entries.pop_front().is_none() {
+                    return Err(Inconsistency::SpotWithoutEntry(spot_no));
+                }
+            }
+            SpotState::Free => push_bounded(&mut self.pending_departures, now),
+        }
+
+        Ok(true)
+    }
+
+    /// Records a car passing the open barrier.
+    pub fn record_gate_passage(&mut self, now: Instant) {
+        if self.pending_departures.pop_front().is_none() {
+            push_bounded(&mut self.pending_entries, now);
+        }
+    }
+
+    /// Drops the gate and spot events that waited longer than
+    /// [`PARKING_TIMEOUT`] for their counterpart and reports the first one.
+    pub fn check(&mut self, now: Instant) -> Option<Inconsistency> {
+        if expire(&mut self.pending_entries, now) {
+            return Some(Inconsistency::EntryWithoutSpot);
+        }
+        if expire(&mut self.pending_departures, now) {
+            return Some(Inconsistency::DepartureWithoutExit);
+        }
+        None
+    }
+}
+
+/// Queues an event, forgetting the oldest one if the queue is full.
+fn push_bounded(queue: &mut Deque<Instant, MAX_PENDING>, at: Instant) {
+    if queue.is_full() {
+        queue.pop_front();
+    }
+    let _ = queue.push_back(at);
+}
+
+/// Removes the oldest event if it is older than [`PARKING_TIMEOUT`].
+fn expire(queue: &mut Deque<Instant, MAX_PENDING>, now: Instant) -> bool {
+    match queue.front() {
+        Some(at) if now.saturating_duration_since(*at) > PARKING_TIMEOUT => {
+            queue.pop_front();
+            true
+        }
+        _ => false,
+    }
+}
+
+impl Default for Occupancy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> Instant {
+        Instant::from_secs(secs)
+    }
+
+    /// A lot whose sensors all reported, spot 1 occupied.
+    fn lot() -> Occupancy {
+        let mut lot = Occupancy::new();
+        for spot in 1..=SPOT_COUNT as u8 {
+            let state = if spot == 1 { SpotState::Occupied } else { SpotState::Free };
+            assert_eq!(lot.update_spot(spot, state, at(0)), Ok(true));
+        }
+        lot
+    }
+
+    #[test]
+    fn first_readings_are_taken_as_is() {
+        let lot = lot();
+        assert_eq!(lot.snapshot().spots, [SpotState::Occupied, SpotState::Free, SpotState::Free, SpotState::Free]);
+        assert_eq!(lot.snapshot().free_spaces(), 3);
+    }
+
+    #[test]
+    fn entry() {
+        let mut lot = lot();
+        lot.record_gate_passage(at(10));
+        assert_eq!(lot.update_spot(2, SpotState::Occupied, at(40)), Ok(true));
+        assert_eq!(lot.update_spot(2, SpotState::Occupied, at(41)), Ok(false));
+        assert_eq!(lot.check(at(10 + 3600)), None);
+        assert_eq!(lot.snapshot().encode(), "Lot: 1100");
+    }
+
+    #[test]
+    fn exit() {
+        let mut lot = lot();
+        assert_eq!(lot.update_spot(1, SpotState::Free, at(10)), Ok(true));
+        lot.record_gate_passage(at(40));
+        assert_eq!(lot.check(at(10 + 3600)), None);
+        // The passage was the exit, not an entry waiting for a spot
+        assert_eq!(lot.update_spot(3, SpotState::Occupied, at(3700)), Err(Inconsistency::SpotWithoutEntry(3)));
+    }
+
+    #[test]
+    fn spot_freed_without_a_gate_passage() {
+        let mut lot = lot();
+        assert_eq!(lot.update_spot(1, SpotState::Free, at(10)), Ok(true));
+        assert_eq!(lot.check(at(10) + PARKING_TIMEOUT), None);
+        assert_eq!(lot.check(at(11) + PARKING_TIMEOUT), Some(Inconsistency::DepartureWithoutExit));
+        assert_eq!(lot.check(at(12) + PARKING_TIMEOUT), None);
+    }
+
+    #[test]
+    fn gate_passage_without_a_spot_change() {
+        let mut lot = lot();
+        lot.record_gate_passage(at(10));
+        assert_eq!(lot.check(at(10) + PARKING_TIMEOUT), None);
+        assert_eq!(lot.check(at(11) + PARKING_TIMEOUT), Some(Inconsistency::EntryWithoutSpot));
+        assert_eq!(lot.check(at(12) + PARKING_TIMEOUT), None);
+    }
+
+    #[test]
+    fn spot_taken_without_an_entry() {
+        let mut lot = lot();
+        assert_eq!(lot.update_spot(4, SpotState::Occupied, at(10)), Err(Inconsistency::SpotWithoutEntry(4)));
+        // The reading still counts
+        assert_eq!(lot.snapshot().encode(), "Lot: 1001");
+    }
+
+    #[test]
+    fn unknown_spots_are_ignored() {
+        let mut lot = lot();
+        assert_eq!(lot.update_spot(0, SpotState::Occupied, at(10)), Ok(false));
+        assert_eq!(lot.update_spot(SPOT_COUNT as u8 + 1, SpotState::Occupied, at(10)), Ok(false));
+        assert_eq!(lot.snapshot(), self::lot().snapshot());
+    }
+
+    #[test]
+    fn reserved_spots_arent_offered() {
+        let snapshot = lot().snapshot().with_reserved([false, true, false, false]);
+        assert!(!snapshot.is_available(0));
+        assert!(!snapshot.is_available(1));
+        assert!(snapshot.is_available(2));
+        assert_eq!(snapshot.free_spaces(), 2);
+        assert_eq!(snapshot.encode(), "Lot: 1R00");
+    }
+}
@@ -0,0 +1,67 @@
+//! NOR flash in RAM for the tests of the flash formats.
+
+use embedded_storage::nor_flash::{ErrorType, NorFlash, NorFlashErrorKind, ReadNorFlash};
+
+/// Writes only clear bits, like NOR flash, and counts the erases.
+pub struct RamFlash<const SIZE: usize, const ERASE: usize> {
+    pub bytes: [u8; SIZE],
+    pub erases: usize,
+}
+
+impl<const SIZE: usize, const ERASE: usize> RamFlash<SIZE, ERASE> {
+    pub fn new() -> Self {
+        Self {
+            bytes: [0xff; SIZE],
+            erases: 0,
+        }
+    }
+
+    fn range(&self, offset: u32, len: usize) -> Result<core::ops::Range<usize>, NorFlashErrorKind> {
+        let start = offset as usize;
+        match start.checked_add(len) {
+            Some(end) if end <= SIZE => Ok(start..end),
+            _ => Err(NorFlashErrorKind::OutOfBounds),
+        }
+    }
+}
+
+impl<const SIZE: usize, const ERASE: usize> ErrorType for RamFlash<SIZE, ERASE> {
+    type Error = NorFlashErrorKind;
+}
+
+impl<const SIZE: usize, const ERASE: usize> ReadNorFlash for RamFlash<SIZE, ERASE> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let range = self.range(offset, bytes.len())?;
+        bytes.copy_from_slice(&self.bytes[range]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        SIZE
+    }
+}
+
+impl<const SIZE: usize, const ERASE: usize> NorFlash for RamFlash<SIZE, ERASE> {
+    const WRITE_SIZE: usize = 1;
+    const ERASE_SIZE: usize = ERASE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if from as usize % ERASE != 0 || to as usize % ERASE != 0 {
+            return Err(NorFlashErrorKind::NotAligned);
+        }
+        let range = self.range(from, to.saturating_sub(from) as usize)?;
+        self.bytes[range].fill(0xff);
+        self.erases += 1;
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let range = self.range(offset, bytes.len())?;
+        for (stored, new) in self.bytes[range].iter_mut().zip(bytes) {
+            *stored &= new;
+        }
+        Ok(())
+    }
+}
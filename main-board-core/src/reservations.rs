@@ -0,0 +1,386 @@
+//! Spot reservations and their copy in flash.
+//!
+//! A reservation holds one spot for a plate or remote ID between two UTC
+//! times, so the lot needs a synced clock to take and enforce them. A reserved
+//! spot that is still free isn't offered to anybody else, and once the lot has
+//! no other spot left only the holder's remote gets in. A reservation is only
+//! taken while the lot has a spot to offer.
+//!
+//! The whole list is kept in one of two flash sectors, written alternately with
+//! a rising generation, so a power loss during a save leaves the previous list
+//! readable. Like the event log this is generic over the flash and can be
+//! exercised on the host.
+
+use core::fmt::{self, Write};
+
+use embedded_storage::nor_flash::NorFlash;
+use heapless::{String, Vec};
+
+use crate::event_log::checksum;
+use crate::occupancy::{SPOT_COUNT, Snapshot, SpotState};
+
+/// Most reservations held at once, over all spots.
+pub const MAX_RESERVATIONS: usize = 8;
+
+/// Longest plate or remote ID.
+pub const MAX_HOLDER: usize = 16;
+
+pub type Holder = String<MAX_HOLDER>;
+
+/// Marks a sector holding a reservation list, `RSV1`.
+const MAGIC: u32 = 0x3156_5352;
+
+/// Spot, holder length, holder, start and end.
+const ENTRY_SIZE: usize = 2 + MAX_HOLDER + 8 + 8;
+
+/// Magic, generation, count, the entries and the checksum.
+const BLOCK_SIZE: usize = 4 + 4 + 1 + MAX_RESERVATIONS * ENTRY_SIZE + 2;
+
+/// The block padded to whole flash pages.
+const PADDED_SIZE: usize = BLOCK_SIZE.next_multiple_of(256);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Reservation {
+    /// 1-based spot number.
+    pub spot: u8,
+    /// Plate or remote ID the spot is held for.
+    pub holder: Holder,
+    /// Start of the window, UTC seconds since the Unix epoch.
+    pub start: u64,
+    /// End of the window, exclusive.
+    pub end: u64,
+}
+
+impl Reservation {
+    pub fn is_active(&self, now: u64) -> bool {
+        (self.start..self.end).contains(&now)
+    }
+
+    /// One JSON object, `now` marks whether the window is running.
+    pub fn write_json(&self, out: &mut impl Write, now: Option<u64>) -> fmt::Result {
+        write!(
+            out,
+            "{{\"spot\":{},\"holder\":\"{}\",\"start_utc\":{},\"end_utc\":{},\"active\":{}}}",
+            self.spot,
+            self.holder,
+            self.start,
+            self.end,
+            now.is_some_and(|now| self.is_active(now))
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ReservationError {
+    NoSuchSpot,
+    /// The holder is empty, too long or has characters other than letters,
+    /// digits and `-`.
+    BadHolder,
+    /// The window is empty or already over.
+    BadWindow,
+    /// The spot is already reserved for part of the window.
+    Overlap,
+    Full,
+    /// No spot of the lot is free and unreserved.
+    LotFull,
+    /// The window has started and the spot is taken.
+    Occupied,
+    /// The time isn't known yet.
+    Unsynced,
+}
+
+impl ReservationError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReservationError::NoSuchSpot => "no such spot",
+            ReservationError::BadHolder => "bad holder",
+            ReservationError::BadWindow => "bad window",
+            ReservationError::Overlap => "overlaps another reservation",
+            ReservationError::Full => "too many reservations",
+            ReservationError::LotFull => "no spot free",
+            ReservationError::Occupied => "spot taken",
+            ReservationError::Unsynced => "clock unsynced",
+        }
+    }
+}
+
+/// Whether `holder` can be a plate or remote ID.
+pub fn valid_holder(holder: &str) -> bool {
+    !holder.is_empty()
+        && holder.len() <= MAX_HOLDER
+        && holder.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Reservations {
+    list: Vec<Reservation, MAX_RESERVATIONS>,
+}
+
+impl Reservations {
+    pub const fn new() -> Self {
+        Self { list: Vec::new() }
+    }
+
+    /// Adds a reservation, `now` is the current UTC time in seconds and `lot`
+    /// the spots as they are now.
+    pub fn add(&mut self, reservation: Reservation, now: u64, lot: &Snapshot) -> Result<(), ReservationError> {
+        if !(1..=SPOT_COUNT as u8).contains(&reservation.spot) {
+            return Err(ReservationError::NoSuchSpot);
+        }
+        if !valid_holder(&reservation.holder) {
+            return Err(ReservationError::BadHolder);
+        }
+        if reservation.start >= reservation.end || reservation.end <= now {
+            return Err(ReservationError::BadWindow);
+        }
+        let overlaps = self.list.iter().any(|r| {
+            r.spot == reservation.spot && r.start < reservation.end && reservation.start < r.end
+        });
+        if overlaps {
+            return Err(ReservationError::Overlap);
+        }
+        if lot.free_spaces() == 0 {
+            return Err(ReservationError::LotFull);
+        }
+        if reservation.is_active(now) && lot.spots[reservation.spot as usize - 1] != SpotState::Free {
+            return Err(ReservationError::Occupied);
+        }
+
+        self.list.push(reservation).map_err(|_| ReservationError::Full)
+    }
+
+    /// Drops every reservation of spot `spot`, returns how many there were.
+    pub fn cancel(&mut self, spot: u8) -> usize {
+        let before = self.list.len();
+        self.list.retain(|r| r.spot != spot);
+        before - self.list.len()
+    }
+
+    /// Drops the reservations whose window is over, returns whether there
+    /// were any.
+    pub fn expire(&mut self, now: u64) -> bool {
+        let before = self.list.len();
+        self.list.retain(|r| r.end > now);
+        self.list.len() != before
+    }
+
+    /// Which spots are held right now, in spot order.
+    pub fn reserved(&self, now: u64) -> [bool; SPOT_COUNT] {
+        let mut reserved = [false; SPOT_COUNT];
+        for r in self.active(now) {
+            if let Some(spot) = reserved.get_mut((r.spot as usize).wrapping_sub(1)) {
+                *spot = true;
+            }
+        }
+        reserved
+    }
+
+    /// The reservations whose window is running.
+    pub fn active(&self, now: u64) -> impl Iterator<Item = &Reservation> {
+        self.list.iter().filter(move |r| r.is_active(now))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Reservation> {
+        self.list.iter()
+    }
+
+    fn encode(&self, generation: u32) -> [u8; BLOCK_SIZE] {
+        let mut out = [0; BLOCK_SIZE];
+        out[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        out[4..8].copy_from_slice(&generation.to_le_bytes());
+        out[8] = self.list.len() as u8;
+        for (r, entry) in self.list.iter().zip(out[9..].chunks_exact_mut(ENTRY_SIZE)) {
+            entry[0] = r.spot;
+            entry[1] = r.holder.len() as u8;
+            entry[2..2 + r.holder.len()].copy_from_slice(r.holder.as_bytes());
+            entry[2 + MAX_HOLDER..10 + MAX_HOLDER].copy_from_slice(&r.start.to_le_bytes());
+            entry[10 + MAX_HOLDER..18 + MAX_HOLDER].copy_from_slice(&r.end.to_le_bytes());
+        }
+        let check = checksum(&out[..BLOCK_SIZE - 2]);
+        out[BLOCK_SIZE - 2..].copy_from_slice(&check.to_le_bytes());
+        out
+    }
+
+    /// Returns the list and its generation, `None` for an erased or torn
+    /// sector.
+    fn decode(bytes: &[u8; BLOCK_SIZE]) -> Option<(Self, u32)> {
+        if bytes[0..4] != MAGIC.to_le_bytes()
+            || checksum(&bytes[..BLOCK_SIZE - 2]).to_le_bytes() != bytes[BLOCK_SIZE - 2..]
+        {
+            return None;
+        }
+        let generation = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        let count = bytes[8] as usize;
+        if count > MAX_RESERVATIONS {
+            return None;
+        }
+
+        let mut list = Vec::new();
+        for entry in bytes[9..].chunks_exact(ENTRY_SIZE).take(count) {
+            let holder = entry.get(2..2 + entry[1] as usize)?;
+            let holder = core::str::from_utf8(holder).ok()?;
+            let mut start = [0; 8];
+            let mut end = [0; 8];
+            start.copy_from_slice(&entry[2 + MAX_HOLDER..10 + MAX_HOLDER]);
+            end.copy_from_slice(&entry[10 + MAX_HOLDER..18 + MAX_HOLDER]);
+            let _ = list.push(Reservation {
+                spot: entry[0],
+                holder: Holder::try_from(holder).ok()?,
+                start: u64::from_le_bytes(start),
+                end: u64::from_le_bytes(end),
+            });
+        }
+
+        Some((Self { list }, generation))
+    }
+}
+
+/// The reservation list in two flash sectors.
+pub struct ReservationStore<F: NorFlash> {
+    flash: F,
+    /// Start of the two sectors.
+    base: u32,
+    /// Generation of the newest copy, which sector holds it follows from it.
+    generation: u32,
+}
+
+impl<F: NorFlash> ReservationStore<F> {
+    /// Size of the flash region the store needs.
+    pub const SIZE: u32 = 2 * F::ERASE_SIZE as u32;
+
+    /// Reads the newest list from the [`Self::SIZE`] bytes at `base`, empty
+    /// if none was saved yet.
+    pub fn load(mut flash: F, base: u32) -> Result<(Self, Reservations), F::Error> {
+        let mut newest: Option<(Reservations, u32)> = None;
+        for sector in 0..2 {
+            let mut bytes = [0; BLOCK_SIZE];
+            flash.read(base + sector * F::ERASE_SIZE as u32, &mut bytes)?;
+            if let Some((list, generation)) = Reservations::decode(&bytes) {
+                if newest.as_ref().is_none_or(|(_, g)| generation > *g) {
+                    newest = Some((list, generation));
+                }
+            }
+        }
+
+        let (list, generation) = newest.unwrap_or_default();
+        Ok((Self { flash, base, generation }, list))
+    }
+
+    /// Writes `list` over the older copy.
+    pub fn save(&mut self, list: &Reservations) -> Result<(), F::Error> {
+        let generation = self.generation.wrapping_add(1);
+        let sector = self.base + (generation % 2) * F::ERASE_SIZE as u32;
+
+        let mut block = [0xff; PADDED_SIZE];
+        block[..BLOCK_SIZE].copy_from_slice(&list.encode(generation));
+
+        self.flash.erase(sector, sector + F::ERASE_SIZE as u32)?;
+        self.flash.write(sector, &block)?;
+        self.generation = generation;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ram_flash::RamFlash;
+
+    type SmallFlash = RamFlash<2048, 1024>;
+
+    const FREE: Snapshot = Snapshot {
+        spots: [SpotState::Free; SPOT_COUNT],
+        reserved: [false; SPOT_COUNT],
+    };
+
+    fn reservation(spot: u8, holder: &str, start: u64, end: u64) -> Reservation {
+        Reservation {
+            spot,
+            holder: Holder::try_from(holder).unwrap(),
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn checks_what_it_takes() {
+        let mut list = Reservations::new();
+        assert_eq!(list.add(reservation(0, "ab-123", 100, 200), 50, &FREE), Err(ReservationError::NoSuchSpot));
+        assert_eq!(list.add(reservation(1, "ab 123", 100, 200), 50, &FREE), Err(ReservationError::BadHolder));
+        assert_eq!(list.add(reservation(1, "ab-123", 200, 100), 50, &FREE), Err(ReservationError::BadWindow));
+        assert_eq!(list.add(reservation(1, "ab-123", 100, 200), 200, &FREE), Err(ReservationError::BadWindow));
+
+        assert_eq!(list.add(reservation(1, "ab-123", 100, 200), 50, &FREE), Ok(()));
+        assert_eq!(list.add(reservation(1, "ir-1a", 150, 250), 50, &FREE), Err(ReservationError::Overlap));
+        assert_eq!(list.add(reservation(1, "ir-1a", 200, 250), 50, &FREE), Ok(()));
+    }
+
+    #[test]
+    fn needs_a_free_spot() {
+        let mut list = Reservations::new();
+        let mut lot = FREE;
+        lot.spots = [SpotState::Occupied; SPOT_COUNT];
+        assert_eq!(list.add(reservation(1, "ab-123", 100, 200), 50, &lot), Err(ReservationError::LotFull));
+
+        // The one free spot is held for somebody else
+        lot.spots[2] = SpotState::Free;
+        lot.reserved[2] = true;
+        assert_eq!(list.add(reservation(1, "ab-123", 100, 200), 50, &lot), Err(ReservationError::LotFull));
+
+        // An occupied spot can be reserved for later, not right away
+        lot.reserved[2] = false;
+        assert_eq!(list.add(reservation(1, "ab-123", 50, 200), 50, &lot), Err(ReservationError::Occupied));
+        assert_eq!(list.add(reservation(1, "ab-123", 100, 200), 50, &lot), Ok(()));
+        assert_eq!(list.add(reservation(3, "ir-1a", 50, 200), 50, &lot), Ok(()));
+    }
+
+    #[test]
+    fn active_and_expired() {
+        let mut list = Reservations::new();
+        list.add(reservation(2, "ab-123", 100, 200), 50, &FREE).unwrap();
+        list.add(reservation(4, "ir-1a", 150, 250), 50, &FREE).unwrap();
+
+        assert_eq!(list.reserved(120), [false, true, false, false]);
+        assert_eq!(list.reserved(200), [false, false, false, true]);
+        let holders: std::vec::Vec<&str> = list.active(160).map(|r| r.holder.as_str()).collect();
+        assert_eq!(holders, ["ab-123", "ir-1a"]);
+
+        assert!(list.expire(200));
+        assert!(!list.expire(200));
+        assert_eq!(list.iter().count(), 1);
+        assert_eq!(list.cancel(4), 1);
+        assert_eq!(list.cancel(4), 0);
+    }
+
+    #[test]
+    fn saves_and_loads() {
+        let (mut store, list) = ReservationStore::load(SmallFlash::new(), 0).unwrap();
+        assert_eq!(list, Reservations::new());
+
+        let mut list = Reservations::new();
+        list.add(reservation(2, "ab-123", 100, 200), 50, &FREE).unwrap();
+        store.save(&list).unwrap();
+        list.add(reservation(3, "ir-1a", 100, 200), 50, &FREE).unwrap();
+        store.save(&list).unwrap();
+
+        let (store, loaded) = ReservationStore::load(store.flash, 0).unwrap();
+        assert_eq!(loaded, list);
+        assert_eq!(store.generation, 2);
+    }
+
+    #[test]
+    fn torn_save_keeps_the_previous_list() {
+        let (mut store, _) = ReservationStore::load(SmallFlash::new(), 0).unwrap();
+        let mut list = Reservations::new();
+        list.add(reservation(2, "ab-123", 100, 200), 50, &FREE).unwrap();
+        store.save(&list).unwrap();
+        let saved = list.clone();
+        list.add(reservation(3, "ir-1a", 100, 200), 50, &FREE).unwrap();
+        store.save(&list).unwrap();
+
+        // The newest copy, in the first sector, lost its checksum
+        store.flash.bytes[BLOCK_SIZE - 1] = 0;
+        let (_, loaded) = ReservationStore::load(store.flash, 0).unwrap();
+        assert_eq!(loaded, saved);
+    }
+}
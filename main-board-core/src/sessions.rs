@@ -0,0 +1,384 @@
+//! Parking sessions and the statistics built from them.
+//!
+//! A session runs from a spot turning occupied until it is freed again. A car
+//! that was already parked at boot has no known arrival, so it counts towards
+//! the occupancy but doesn't make a session when it leaves.
+//!
+//! A finished session stays pending as the spot's departure until the car
+//! passes the exit, so it can be paid for on the way out.
+
+use core::fmt::{self, Write};
+
+use embassy_time::{Duration, Instant};
+use heapless::{Deque, String};
+
+use crate::occupancy::{SpotState, SPOT_COUNT};
+
+/// Finished sessions kept for `GET /sessions`.
+pub const MAX_RECENT: usize = 16;
+
+/// Window the turnover is counted over.
+const TURNOVER_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+/// Most session ends remembered for the turnover.
+const MAX_TURNOVER: usize = 64;
+
+/// One car on one spot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Session {
+    /// 1-based spot number.
+    pub spot: u8,
+    pub start: Instant,
+    pub end: Instant,
+}
+
+impl Session {
+    pub fn duration(&self) -> Duration {
+        // The end never comes first, and a panic would need the defmt logger
+        // the host tests lack
+        self.end.saturating_duration_since(self.start)
+    }
+
+    /// One JSON object with the times since boot and, through `utc`, the
+    /// wall-clock times once the clock is synced.
+    pub fn write_json(&self, out: &mut impl Write, utc: impl Fn(Instant) -> Option<u64>) -> fmt::Result {
+        write!(
+            out,
+            "{{\"spot\":{},\"duration_secs\":{},\"start_uptime_ms\":{},\"end_uptime_ms\":{}",
+            self.spot,
+            self.duration().as_secs(),
+            self.start.as_millis(),
+            self.end.as_millis()
+        )?;
+        for (name, at) in [("start", self.start), ("end", self.end)] {
+            match utc(at) {
+                Some(ms) => write!(out, ",\"{}_utc_ms\":{}", name, ms)?,
+                None => write!(out, ",\"{}_utc_ms\":null", name)?,
+            }
+        }
+        out.write_char('}')
+    }
+}
+
+/// Finished sessions of one spot.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct SpotStats {
+    pub sessions: u32,
+    pub total_dwell_secs: u64,
+}
+
+impl SpotStats {
+    pub fn average_dwell_secs(&self) -> Option<u64> {
+        self.total_dwell_secs.checked_div(self.sessions as u64)
+    }
+}
+
+/// Statistics over the whole lot.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct Stats {
+    /// Sessions finished since boot.
+    pub sessions: u32,
+    /// Average session length, `None` before the first one finished.
+    pub average_dwell_secs: Option<u64>,
+    /// Sessions finished in the last hour.
+    pub turnover_per_hour: u32,
+    /// Spots occupied right now.
+    pub occupied: u8,
+    /// Most spots occupied at the same time since boot.
+    pub peak_occupied: u8,
+}
+
+impl Stats {
+    /// Encodes the statistics for the display as `Stats: <sessions>
+    /// <average dwell secs or -> <turnover per hour> <peak occupied>`, e.g.
+    /// `Stats: 12 840 3 4`.
+    pub fn encode(&self) -> String<48> {
+        let mut out = String::new();
+        let _ = write!(out, "Stats: {} ", self.sessions);
+        let _ = match self.average_dwell_secs {
+            Some(secs) => write!(out, "{}", secs),
+            None => out.write_char('-'),
+        };
+        let _ = write!(out, " {} {}", self.turnover_per_hour, self.peak_occupied);
+        out
+    }
+}
+
+/// A car that left its spot and hasn't passed the exit yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Departure {
+    pub session: Session,
+    pub paid: bool,
+}
+
+pub struct Sessions {
+    /// Last reading of every spot, `None` until its sensor reported once.
+    spots: [Option<SpotState>; SPOT_COUNT],
+    /// Arrival on every occupied spot, `None` if the car was there at boot.
+    started: [Option<Instant>; SPOT_COUNT],
+    per_spot: [SpotStats; SPOT_COUNT],
+    recent: Deque<Session, MAX_RECENT>,
+    /// Last departure from every spot, a newer one replaces it.
+    departures: [Option<Departure>; SPOT_COUNT],
+    /// End times of the sessions within [`TURNOVER_WINDOW`].
+    ends: Deque<Instant, MAX_TURNOVER>,
+    peak_occupied: u8,
+}
+
+impl Sessions {
+    pub const fn new() -> Self {
+        Self {
+            spots: [None; SPOT_COUNT],
+            started: [None; SPOT_COUNT],
+            per_spot: [SpotStats {
+                sessions: 0,
+                total_dwell_secs: 0,
+            }; SPOT_COUNT],
+            recent: Deque::new(),
+            departures: [None; SPOT_COUNT],
+            ends: Deque::new(),
+            peak_occupied: 0,
+        }
+    }
+
+    /// Records the latest reading of spot `spot_no` (1-based) and returns the
+    /// session it finished, if any.
+    pub fn update(&mut self, spot_no: u8, state: SpotState, now: Instant) -> Option<Session> {
+        let index = (spot_no as usize).checked_sub(1).filter(|i| *i < SPOT_COUNT)?;
+
+        let previous = self.spots[index].replace(state);
+        if previous == Some(state) {
+            return None;
+        }
+
+        match state {
+            SpotState::Occupied => {
+                // Only a change we saw is an arrival
+                self.started[index] = previous.map(|_| now);
+                self.peak_occupied = self.peak_occupied.max(self.occupied());
+                None
+            }
+            SpotState::Free => {
+                let session = Session {
+                    spot: spot_no,
+                    start: self.started[index].take()?,
+                    end: now,
+                };
+                self.finish(index, session);
+                Some(session)
+            }
+        }
+    }
+
+    fn finish(&mut self, index: usize, session: Session) {
+        let stats = &mut self.per_spot[index];
+        stats.sessions += 1;
+        stats.total_dwell_secs += session.duration().as_secs();
+
+        if self.recent.is_full() {
+            self.recent.pop_front();
+        }
+        let _ = self.recent.push_back(session);
+        self.departures[index] = Some(Departure { session, paid: false });
+
+        self.expire_ends(session.end);
+        if self.ends.is_full() {
+            self.ends.pop_front();
+        }
+        let _ = self.ends.push_back(session.end);
+    }
+
+    /// Forgets the session ends that fell out of [`TURNOVER_WINDOW`].
+    fn expire_ends(&mut self, now: Instant) {
+        while self
+            .ends
+            .front()
+            .is_some_and(|end| now.saturating_duration_since(*end) > TURNOVER_WINDOW)
+        {
+            self.ends.pop_front();
+        }
+    }
+
+    fn occupied(&self) -> u8 {
+        self.spots.iter().filter(|s| **s == Some(SpotState::Occupied)).count() as u8
+    }
+
+    /// How long the car on every spot has been parked, `None` for free spots
+    /// and cars that were there at boot.
+    pub fn parked_secs(&self, now: Instant) -> [Option<u64>; SPOT_COUNT] {
+        self.started.map(|start| start.map(|start| now.saturating_duration_since(start).as_secs()))
+    }
+
+    pub fn spot_stats(&self) -> &[SpotStats; SPOT_COUNT] {
+        &self.per_spot
+    }
+
+    /// Finished sessions, the oldest first.
+    pub fn recent(&self) -> impl Iterator<Item = &Session> {
+        self.recent.iter()
+    }
+
+    /// The car that last left spot `spot_no`, unless it passed the exit.
+    pub fn departure(&self, spot_no: u8) -> Option<Departure> {
+        *self.departures.get((spot_no as usize).wrapping_sub(1))?
+    }
+
+    /// Every car that left its spot and hasn't passed the exit.
+    pub fn departures(&self) -> impl Iterator<Item = Departure> + '_ {
+        self.departures.iter().flatten().copied()
+    }
+
+    /// Marks the departure from spot `spot_no` as paid, returns whether there
+    /// is one.
+    pub fn pay(&mut self, spot_no: u8) -> bool {
+        self.departure_mut(spot_no).map(|d| d.paid = true).is_some()
+    }
+
+    /// Forgets the departure from spot `spot_no` once the car is out.
+    pub fn exited(&mut self, spot_no: u8) {
+        if let Some(departure) = self.departures.get_mut((spot_no as usize).wrapping_sub(1)) {
+            *departure = None;
+        }
+    }
+
+    fn departure_mut(&mut self, spot_no: u8) -> Option<&mut Departure> {
+        self.departures.get_mut((spot_no as usize).wrapping_sub(1))?.as_mut()
+    }
+
+    pub fn stats(&self, now: Instant) -> Stats {
+        let sessions = self.per_spot.iter().map(|s| s.sessions).sum();
+        let total_dwell_secs: u64 = self.per_spot.iter().map(|s| s.total_dwell_secs).sum();
+        let turnover = self
+            .ends
+            .iter()
+            .filter(|end| now.saturating_duration_since(**end) <= TURNOVER_WINDOW)
+            .count();
+
+        Stats {
+            sessions,
+            average_dwell_secs: total_dwell_secs.checked_div(sessions as u64),
+            turnover_per_hour: turnover as u32,
+            occupied: self.occupied(),
+            peak_occupied: self.peak_occupied,
+        }
+    }
+}
+
+impl Default for Sessions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: u64) -> Instant {
+        Instant::from_secs(secs)
+    }
+
+    /// Every sensor reported a free spot at boot.
+    fn sessions() -> Sessions {
+        let mut sessions = Sessions::new();
+        for spot in 1..=SPOT_COUNT as u8 {
+            assert_eq!(sessions.update(spot, SpotState::Free, at(0)), None);
+        }
+        sessions
+    }
+
+    /// Parks on `spot` from `start` to `end`, returns the session.
+    fn park(sessions: &mut Sessions, spot: u8, start: u64, end: u64) -> Session {
+        assert_eq!(sessions.update(spot, SpotState::Occupied, at(start)), None);
+        sessions.update(spot, SpotState::Free, at(end)).unwrap()
+    }
+
+    #[test]
+    fn dwell_across_midnight() {
+        // Booted at 20:00 UTC, parked from 23:30 to 01:15
+        let boot_utc_ms = 20 * 3600 * 1000;
+        let utc = |at: Instant| Some(boot_utc_ms + at.as_millis());
+        let mut sessions = sessions();
+        let session = park(&mut sessions, 2, 3 * 3600 + 1800, 5 * 3600 + 900);
+        assert_eq!(session.duration().as_secs(), 6300);
+        assert_eq!(sessions.spot_stats()[1], SpotStats { sessions: 1, total_dwell_secs: 6300 });
+
+        let mut json = std::string::String::new();
+        session.write_json(&mut json, utc).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(json["duration_secs"], 6300);
+        assert_eq!(json["start_utc_ms"], (23 * 3600 + 1800) * 1000);
+        assert_eq!(json["end_utc_ms"], (24 * 3600 + 3600 + 900) * 1000);
+
+        // Without a synced clock
+        let mut json = std::string::String::new();
+        session.write_json(&mut json, |_| None).unwrap();
+        assert!(json.ends_with(",\"start_utc_ms\":null,\"end_utc_ms\":null}"));
+    }
+
+    #[test]
+    fn turnover_over_a_day() {
+        let mut sessions = sessions();
+        // A car every two hours through the day, 20 minutes each
+        for hour in (0..24).step_by(2) {
+            park(&mut sessions, 1, hour * 3600, hour * 3600 + 1200);
+        }
+        let last_end = 22 * 3600 + 1200;
+
+        let stats = sessions.stats(at(last_end));
+        assert_eq!(stats.sessions, 12);
+        assert_eq!(stats.average_dwell_secs, Some(1200));
+        // Only the sessions of the last hour count
+        assert_eq!(stats.turnover_per_hour, 1);
+        assert_eq!(sessions.stats(at(last_end + 3600)).turnover_per_hour, 1);
+        assert_eq!(sessions.stats(at(last_end + 3601)).turnover_per_hour, 0);
+
+        // The busy hour, four cars in it
+        for spot in 1..=4 {
+            park(&mut sessions, spot, last_end + 600, last_end + 600 + spot as u64 * 300);
+        }
+        assert_eq!(sessions.stats(at(last_end + 2400)).turnover_per_hour, 5);
+        assert_eq!(sessions.recent().count(), MAX_RECENT);
+    }
+
+    #[test]
+    fn peak_occupancy() {
+        let mut sessions = sessions();
+        assert_eq!(sessions.stats(at(0)).peak_occupied, 0);
+        sessions.update(1, SpotState::Occupied, at(10));
+        sessions.update(2, SpotState::Occupied, at(20));
+        sessions.update(3, SpotState::Occupied, at(30));
+        sessions.update(1, SpotState::Free, at(40));
+        sessions.update(4, SpotState::Occupied, at(50));
+
+        let stats = sessions.stats(at(60));
+        assert_eq!((stats.occupied, stats.peak_occupied), (3, 3));
+        sessions.update(1, SpotState::Occupied, at(70));
+        sessions.update(2, SpotState::Free, at(80));
+        assert_eq!(sessions.stats(at(90)).peak_occupied, 4);
+        assert_eq!(sessions.stats(at(90)).encode(), "Stats: 2 45 2 4");
+    }
+
+    #[test]
+    fn cars_parked_at_boot_make_no_session() {
+        let mut sessions = Sessions::new();
+        sessions.update(1, SpotState::Occupied, at(0));
+        assert_eq!(sessions.parked_secs(at(100))[0], None);
+        assert_eq!(sessions.update(1, SpotState::Free, at(100)), None);
+        assert_eq!(sessions.stats(at(100)).average_dwell_secs, None);
+        assert_eq!(sessions.departure(1), None);
+    }
+
+    #[test]
+    fn departures_until_the_exit() {
+        let mut sessions = sessions();
+        let session = park(&mut sessions, 3, 100, 700);
+        assert_eq!(sessions.departure(3), Some(Departure { session, paid: false }));
+        assert!(sessions.pay(3));
+        assert!(!sessions.pay(2));
+        assert_eq!(sessions.departures().collect::<std::vec::Vec<_>>(), [Departure { session, paid: true }]);
+        sessions.exited(3);
+        assert_eq!(sessions.departure(3), None);
+        assert_eq!(sessions.departure(0), None);
+    }
+}
@@ -0,0 +1,209 @@
+//! Settings that can be changed while the lot runs, with `GET /config` and
+//! `POST /config/<key>/<value>`.
+//!
+//! They start from the constants in `main.rs` and are kept until the board
+//! restarts. The checks don't touch the board, so they can be exercised on
+//! the host.
+
+use core::fmt::{self, Write};
+
+use crate::tariff::{self, Tariff};
+
+/// Longest the barrier stays open. The barrier task doesn't check in with the
+/// supervisor while the barrier is open, keep this below its timeout.
+pub const MAX_BARRIER_OPEN_SECS: u32 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Key {
+    BarrierOpenSecs,
+    UtcOffsetMinutes,
+    GraceMinutes,
+    HourlyRate,
+    NightRate,
+    NightFrom,
+    NightUntil,
+    DailyCap,
+}
+
+impl Key {
+    pub const ALL: [Key; 8] = [
+        Key::BarrierOpenSecs,
+        Key::UtcOffsetMinutes,
+        Key::GraceMinutes,
+        Key::HourlyRate,
+        Key::NightRate,
+        Key::NightFrom,
+        Key::NightUntil,
+        Key::DailyCap,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Key::BarrierOpenSecs => "barrier_open_secs",
+            Key::UtcOffsetMinutes => "utc_offset_minutes",
+            Key::GraceMinutes => "grace_minutes",
+            Key::HourlyRate => "hourly_rate",
+            Key::NightRate => "night_rate",
+            Key::NightFrom => "night_from",
+            Key::NightUntil => "night_until",
+            Key::DailyCap => "daily_cap",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Key::ALL.into_iter().find(|key| key.as_str() == s)
+    }
+
+    /// Lowest and highest value taken.
+    fn range(&self) -> (i64, i64) {
+        match self {
+            Key::BarrierOpenSecs => (1, MAX_BARRIER_OPEN_SECS as i64),
+            // UTC-12 to UTC+14
+            Key::UtcOffsetMinutes => (-12 * 60, 14 * 60),
+            Key::GraceMinutes => (0, 24 * 60),
+            Key::HourlyRate | Key::NightRate | Key::DailyCap => (0, tariff::MAX_AMOUNT as i64),
+            Key::NightFrom | Key::NightUntil => (0, 23),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Settings {
+    /// How long the barrier stays open before closing on its own.
+    pub barrier_open_secs: u32,
+    /// Time zone of the access schedules and the night tariff, minutes ahead
+    /// of UTC.
+    pub utc_offset_minutes: i64,
+    pub tariff: Tariff,
+}
+
+impl Settings {
+    pub fn get(&self, key: Key) -> i64 {
+        match key {
+            Key::BarrierOpenSecs => self.barrier_open_secs as i64,
+            Key::UtcOffsetMinutes => self.utc_offset_minutes,
+            Key::GraceMinutes => self.tariff.grace_minutes as i64,
+            Key::HourlyRate => self.tariff.hourly_rate as i64,
+            Key::NightRate => self.tariff.night_rate as i64,
+            Key::NightFrom => self.tariff.night_from as i64,
+            Key::NightUntil => self.tariff.night_until as i64,
+            Key::DailyCap => self.tariff.daily_cap as i64,
+        }
+    }
+
+    /// Changes one setting, unless `value` is out of its range.
+    pub fn set(&mut self, key: Key, value: i64) -> Result<(), &'static str> {
+        let (min, max) = key.range();
+        if !(min..=max).contains(&value) {
+            return Err("out of range");
+        }
+
+        // The range checked that the value fits
+        match key {
+            Key::BarrierOpenSecs => self.barrier_open_secs = value as u32,
+            Key::UtcOffsetMinutes => self.utc_offset_minutes = value,
+            Key::GraceMinutes => self.tariff.grace_minutes = value as u32,
+            Key::HourlyRate => self.tariff.hourly_rate = value as u32,
+            Key::NightRate => self.tariff.night_rate = value as u32,
+            Key::NightFrom => self.tariff.night_from = value as u8,
+            Key::NightUntil => self.tariff.night_until = value as u8,
+            Key::DailyCap => self.tariff.daily_cap = value as u32,
+        }
+        Ok(())
+    }
+
+    /// Every setting as a JSON object, keyed by [`Key::as_str`].
+    pub fn write_json(&self, out: &mut impl Write) -> fmt::Result {
+        out.write_char('{')?;
+        for (i, key) in Key::ALL.iter().enumerate() {
+            write!(out, "{}\"{}\":{}", if i > 0 { "," } else { "" }, key.as_str(), self.get(*key))?;
+        }
+        out.write_str("}\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> Settings {
+        Settings {
+            barrier_open_secs: 5,
+            utc_offset_minutes: 60,
+            tariff: Tariff {
+                grace_minutes: 15,
+                hourly_rate: 200,
+                night_rate: 100,
+                night_from: 22,
+                night_until: 6,
+                daily_cap: 2000,
+            },
+        }
+    }
+
+    #[test]
+    fn keys_round_trip() {
+        for key in Key::ALL {
+            assert_eq!(Key::parse(key.as_str()), Some(key));
+        }
+        assert_eq!(Key::parse("hourly-rate"), None);
+        assert_eq!(Key::parse(""), None);
+    }
+
+    #[test]
+    fn set_then_get() {
+        for key in Key::ALL {
+            let mut settings = settings();
+            let (min, max) = key.range();
+            for value in [min, max, (min + max) / 2] {
+                assert_eq!(settings.set(key, value), Ok(()));
+                assert_eq!(settings.get(key), value, "{}", key.as_str());
+            }
+        }
+    }
+
+    #[test]
+    fn bounds() {
+        let cases = [
+            (Key::BarrierOpenSecs, 1, 10),
+            (Key::UtcOffsetMinutes, -720, 840),
+            (Key::GraceMinutes, 0, 1440),
+            (Key::HourlyRate, 0, 1_000_000),
+            (Key::NightRate, 0, 1_000_000),
+            (Key::DailyCap, 0, 1_000_000),
+            (Key::NightFrom, 0, 23),
+            (Key::NightUntil, 0, 23),
+        ];
+        for (key, min, max) in cases {
+            let mut settings = settings();
+            assert_eq!(settings.set(key, min), Ok(()));
+            assert_eq!(settings.set(key, max), Ok(()));
+
+            // Out of range leaves the setting alone
+            assert_eq!(settings.set(key, min - 1), Err("out of range"));
+            assert_eq!(settings.set(key, max + 1), Err("out of range"));
+            assert_eq!(settings.get(key), max, "{}", key.as_str());
+        }
+
+        // Larger than the fields, no wrapping
+        let mut settings = settings();
+        assert_eq!(settings.set(Key::HourlyRate, 1 << 32), Err("out of range"));
+        assert_eq!(settings.set(Key::NightFrom, 256), Err("out of range"));
+        assert_eq!(settings, self::settings());
+    }
+
+    #[test]
+    fn json() {
+        let mut out = std::string::String::new();
+        settings().write_json(&mut out).unwrap();
+        assert_eq!(
+            out,
+            "{\"barrier_open_secs\":5,\"utc_offset_minutes\":60,\"grace_minutes\":15,\"hourly_rate\":200,\
+             \"night_rate\":100,\"night_from\":22,\"night_until\":6,\"daily_cap\":2000}\n"
+        );
+        let json: serde_json::Value = serde_json::from_str(&out).unwrap();
+        for key in Key::ALL {
+            assert_eq!(json[key.as_str()], settings().get(key), "{}", key.as_str());
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Parking fees.
+//!
+//! A stay within the grace period is free, longer stays pay every started hour
+//! from the arrival, at the night rate when the hour starts at night. Every 24
+//! hours from the arrival are charged at most the daily cap.
+//!
+//! Times are local seconds since the Unix epoch, so the night hours follow the
+//! local clock. The fees only depend on the times they are given and can be
+//! exercised on the host.
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+
+/// Highest rate or cap the settings take, in cents.
+pub const MAX_AMOUNT: u32 = 1_000_000;
+
+/// Amounts are in cents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Tariff {
+    /// Stays up to this long are free.
+    pub grace_minutes: u32,
+    /// Per started hour during the day.
+    pub hourly_rate: u32,
+    /// Per started hour at night.
+    pub night_rate: u32,
+    /// Local hour the night starts.
+    pub night_from: u8,
+    /// Local hour the night ends, may be after midnight.
+    pub night_until: u8,
+    /// Most charged for 24 hours.
+    pub daily_cap: u32,
+}
+
+impl Tariff {
+    /// Whether a stay of `secs` is still free.
+    pub fn is_grace(&self, secs: u64) -> bool {
+        secs <= self.grace_minutes as u64 * 60
+    }
+
+    /// Fee of a stay from `entry` until `exit`, local seconds.
+    pub fn fee(&self, entry: u64, exit: u64) -> u32 {
+        let stay = exit.saturating_sub(entry);
+        if self.is_grace(stay) {
+            return 0;
+        }
+
+        // Every full day holds every hour of the day once, so costs the same
+        let days = stay / DAY;
+        let full_day = self.hours_fee(entry, 24);
+        let rest = (stay % DAY).div_ceil(HOUR);
+        let fee = days * full_day as u64 + self.hours_fee(entry + days * DAY, rest) as u64;
+        u32::try_from(fee).unwrap_or(u32::MAX)
+    }
+
+    /// Fee of `hours` started hours from `start`, no more than a day's worth.
+    fn hours_fee(&self, start: u64, hours: u64) -> u32 {
+        let fee: u64 = (0..hours)
+            .map(|hour| {
+                if self.is_night(start + hour * HOUR) {
+                    self.night_rate as u64
+                } else {
+                    self.hourly_rate as u64
+                }
+            })
+            .sum();
+        // No more than the cap, which is a `u32`
+        fee.min(self.daily_cap as u64) as u32
+    }
+
+    fn is_night(&self, at: u64) -> bool {
+        let hour = ((at % DAY) / HOUR) as u8;
+        if self.night_from <= self.night_until {
+            (self.night_from..self.night_until).contains(&hour)
+        } else {
+            hour >= self.night_from || hour < self.night_until
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARIFF: Tariff = Tariff {
+        grace_minutes: 15,
+        hourly_rate: 200,
+        night_rate: 100,
+        night_from: 20,
+        night_until: 7,
+        daily_cap: 1500,
+    };
+
+    /// Local seconds of `hour` o'clock on day `day` after the epoch.
+    fn at(day: u64, hour: u64) -> u64 {
+        day * DAY + hour * HOUR
+    }
+
+    #[test]
+    fn grace() {
+        let entry = at(10, 12);
+        assert_eq!(TARIFF.fee(entry, entry), 0);
+        assert_eq!(TARIFF.fee(entry, entry + 15 * 60), 0);
+        assert_eq!(TARIFF.fee(entry, entry + 15 * 60 + 1), 200);
+        // An exit before the entry, after a clock step, is free
+        assert_eq!(TARIFF.fee(entry, entry - 60), 0);
+    }
+
+    #[test]
+    fn started_hours() {
+        let entry = at(10, 9);
+        assert_eq!(TARIFF.fee(entry, entry + HOUR), 200);
+        assert_eq!(TARIFF.fee(entry, entry + HOUR + 1), 400);
+        assert_eq!(TARIFF.fee(entry, entry + 3 * HOUR), 600);
+    }
+
+    #[test]
+    fn night_rate() {
+        // 18:00 to 22:00, two day hours and two night hours
+        assert_eq!(TARIFF.fee(at(10, 18), at(10, 22)), 600);
+        // An hour that starts at 19:30 is a day hour
+        assert_eq!(TARIFF.fee(at(10, 19) + 30 * 60, at(10, 20) + 30 * 60), 200);
+        // The night runs over midnight until 07:00
+        assert_eq!(TARIFF.fee(at(10, 23), at(11, 2)), 300);
+        assert_eq!(TARIFF.fee(at(11, 5), at(11, 8)), 400);
+    }
+
+    #[test]
+    fn night_within_the_day() {
+        let tariff = Tariff {
+            night_from: 1,
+            night_until: 5,
+            ..TARIFF
+        };
+        assert_eq!(tariff.fee(at(10, 0), at(10, 6)), 200 + 4 * 100 + 200);
+    }
+
+    #[test]
+    fn daily_cap() {
+        // 12 day hours make 2400, and a whole day 3700
+        assert_eq!(TARIFF.fee(at(10, 8), at(10, 20)), 1500);
+        assert_eq!(TARIFF.fee(at(10, 8), at(11, 8)), 1500);
+        // Two full days and two more night hours
+        assert_eq!(TARIFF.fee(at(10, 8), at(12, 8)), 3000);
+        assert_eq!(TARIFF.fee(at(10, 22), at(13, 0)), 3200);
+    }
+
+    #[test]
+    fn day_wrap() {
+        // Every started day is charged from the arrival, whatever the time of day
+        assert_eq!(TARIFF.fee(at(10, 23), at(11, 23)), 1500);
+        assert_eq!(TARIFF.fee(at(10, 23), at(11, 23) + 1), 1600);
+        assert_eq!(TARIFF.fee(at(10, 6), at(11, 7)), 1500 + 100);
+    }
+
+    #[test]
+    fn highest_rates_dont_overflow() {
+        let tariff = Tariff {
+            hourly_rate: u32::MAX,
+            night_rate: u32::MAX,
+            daily_cap: u32::MAX,
+            ..TARIFF
+        };
+        assert_eq!(tariff.fee(at(10, 8), at(10, 10)), u32::MAX);
+        assert_eq!(tariff.fee(at(10, 8), at(20, 8)), u32::MAX);
+
+        let tariff = Tariff {
+            hourly_rate: MAX_AMOUNT,
+            night_rate: MAX_AMOUNT,
+            daily_cap: u32::MAX,
+            ..TARIFF
+        };
+        assert_eq!(tariff.fee(at(10, 8), at(11, 8)), 24 * MAX_AMOUNT);
+    }
+}
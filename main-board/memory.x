@@ -3,14 +3,25 @@ MEMORY {
      * The RP2350 has either external or internal flash.
      *
      * 2 MiB is a safe default here, although a Pico 2 has 4 MiB.
+     *
+     * The firmware runs from the ACTIVE partition of the bootloader, see
+     * `bootloader/memory-rp235x.x`, which has to be flashed first. The last
+     * 72K of those 2 MiB hold the reservations and the event log, see
+     * `RESERVATIONS_OFFSET` and `EVENT_LOG_OFFSET` in `src/main.rs`.
      */
-    FLASH : ORIGIN = 0x10000000, LENGTH = 2048K
+    BOOTLOADER_STATE : ORIGIN = 0x10008000, LENGTH = 4K
+    FLASH : ORIGIN = 0x10009000, LENGTH = 968K
+    DFU : ORIGIN = 0x100FB000, LENGTH = 972K
     /*
      * RAM consists of 8 banks, SRAM0-SRAM7, with a striped mapping.
      * This is usually good for performance, as it distributes load on
      * those banks evenly.
+     *
+     * The first 1K keeps the crash record and an emergency that isn't
+     * cleared over a restart, the bootloader leaves it alone too.
      */
-    RAM : ORIGIN = 0x20000000, LENGTH = 512K
+    CRASH : ORIGIN = 0x20000000, LENGTH = 1K
+    RAM : ORIGIN = 0x20000400, LENGTH = 511K
     /*
      * RAM banks 8 and 9 use a direct mapping. They can be used to have
      * memory areas dedicated for some specific job, improving predictability
@@ -21,6 +32,12 @@ MEMORY {
     SRAM5 : ORIGIN = 0x20081000, LENGTH = 4K
 }
 
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - 0x10000000;
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - 0x10000000;
+
+__bootloader_dfu_start = ORIGIN(DFU) - 0x10000000;
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - 0x10000000;
+
 SECTIONS {
     /* ### Boot ROM info
      *
@@ -58,6 +75,15 @@ SECTIONS {
     } > FLASH
 } INSERT AFTER .text;
 
+SECTIONS {
+    /* Crash record and emergency latch kept over a restart, see
+     * `embassy_lab_utils::crash` and `EMERGENCY_LATCH` in `src/main.rs` */
+    .crash (NOLOAD) : ALIGN(4)
+    {
+        KEEP(*(.crash .crash.*));
+    } > CRASH
+} INSERT AFTER .uninit;
+
 SECTIONS {
     /* ### Boot ROM extra info
      *
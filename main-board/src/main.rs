@@ -1,62 +1,403 @@
 #![no_std]
 #![no_main]
 
+use core::cell::{Cell, RefCell};
+use core::fmt::Write as FmtWrite;
+use core::mem::MaybeUninit;
+use core::pin::pin;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
 use embassy_executor::Spawner;
-use embassy_net::{IpAddress, IpEndpoint, Stack, StackResources};
-use embassy_time::{Duration, Timer};
+use embassy_net::{IpAddress, IpEndpoint, Ipv4Address, Ipv4Cidr, Stack, StackResources, StaticConfigV4};
+use embassy_time::{with_timeout, Duration, Instant, Ticker, Timer};
 use embassy_net::tcp::TcpSocket;
 use static_cell::StaticCell;
-use cyw43::JoinOptions;
 use embassy_rp::{gpio::{AnyPin, Input, Level, Output, Pin, Pull}, pwm::{Config as PwmConfig, Pwm}};
+use embassy_embedded_hal::flash::partition::BlockingPartition;
 use fixed::traits::ToFixed;
-use {defmt_rtt as _, panic_probe as _};
-use heapless::String; 
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_futures::select::{select, select3, Either, Either3};
+use embassy_sync::channel::Channel;
+use embassy_sync::mutex::Mutex as AsyncMutex;
+use embassy_sync::signal::Signal;
+use embedded_io_async::Write;
+use heapless::{String, Vec};
+use embassy_lab_utils::wifi::{self, Connectivity, Network};
+use embassy_lab_utils::sntp::{self, UtcTime};
+use embassy_lab_utils::supervisor::{self, Heartbeat};
+use embassy_lab_utils::ota::{self, BoardFlash};
+#[cfg(feature = "netlog")]
+use embassy_lab_utils::netlog;
+use embassy_lab_utils::{announce, ap, crash, Addressing};
 
 use defmt::*;
 
 mod irqs;
 
-const SOCK: usize = 8;
+use main_board_core::{access, barrier, commands, event_log, gate, http, mqtt, occupancy, reservations, sessions, settings, tariff};
+
+use access::{Date, Days, LocalTime, Policy, Rule, Schedule, Weekday};
+use barrier::{BarrierCommand, BarrierState, Control, Opening};
+use commands::{Command, LineBuffer, LineError};
+use event_log::{Event, EventLog, Peer, Record, Timestamp};
+use gate::{Gate, Opener, Pass, Refusal, Waiting};
+use http::{BoardReset, EventFormat, Route, StatusCode, StatusReport};
+use mqtt::QoS;
+use occupancy::{Occupancy, SpotState, SPOT_COUNT};
+use reservations::{Holder, MAX_RESERVATIONS, Reservation, ReservationError, ReservationStore, Reservations};
+use sessions::{Departure, Session, Sessions};
+use settings::Settings;
+use tariff::Tariff;
+
+const SOCK: usize = 15;
 static RESOURCES: StaticCell<StackResources<SOCK>> = StaticCell::<StackResources<SOCK>>::new();
 const WIFI_NETWORK: &str = "desk";
 const WIFI_PASSWORD: &str = "testing123";
+const NETWORKS: &[Network] = &[Network::new(WIFI_NETWORK, WIFI_PASSWORD)];
+
+/// Lowest level streamed on the log port for `tools/netlog`, `None` keeps
+/// the log on RTT.
+#[cfg(feature = "netlog")]
+const NET_LOG: Option<netlog::Level> = Some(netlog::Level::Info);
+
+/// Open our own access point instead of joining [`WIFI_NETWORK`], for running
+/// the lot without existing WiFi.
+const ACCESS_POINT: bool = false;
+
+/// Name sent to the DHCP server.
+const HOSTNAME: &str = "parking-main";
+/// Address used when DHCP doesn't answer, the one the IR board expects.
+const FALLBACK_IP: Ipv4Address = Ipv4Address::new(192, 168, 23, 155);
+/// How long to wait for a DHCP lease before using [`FALLBACK_IP`].
+const DHCP_FALLBACK_TIMEOUT: Duration = Duration::from_secs(10);
+/// Where the clock is synced from, a host name or an IPv4 address.
+const NTP_SERVER: &str = "pool.ntp.org";
+/// Display address used until the display announces itself.
+const DISPLAY_IP: Ipv4Address = Ipv4Address::new(192, 168, 23, 41);
+
+/// How often the status is resent to the display when nothing changed.
+const SNAPSHOT_RESEND_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long to wait for the display to report its health after a status.
+const DISPLAY_REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long the supervised tasks may go without checking in. The barrier
+/// stays open for 5 seconds, a status can take a connect and a reply timeout.
+const SENSOR_TIMEOUT: Duration = Duration::from_secs(5);
+const BARRIER_TIMEOUT: Duration = Duration::from_secs(15);
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(40);
+/// The command port can wait for the barrier behind a full queue of openings.
+const COMMANDS_TIMEOUT: Duration = Duration::from_secs(60);
+/// Tasks serving TCP port 6000, each takes one client at a time.
+const COMMAND_TASKS: [&str; 3] = ["commands 1", "commands 2", "commands 3"];
+/// How often a task waiting for events checks in.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long an HTTP client may stay idle before it is dropped.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Flash region of the event log, `memory.x` keeps the program out of it.
+const EVENT_LOG_OFFSET: u32 = 0x1f_0000;
+const EVENT_LOG_SIZE: u32 = 64 * 1024;
+/// Records read from the event log at a time for a download.
+const EVENT_BATCH: usize = 16;
+/// Flash region of the reservations, right below the event log.
+const RESERVATIONS_OFFSET: u32 = 0x1e_e000;
+const RESERVATIONS_SIZE: u32 = 8 * 1024;
+
+/// How often expired reservations are dropped and the reserved spots updated.
+const RESERVATION_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+const _: () = assert!(RESERVATIONS_SIZE == ReservationStore::<FlashPartition>::SIZE);
+
+/// One region of the flash, shared by the event log, the reservations and the
+/// firmware updates.
+type FlashPartition = BlockingPartition<'static, CriticalSectionRawMutex, BoardFlash>;
+type SharedEventLog = AsyncMutex<CriticalSectionRawMutex, EventLog<FlashPartition>>;
+
+/// Time zone of the access schedules and the night tariff, minutes ahead of
+/// UTC. Daylight saving time isn't followed. Changed with
+/// `POST /config/utc_offset_minutes/<minutes>` until the next restart.
+const UTC_OFFSET_MINUTES: i64 = 2 * 60;
+
+/// Who may open the barrier with a remote and when. The IR board names the
+/// remotes after the address in their codes, `ir-<hex address>`. Every
+/// opening goes through the [`gate`], which checks the remotes against this.
+const ACCESS_POLICY: Policy = Policy {
+    rules: &[
+        // The lab remote, at any time
+        Rule::new("ir-00", &[Schedule::ALWAYS]),
+        // Office hours on weekdays, Saturday and holiday mornings
+        Rule::new(
+            "ir-01",
+            &[
+                Schedule::hours(Days::WEEKDAYS, 7, 19),
+                Schedule::hours(Days::day(Weekday::Saturday).and(Days::HOLIDAY), 8, 12),
+            ],
+        ),
+    ],
+    holidays: &[Date::yearly(1, 1), Date::yearly(5, 1), Date::yearly(12, 25), Date::yearly(12, 26)],
+};
+
+/// What the exit terminal charges, in cents. The fields are settings too, see
+/// [`settings::Key`].
+const TARIFF: Tariff = Tariff {
+    grace_minutes: 15,
+    hourly_rate: 200,
+    night_rate: 100,
+    night_from: 20,
+    night_until: 7,
+    daily_cap: 1500,
+};
+
+/// How long a car that left its spot without paying keeps the barrier closed
+/// for everyone else, it is taken to be waiting at the exit until then.
+const EXIT_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// Address the remote keys are taken from on TCP port 6000, `None` for the
+/// one the IR board announces. Any host on the network can announce itself as
+/// the IR board, so give the IR board a fixed address where that matters.
+const IR_BOARD_IP: Option<Ipv4Address> = None;
+
+/// MQTT broker the lot is published to, `None` to not use MQTT.
+const MQTT_BROKER: Option<Ipv4Address> = None;
+/// Lot name in the MQTT topics, `parking/<lot>/...`.
+const MQTT_LOT: &str = "lot1";
+/// Keep alive agreed with the broker, a ping is sent every half of it.
+const MQTT_KEEP_ALIVE: Duration = Duration::from_secs(60);
+/// How long to wait before connecting to the broker again.
+const MQTT_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+/// How long the broker may take to acknowledge a publish before the
+/// connection counts as lost.
+const MQTT_ACK_TIMEOUT: Duration = Duration::from_secs(20);
+/// Publishes waiting for their PUBACK at once, one per spot and the barrier.
+const MQTT_IN_FLIGHT: usize = SPOT_COUNT + 1;
+
+/// How long the barrier stays open before closing on its own, at most
+/// [`settings::MAX_BARRIER_OPEN_SECS`].
+const BARRIER_OPEN_SECS: u32 = 5;
+
+const _: () = assert!(BARRIER_OPEN_SECS >= 1 && BARRIER_OPEN_SECS <= settings::MAX_BARRIER_OPEN_SECS);
+
+/// Key the operators start and clear an emergency, change the settings and
+/// send barrier commands over HTTP and MQTT with, and the emergency over the
+/// command port. It is built in from the `PARKING_OPERATOR_KEY` environment
+/// variable, so every lot has its own and none is in the repository. Built
+/// without it, every command that needs the key is refused.
+const OPERATOR_KEY: Option<&str> = option_env!("PARKING_OPERATOR_KEY");
+
+// The key is sent as one word, with the commands and as a bearer token
+const _: () = if let Some(key) = OPERATOR_KEY {
+    let key = key.as_bytes();
+    assert!(key.len() >= 8 && key.len() <= commands::MAX_KEY, "PARKING_OPERATOR_KEY needs 8 to 32 characters");
+    let mut i = 0;
+    while i < key.len() {
+        assert!(key[i].is_ascii_graphic(), "PARKING_OPERATOR_KEY can only have printable ASCII without spaces");
+        i += 1;
+    }
+};
+
+/// How often both barrier LEDs switch during an emergency.
+const EMERGENCY_FLASH_INTERVAL: Duration = Duration::from_millis(500);
+/// How long the emergency input has to stay low or high to count.
+const EMERGENCY_DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// The settings changed over HTTP, starting from the constants above.
+static SETTINGS: Mutex<CriticalSectionRawMutex, Cell<Settings>> = Mutex::new(Cell::new(Settings {
+    barrier_open_secs: BARRIER_OPEN_SECS,
+    utc_offset_minutes: UTC_OFFSET_MINUTES,
+    tariff: TARIFF,
+}));
+
+/// Lot state shared by the sensor tasks, the barrier and the snapshot publisher.
+static OCCUPANCY: Mutex<CriticalSectionRawMutex, RefCell<Occupancy>> = Mutex::new(RefCell::new(Occupancy::new()));
+/// Parking sessions and their statistics, fed by the sensor tasks.
+static SESSIONS: Mutex<CriticalSectionRawMutex, RefCell<Sessions>> = Mutex::new(RefCell::new(Sessions::new()));
+/// Barrier state shown on the display.
+static BARRIER_STATE: Mutex<CriticalSectionRawMutex, Cell<BarrierState>> = Mutex::new(Cell::new(BarrierState::Closed));
+/// Last time a command arrived from the IR board.
+static IR_LAST_SEEN: Mutex<CriticalSectionRawMutex, Cell<Option<Instant>>> = Mutex::new(Cell::new(None));
+/// Set while the display board reports that its screen does not respond.
+static DISPLAY_FAULT: AtomicBool = AtomicBool::new(false);
+/// Barrier commands from the IR board, the command port, HTTP and MQTT, with
+/// whether the sender waits on [`BARRIER_REPLY`]. Only the command port
+/// waits, one command at a time, so the reply is always its own.
+static BARRIER_COMMANDS: Channel<CriticalSectionRawMutex, (BarrierCommand, bool), 4> = Channel::new();
+/// State the barrier was left in by a command that waits for it.
+static BARRIER_REPLY: Signal<CriticalSectionRawMutex, BarrierState> = Signal::new();
+/// Starts an emergency, ahead of the queued barrier commands and cutting an
+/// opening short.
+static EMERGENCY: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Set while the emergency input is active, the emergency can't be cleared
+/// before it is released.
+static EMERGENCY_INPUT: AtomicBool = AtomicBool::new(false);
+
+/// Keeps an emergency on over a restart, in the RAM that survives it next to
+/// the crash record, see `memory.x` and [`barrier::Control::latch`].
+#[unsafe(link_section = ".crash.emergency")]
+static mut EMERGENCY_LATCH: MaybeUninit<u32> = MaybeUninit::uninit();
+
+fn write_latch(latch: u32) {
+    // SAFETY: only the barrier task touches the latch
+    unsafe { (&raw mut EMERGENCY_LATCH).cast::<u32>().write_volatile(latch) }
+}
+
+fn read_latch() -> u32 {
+    // SAFETY: any value is a `u32`, random RAM after power on won't be the magic
+    unsafe { (&raw const EMERGENCY_LATCH).cast::<u32>().read_volatile() }
+}
+/// Times the barrier opened since boot.
+static OPENINGS: AtomicU32 = AtomicU32::new(0);
+/// Open commands refused because the barrier was locked.
+static DENIED_OPENINGS: AtomicU32 = AtomicU32::new(0);
+/// Events waiting to be written to flash, with the time they happened.
+static EVENTS: Channel<CriticalSectionRawMutex, (Instant, Event), 16> = Channel::new();
+/// Spot reservations, saved to flash by the reservation task.
+static RESERVATIONS: Mutex<CriticalSectionRawMutex, RefCell<Reservations>> = Mutex::new(RefCell::new(Reservations::new()));
+/// Spots held by a reservation right now.
+static RESERVED: Mutex<CriticalSectionRawMutex, Cell<[bool; SPOT_COUNT]>> = Mutex::new(Cell::new([false; SPOT_COUNT]));
+/// Raised whenever the reservations were changed and have to be saved.
+static RESERVATIONS_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// The event log, shared by its writer and the HTTP download.
+static EVENT_LOG: StaticCell<SharedEventLog> = StaticCell::new();
+/// Raised whenever the status has to be published right away.
+static SNAPSHOT_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Raised whenever the MQTT broker has to be told about a change.
+static MQTT_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Wakes everything that publishes the lot state.
+fn state_changed() {
+    SNAPSHOT_CHANGED.signal(());
+    MQTT_CHANGED.signal(());
+}
+
+/// Updates the barrier state and publishes it if it changed.
+fn set_barrier_state(state: BarrierState) {
+    if BARRIER_STATE.lock(|s| s.replace(state)) != state {
+        state_changed();
+    }
+}
 
 #[embassy_executor::task(pool_size = 4)]
-async fn sensor_task(pin: AnyPin, mut led_green: Output<'static>, mut led_red: Output<'static>, stack: Stack<'static>, sensor_no: u64) {
+async fn sensor_task(
+    pin: AnyPin,
+    mut led_green: Output<'static>,
+    mut led_red: Output<'static>,
+    sensor_no: u8,
+    heartbeat: Heartbeat,
+) {
     let sensor = Input::new(pin, Pull::Up);
 
     loop {
+        heartbeat.beat();
+
         // Check the sensor state
-        let mut state: String<128> = String::new();
-        if !sensor.is_high() {
-            let _ = core::fmt::write(&mut state, format_args!("Sensor {}: Occupied", sensor_no));
+        let state = if !sensor.is_high() {
             // Turn on the red LED
             led_red.set_high();
             led_green.set_low();
+            SpotState::Occupied
+        } else if RESERVED.lock(|r| r.get())[sensor_no as usize - 1] {
+            // Blink the green LED while the spot is held for somebody
+            led_red.set_low();
+            led_green.toggle();
+            SpotState::Free
         } else {
-            let _ = core::fmt::write(&mut state, format_args!("Sensor {}: Not Occupied", sensor_no));
             // Turn on the green LED
             led_red.set_low();
             led_green.set_high();
+            SpotState::Free
+        };
+
+        // Feed the reading into the occupancy model and wake the publisher on changes
+        let result = OCCUPANCY.lock(|o| o.borrow_mut().update_spot(sensor_no, state, Instant::now()));
+        match result {
+            Ok(true) => {
+                info!("Sensor {}: {}", sensor_no, state);
+                spot_changed(sensor_no, state);
+            }
+            Ok(false) => {}
+            Err(inconsistency) => {
+                warn!("Occupancy mismatch: {}", inconsistency);
+                spot_changed(sensor_no, state);
+            }
         }
 
+        // Wait before checking the sensor state again
+        Timer::after(Duration::from_secs(1)).await;
+    }
+}
+
+/// Logs a spot change, closes or opens its session and publishes it.
+fn spot_changed(spot_no: u8, state: SpotState) {
+    log_spot_change(spot_no, state);
+    if let Some(session) = SESSIONS.lock(|s| s.borrow_mut().update(spot_no, state, Instant::now())) {
+        info!("Spot {} session ended after {}s", spot_no, session.duration().as_secs());
+    }
+    state_changed();
+}
+
+/// Publishes the lot snapshot, the barrier state and the IR link age to the
+/// display board whenever they change and periodically in between, so a lost
+/// message is corrected by the next one.
+#[embassy_executor::task]
+async fn snapshot_task(stack: Stack<'static>, heartbeat: Heartbeat) {
+    let mut display_reachable = true;
+
+    loop {
+        heartbeat.beat();
+
+        // Wait for a change, or resend the current state after a while
+        let _ = with_timeout(SNAPSHOT_RESEND_INTERVAL, SNAPSHOT_CHANGED.wait()).await;
+
+        let snapshot = OCCUPANCY.lock(|o| {
+            let mut o = o.borrow_mut();
+            while let Some(inconsistency) = o.check(Instant::now()) {
+                warn!("Occupancy mismatch: {}", inconsistency);
+            }
+            o.snapshot()
+        })
+        .with_reserved(RESERVED.lock(|r| r.get()));
+        let now = Instant::now();
+
+        // One line per status item: the lot, the barrier, the session
+        // statistics and the IR board link age
+        let mut message: String<192> = String::new();
+        let _ = writeln!(message, "{}", snapshot.encode().as_str());
+        let _ = writeln!(message, "{}", BARRIER_STATE.lock(|s| s.get()).encode().as_str());
+        let _ = writeln!(message, "{}", SESSIONS.lock(|s| s.borrow().stats(now)).encode().as_str());
+        let _ = match IR_LAST_SEEN.lock(|s| s.get()) {
+            Some(at) => writeln!(message, "Link: ir {}", (now - at).as_secs()),
+            None => writeln!(message, "Link: ir never"),
+        };
+
         // Create a new TcpSocket for each connection attempt
-        let mut tx_buffer = [0; 128];
+        let mut tx_buffer = [0; 256];
         let mut rx_buffer = [0; 128];
         let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
         socket.set_timeout(Some(Duration::from_secs(10)));
 
         // Connect to the TCP server
-        match socket.connect(IpEndpoint::new(IpAddress::v4(192, 168, 23, 41), 6000)).await {
+        let display_ip = announce::peer_or("display", DISPLAY_IP);
+        match socket.connect(IpEndpoint::new(IpAddress::Ipv4(display_ip), 6000)).await {
             Ok(_) => {
                 info!("Connected to server");
+                display_reachable = true;
 
-                // Send the sensor state
-                let buffer = state.as_bytes();
-                if let Err(e) = socket.write(buffer).await {
+                // Send the snapshot
+                if let Err(e) = socket.write(message.as_bytes()).await {
                     warn!("write error: {:?}", e);
                 } else {
-                    info!("Sent state: {}", state.as_str());
+                    info!("Sent status: {} ({}/{} free)", message.as_str(), snapshot.free_spaces(), SPOT_COUNT);
+
+                    // The display answers with its own health
+                    let mut reply = [0; 64];
+                    match with_timeout(DISPLAY_REPLY_TIMEOUT, socket.read(&mut reply)).await {
+                        Ok(Ok(n)) if n > 0 => update_display_health(&reply[..n]),
+                        Ok(Ok(_)) => warn!("display closed without a reply"),
+                        Ok(Err(e)) => warn!("read error: {:?}", e),
+                        Err(_) => warn!("no reply from the display"),
+                    }
                 }
 
                 // Close the socket
@@ -64,89 +405,1216 @@ async fn sensor_task(pin: AnyPin, mut led_green: Output<'static>, mut led_red: O
             }
             Err(e) => {
                 warn!("connect error: {:?}", e);
+                if display_reachable {
+                    log_event(Event::LinkLost(Peer::Display));
+                    display_reachable = false;
+                }
             }
         }
+    }
+}
 
-        // Wait before checking the sensor state again
-        Timer::after(Duration::from_secs(1)).await;
+/// Parses the `Display: Ok` or `Display: Fault` reply and logs changes.
+fn update_display_health(reply: &[u8]) {
+    let fault = match core::str::from_utf8(reply).map(|r| r.trim()) {
+        Ok("Display: Ok") => false,
+        Ok("Display: Fault") => true,
+        _ => {
+            warn!("Unknown display reply");
+            return;
+        }
+    };
+
+    if DISPLAY_FAULT.swap(fault, Ordering::Relaxed) != fault {
+        if fault {
+            warn!("Display board reports a display fault, running headless");
+        } else {
+            info!("Display board display is working");
+        }
     }
 }
 
-#[embassy_executor::main]
-async fn main(spawner: Spawner) {
+/// The barrier servo with its two positions and the state LEDs.
+struct Barrier {
+    servo: Pwm<'static>,
+    servo_config: PwmConfig,
+    open_pulse: u16,
+    closed_pulse: u16,
+    led_open: Output<'static>,
+    led_closed: Output<'static>,
+}
 
-    let peripherals = embassy_rp::init(Default::default());
+impl Barrier {
+    fn set_open(&mut self, open: bool) {
+        self.servo_config.compare_a = if open { self.open_pulse } else { self.closed_pulse };
+        self.servo.set_config(&self.servo_config);
 
-    // Barrier LED pins
-    let mut barrier_led_open = Output::new(peripherals.PIN_16, Level::Low);
-    let mut barrier_led_closed = Output::new(peripherals.PIN_17, Level::High);
+        // Green LED while open, red while closed
+        self.led_open.set_level(open.into());
+        self.led_closed.set_level((!open).into());
+    }
 
-    // Init WiFi driver
-    let (net_device, mut control) = embassy_lab_utils::init_wifi!(&spawner, peripherals).await;
+    /// Switches both LEDs together, the servo stays where it is.
+    fn set_leds(&mut self, on: bool) {
+        self.led_open.set_level(on.into());
+        self.led_closed.set_level(on.into());
+    }
+}
 
-    // Default config for dynamic IP address
-    let config = embassy_net::Config::dhcpv4(Default::default());
+/// Runs the barrier commands one after the other, so an open command is
+/// finished before the next command is looked at. An emergency goes first.
+#[embassy_executor::task]
+async fn barrier_task(mut barrier: Barrier, heartbeat: Heartbeat) {
+    let mut control = Control::restore(read_latch());
+    let mut leds_on = false;
 
-    // Init network stack
-    let stack = embassy_lab_utils::init_network_stack(&spawner, net_device, &RESOURCES, config);
+    // Start closed with the red LED on, or open again if the board restarted
+    // during an emergency
+    if control.is_emergency() {
+        warn!("Restarted during an emergency");
+        barrier.set_open(true);
+        set_barrier_state(BarrierState::Emergency);
+    } else {
+        barrier.set_open(false);
+        set_barrier_state(BarrierState::Closed);
+    }
+
+    loop {
+        heartbeat.beat();
+        let timeout = if control.is_emergency() { EMERGENCY_FLASH_INTERVAL } else { HEARTBEAT_INTERVAL };
+        let (command, reply) = match with_timeout(timeout, select(EMERGENCY.wait(), BARRIER_COMMANDS.receive())).await {
+            Ok(Either::First(())) => {
+                start_emergency(&mut barrier, &mut control);
+                continue;
+            }
+            Ok(Either::Second(request)) => request,
+            Err(_) => {
+                if control.is_emergency() {
+                    leds_on = !leds_on;
+                    barrier.set_leds(leds_on);
+                }
+                continue;
+            }
+        };
+        run_barrier_command(&mut barrier, &mut control, command).await;
+        if reply {
+            BARRIER_REPLY.signal(BARRIER_STATE.lock(|s| s.get()));
+        }
+    }
+}
+
+/// Opens and holds the barrier whether locked or not, until an operator
+/// clears the emergency.
+fn start_emergency(barrier: &mut Barrier, control: &mut Control) {
+    if !control.start_emergency() {
+        return;
+    }
+    write_latch(control.latch());
+    barrier.set_open(true);
+    set_barrier_state(BarrierState::Emergency);
+    warn!("Emergency, barrier held open");
+    log_event(Event::EmergencyStarted);
+}
+
+/// Opens, locks or unlocks the barrier. A toggle goes by the lock state the
+/// barrier task knows. During an emergency the barrier stays open, the lock
+/// only takes effect once the emergency is cleared.
+async fn run_barrier_command(barrier: &mut Barrier, control: &mut Control, command: BarrierCommand) {
+    match command {
+        BarrierCommand::Open => match control.open() {
+            Opening::HeldOpen => info!("Barrier held open for the emergency"),
+            Opening::Locked => {
+                info!("Barrier is locked. Cannot open.");
+                DENIED_OPENINGS.fetch_add(1, Ordering::Relaxed);
+                log_event(Event::CommandRejected);
+            }
+            Opening::Open => {
+                barrier.set_open(true);
+                info!("Barrier opened");
+                log_event(Event::BarrierOpened);
+                OPENINGS.fetch_add(1, Ordering::Relaxed);
+                OCCUPANCY.lock(|o| o.borrow_mut().record_gate_passage(Instant::now()));
+                set_barrier_state(BarrierState::Open);
+
+                // Close the barrier again on its own, unless an emergency
+                // keeps it open
+                let open_secs = SETTINGS.lock(|s| s.get()).barrier_open_secs;
+                let open_time = Duration::from_secs(open_secs as u64);
+                if let Either::Second(()) = select(Timer::after(open_time), EMERGENCY.wait()).await {
+                    start_emergency(barrier, control);
+                    return;
+                }
+                barrier.set_open(false);
+                info!("Barrier closed automatically");
+                log_event(Event::BarrierClosed);
+                set_barrier_state(BarrierState::Closed);
+            }
+        },
+        BarrierCommand::ClearEmergency => {
+            if control.clear_emergency() {
+                write_latch(control.latch());
+                barrier.set_open(false);
+                set_barrier_state(control.state());
+                info!("Emergency cleared");
+                log_event(Event::EmergencyCleared);
+            }
+        }
+        BarrierCommand::ToggleLock | BarrierCommand::Lock | BarrierCommand::Unlock => {
+            match control.set_lock(command) {
+                None => info!("Barrier already {}", if control.is_locked() { "locked" } else { "unlocked" }),
+                Some(true) => {
+                    info!("Barrier locked");
+                    log_event(Event::BarrierLocked);
+                }
+                Some(false) => {
+                    info!("Barrier unlocked");
+                    log_event(Event::BarrierUnlocked);
+                }
+            }
+            set_barrier_state(control.state());
+        }
+    }
+}
+
+/// Starts an emergency when the emergency input goes low, from a button or
+/// the contact of an alarm system. Only an operator clears it, once the input
+/// is released.
+#[embassy_executor::task]
+async fn emergency_input_task(mut input: Input<'static>) {
+    loop {
+        input.wait_for_low().await;
+        Timer::after(EMERGENCY_DEBOUNCE).await;
+        if input.is_high() {
+            continue;
+        }
+        EMERGENCY_INPUT.store(true, Ordering::Relaxed);
+        warn!("Emergency input active");
+        EMERGENCY.signal(());
+
+        // Ignore bounces on the way back
+        loop {
+            input.wait_for_high().await;
+            Timer::after(EMERGENCY_DEBOUNCE).await;
+            if input.is_high() {
+                break;
+            }
+        }
+        EMERGENCY_INPUT.store(false, Ordering::Relaxed);
+        info!("Emergency input released");
+    }
+}
+
+/// Whether `key` is [`OPERATOR_KEY`], compared without returning early.
+/// Always false when built without one.
+fn is_operator(key: Option<&str>) -> bool {
+    let (Some(key), Some(operator_key)) = (key, OPERATOR_KEY) else {
+        return false;
+    };
+    key.len() == operator_key.len()
+        && key.bytes().zip(operator_key.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Serves the dashboard, `GET /status` and the barrier commands, one
+/// connection at a time.
+#[embassy_executor::task]
+async fn http_task(stack: Stack<'static>, log: &'static SharedEventLog) {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 2048];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(HTTP_TIMEOUT));
+
+        if let Err(e) = socket.accept(http::HTTP_PORT).await {
+            warn!("HTTP accept error: {:?}", e);
+            continue;
+        }
+
+        if let Err(e) = serve_http(&mut socket, log).await {
+            warn!("HTTP write error: {:?}", e);
+        }
+
+        socket.close();
+        let _ = socket.flush().await;
+    }
+}
+
+/// Reads one request and answers it.
+async fn serve_http(socket: &mut TcpSocket<'_>, log: &SharedEventLog) -> Result<(), embassy_net::tcp::Error> {
+    // Read until the header is complete or the buffer is full
+    let mut buf = [0; 512];
+    let mut len = 0;
+    while len < buf.len() && http::parse_request(&buf[..len]) == Err(http::ParseError::Incomplete) {
+        match socket.read(&mut buf[len..]).await {
+            Ok(0) | Err(_) => return Ok(()),
+            Ok(n) => len += n,
+        }
+    }
+
+    let request = match http::parse_request(&buf[..len]) {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("Bad HTTP request: {}", e);
+            return respond(socket, StatusCode::BadRequest, "text/plain", b"Bad request\n").await;
+        }
+    };
+
+    match http::route(&request) {
+        Route::Dashboard => respond(socket, StatusCode::Ok, "text/html", http::DASHBOARD.as_bytes()).await,
+        Route::Status => {
+            let spots = OCCUPANCY.lock(|o| o.borrow().snapshot()).spots;
+            let now = Instant::now();
+            let (stats, spot_stats, parked_secs) = SESSIONS.lock(|s| {
+                let s = s.borrow();
+                (s.stats(now), *s.spot_stats(), s.parked_secs(now))
+            });
+            let reserved = RESERVED.lock(|r| r.get());
+            let reports = [
+                ("main", Some(crash::last())),
+                ("display", announce::last_reset("display")),
+                ("ir", announce::last_reset("ir")),
+            ];
+            let resets: Vec<BoardReset, 3> = reports
+                .iter()
+                .filter_map(|(board, report)| {
+                    let report = report.as_ref()?;
+                    Some(BoardReset {
+                        board,
+                        cause: report.cause.as_str(),
+                        uptime_secs: report.uptime_secs,
+                        detail: &report.detail,
+                    })
+                })
+                .collect();
+            let report = StatusReport {
+                spots: &spots,
+                reserved: &reserved,
+                barrier: BARRIER_STATE.lock(|s| s.get()),
+                openings: OPENINGS.load(Ordering::Relaxed),
+                denied_openings: DENIED_OPENINGS.load(Ordering::Relaxed),
+                ir_last_seen_secs: IR_LAST_SEEN.lock(|s| s.get()).map(|at| (Instant::now() - at).as_secs()),
+                display_fault: DISPLAY_FAULT.load(Ordering::Relaxed),
+                resets: &resets,
+                utc_ms: sntp::now_utc().map(|utc| utc.as_unix_millis()),
+                stats,
+                spot_stats: &spot_stats,
+                parked_secs: &parked_secs,
+            };
+            let mut body: String<{ StatusReport::MAX_JSON }> = String::new();
+            if report.write_json(&mut body).is_err() {
+                warn!("Status report too long");
+                return respond(socket, StatusCode::InternalServerError, "text/plain", b"Status too long\n").await;
+            }
+            respond(socket, StatusCode::Ok, "application/json", body.as_bytes()).await
+        }
+        Route::Sessions => serve_sessions(socket).await,
+        Route::Reservations => serve_reservations(socket).await,
+        Route::Events(format) => serve_events(socket, log, format).await,
+        Route::Barrier(_) if !is_operator(request.token) => {
+            warn!("HTTP barrier command with a wrong key");
+            respond(socket, StatusCode::Unauthorized, "text/plain", b"Wrong key\n").await
+        }
+        Route::Barrier(BarrierCommand::Open) => {
+            info!("HTTP barrier command: Open");
+            match open_barrier(Opener::Operator) {
+                Ok(()) => respond(socket, StatusCode::Accepted, "application/json", b"{\"queued\":true}").await,
+                Err(OpenError::Refused(refusal)) => {
+                    let mut body: String<64> = String::new();
+                    let _ = writeln!(body, "Error: {}", refusal.as_str());
+                    respond(socket, StatusCode::Forbidden, "text/plain", body.as_bytes()).await
+                }
+                Err(OpenError::Busy) => {
+                    respond(socket, StatusCode::ServiceUnavailable, "text/plain", b"Barrier busy\n").await
+                }
+            }
+        }
+        Route::Barrier(command) => {
+            info!("HTTP barrier command: {}", command);
+            if BARRIER_COMMANDS.try_send((command, false)).is_ok() {
+                respond(socket, StatusCode::Accepted, "application/json", b"{\"queued\":true}").await
+            } else {
+                respond(socket, StatusCode::ServiceUnavailable, "text/plain", b"Barrier busy\n").await
+            }
+        }
+        Route::Emergency(on) => {
+            if !is_operator(request.token) {
+                warn!("HTTP emergency command with a wrong key");
+                return respond(socket, StatusCode::Unauthorized, "text/plain", b"Wrong key\n").await;
+            }
+            if on {
+                info!("HTTP emergency");
+                EMERGENCY.signal(());
+                respond(socket, StatusCode::Ok, "application/json", b"{\"emergency\":true}").await
+            } else if EMERGENCY_INPUT.load(Ordering::Relaxed) {
+                respond(socket, StatusCode::Conflict, "text/plain", b"Emergency input active\n").await
+            } else if BARRIER_COMMANDS.try_send((BarrierCommand::ClearEmergency, false)).is_ok() {
+                respond(socket, StatusCode::Accepted, "application/json", b"{\"queued\":true}").await
+            } else {
+                respond(socket, StatusCode::ServiceUnavailable, "text/plain", b"Barrier busy\n").await
+            }
+        }
+        Route::Config => {
+            let mut body: String<256> = String::new();
+            let _ = SETTINGS.lock(|s| s.get()).write_json(&mut body);
+            respond(socket, StatusCode::Ok, "application/json", body.as_bytes()).await
+        }
+        Route::SetConfig(key, value) => {
+            if !is_operator(request.token) {
+                warn!("HTTP setting change with a wrong key");
+                return respond(socket, StatusCode::Unauthorized, "text/plain", b"Wrong key\n").await;
+            }
+            let result = SETTINGS.lock(|s| {
+                let mut settings = s.get();
+                settings.set(key, value)?;
+                s.set(settings);
+                Ok(())
+            });
+            match result {
+                Ok(()) => {
+                    info!("Setting {} changed to {}", key.as_str(), value);
+                    respond(socket, StatusCode::Ok, "application/json", b"{\"changed\":true}").await
+                }
+                Err(reason) => {
+                    let mut body: String<64> = String::new();
+                    let _ = writeln!(body, "Error: {}", reason);
+                    respond(socket, StatusCode::BadRequest, "text/plain", body.as_bytes()).await
+                }
+            }
+        }
+        Route::BadValue => respond(socket, StatusCode::BadRequest, "text/plain", b"Error: not a number\n").await,
+        Route::NotFound => respond(socket, StatusCode::NotFound, "text/plain", b"Not found\n").await,
+        Route::MethodNotAllowed => {
+            respond(socket, StatusCode::MethodNotAllowed, "text/plain", b"Method not allowed\n").await
+        }
+    }
+}
+
+async fn respond(
+    socket: &mut TcpSocket<'_>,
+    status: StatusCode,
+    content_type: &str,
+    body: &[u8],
+) -> Result<(), embassy_net::tcp::Error> {
+    socket.write_all(http::response_head(status, content_type, Some(body.len())).as_bytes()).await?;
+    socket.write_all(body).await
+}
+
+/// Streams the last finished sessions as a JSON array, the oldest first.
+async fn serve_sessions(socket: &mut TcpSocket<'_>) -> Result<(), embassy_net::tcp::Error> {
+    let recent: Vec<Session, { sessions::MAX_RECENT }> = SESSIONS.lock(|s| s.borrow().recent().copied().collect());
+
+    socket.write_all(http::response_head(StatusCode::Ok, "application/json", None).as_bytes()).await?;
+    socket.write_all(b"[").await?;
+
+    let mut line: String<192> = String::new();
+    for (i, session) in recent.iter().enumerate() {
+        line.clear();
+        if i > 0 {
+            let _ = line.push(',');
+        }
+        let _ = session.write_json(&mut line, |at| sntp::to_utc(at).map(|utc| utc.as_unix_millis()));
+        socket.write_all(line.as_bytes()).await?;
+    }
+
+    socket.write_all(b"]\n").await
+}
+
+/// Streams the reservations as a JSON array.
+async fn serve_reservations(socket: &mut TcpSocket<'_>) -> Result<(), embassy_net::tcp::Error> {
+    let list = RESERVATIONS.lock(|r| r.borrow().clone());
+    let now = sntp::now_utc().map(|utc| utc.as_unix_secs());
+
+    socket.write_all(http::response_head(StatusCode::Ok, "application/json", None).as_bytes()).await?;
+    socket.write_all(b"[").await?;
+
+    let mut line: String<128> = String::new();
+    for (i, reservation) in list.iter().enumerate() {
+        line.clear();
+        if i > 0 {
+            let _ = line.push(',');
+        }
+        let _ = reservation.write_json(&mut line, now);
+        socket.write_all(line.as_bytes()).await?;
+    }
+
+    socket.write_all(b"]\n").await
+}
+
+/// Streams the event log from the oldest record. The log is only locked
+/// while a batch of records is read, so events keep being written during a
+/// long download.
+async fn serve_events(
+    socket: &mut TcpSocket<'_>,
+    log: &SharedEventLog,
+    format: EventFormat,
+) -> Result<(), embassy_net::tcp::Error> {
+    socket.write_all(http::response_head(StatusCode::Ok, format.content_type(), None).as_bytes()).await?;
+    socket.write_all(match format {
+        EventFormat::Csv => Record::CSV_HEADER.as_bytes(),
+        EventFormat::Json => b"[",
+    }).await?;
+
+    let mut cursor = log.lock().await.cursor();
+    let mut line: String<128> = String::new();
+    let mut first = true;
+    let mut done = false;
+    while !done {
+        let mut batch: Vec<Record, EVENT_BATCH> = Vec::new();
+        {
+            let mut log = log.lock().await;
+            while !batch.is_full() {
+                match log.read(&mut cursor) {
+                    Ok(Some(record)) => {
+                        let _ = batch.push(record);
+                    }
+                    Ok(None) => {
+                        done = true;
+                        break;
+                    }
+                    Err(e) => {
+                        warn!("Event log read failed: {:?}", e);
+                        done = true;
+                        break;
+                    }
+                }
+            }
+        }
+
+        for record in &batch {
+            line.clear();
+            let _ = match format {
+                EventFormat::Csv => record.write_csv(&mut line),
+                EventFormat::Json => {
+                    if !first {
+                        let _ = line.push(',');
+                    }
+                    record.write_json(&mut line)
+                }
+            };
+            first = false;
+            socket.write_all(line.as_bytes()).await?;
+        }
+    }
+
+    if format == EventFormat::Json {
+        socket.write_all(b"]\n").await?;
+    }
+    Ok(())
+}
+
+/// Queues an event for the flash log.
+fn log_event(event: Event) {
+    if EVENTS.try_send((Instant::now(), event)).is_err() {
+        warn!("Event log queue full, dropping {}", event);
+    }
+}
+
+fn log_spot_change(spot_no: u8, state: SpotState) {
+    log_event(match state {
+        SpotState::Occupied => Event::SpotOccupied(spot_no),
+        SpotState::Free => Event::SpotFreed(spot_no),
+    });
+}
+
+/// Writes the queued events to flash and records WiFi link losses.
+#[embassy_executor::task]
+async fn event_log_task(log: &'static SharedEventLog) {
+    let mut connectivity = unwrap!(wifi::receiver());
 
-    //Connect to WiFi
     loop {
-        match control.join(WIFI_NETWORK, JoinOptions::new(WIFI_PASSWORD.as_bytes())).await {
+        let (at, event) = match select(EVENTS.receive(), connectivity.changed()).await {
+            Either::First(queued) => queued,
+            Either::Second(Connectivity::Down) => (Instant::now(), Event::LinkLost(Peer::Wifi)),
+            Either::Second(Connectivity::Up(_)) => continue,
+        };
+
+        // Wall-clock time once synced, events from before only know the uptime
+        let time = match sntp::to_utc(at) {
+            Some(utc) => Timestamp::Utc(utc.as_unix_millis()),
+            None => Timestamp::Uptime(at.as_millis()),
+        };
+
+        match log.lock().await.append(time, event) {
+            Ok(record) => info!("Logged {}", record),
+            Err(e) => warn!("Event log write failed: {:?}", e),
+        }
+    }
+}
+
+/// Serves one client of TCP port 6000 at a time, one task per client served
+/// at once.
+#[embassy_executor::task(pool_size = COMMAND_TASKS.len())]
+async fn command_task(stack: Stack<'static>, heartbeat: Heartbeat) {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+
+    loop {
+        heartbeat.beat();
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+
+        // Keep the same accept going, so the socket listens all along
+        let accepted = {
+            let mut accept = pin!(socket.accept(6000));
+            loop {
+                match with_timeout(HEARTBEAT_INTERVAL, &mut accept).await {
+                    Ok(result) => break result,
+                    Err(_) => heartbeat.beat(),
+                }
+            }
+        };
+        if let Err(e) = accepted {
+            warn!("accept error: {:?}", e);
+            continue;
+        }
+
+        info!("Received connection from {:?}", socket.remote_endpoint());
+        serve_commands(&mut socket, &heartbeat).await;
+        socket.abort();
+        let _ = socket.flush().await;
+    }
+}
+
+/// Runs the commands of one connection until the client closes it.
+async fn serve_commands(socket: &mut TcpSocket<'_>, heartbeat: &Heartbeat) {
+    // The IR board may connect before it announced itself, so this is checked
+    // again for every read
+    let mut from_ir_board = is_ir_board(socket);
+    if from_ir_board {
+        IR_LAST_SEEN.lock(|s| s.set(Some(Instant::now())));
+    }
+    let mut lines = LineBuffer::new();
+    let mut buf = [0; 256];
+
+    loop {
+        heartbeat.beat();
+
+        // Read data from the socket, the IR board stays connected and idle
+        // between keys
+        let read = match with_timeout(HEARTBEAT_INTERVAL, socket.read(&mut buf)).await {
+            Ok(read) => read,
+            Err(_) => continue,
+        };
+        let n = match read {
+            Ok(0) => {
+                warn!("read EOF");
+                if from_ir_board {
+                    log_event(Event::LinkLost(Peer::Ir));
+                }
+                return;
+            }
+            Ok(n) => n,
+            Err(e) => {
+                warn!("read error: {:?}", e);
+                if from_ir_board {
+                    log_event(Event::LinkLost(Peer::Ir));
+                }
+                return;
+            }
+        };
+
+        from_ir_board = is_ir_board(socket);
+        if from_ir_board {
+            IR_LAST_SEEN.lock(|s| s.set(Some(Instant::now())));
+        }
+
+        // One command per line, a line may take several reads
+        for &byte in &buf[..n] {
+            let line = match lines.push(byte) {
+                None => continue,
+                Some(Ok(line)) => line,
+                Some(Err(LineError::TooLong)) => {
+                    send_reply(socket, Err("line too long")).await;
+                    continue;
+                }
+                Some(Err(LineError::NotText)) => {
+                    warn!("Command line that isn't text from {:?}", socket.remote_endpoint());
+                    continue;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let result = match commands::parse(&line) {
+                Ok(command) if command.is_remote_key() && !from_ir_board => {
+                    warn!("Remote command from {:?} ignored, not the IR board", socket.remote_endpoint());
+                    continue;
+                }
+                Ok(command) => {
+                    let expects_reply = command.expects_reply();
+                    let result = run_command(command).await;
+                    if !expects_reply {
+                        continue;
+                    }
+                    result
+                }
+                Err(commands::ParseError::Malformed) => Err("malformed command"),
+                Err(commands::ParseError::Unknown) => {
+                    warn!("Unknown command received: {}", line.as_str());
+                    continue;
+                }
+            };
+            send_reply(socket, result).await;
+        }
+    }
+}
+
+/// Whether the client is the IR board, the only one sending the keys of the
+/// remotes.
+fn is_ir_board(socket: &TcpSocket<'_>) -> bool {
+    match socket.remote_endpoint() {
+        Some(IpEndpoint { addr: IpAddress::Ipv4(ip), .. }) => IR_BOARD_IP.or_else(|| announce::peer("ir")) == Some(ip),
+        _ => false,
+    }
+}
+
+async fn send_reply(socket: &mut TcpSocket<'_>, result: Result<Reply, &'static str>) {
+    let mut reply: String<64> = String::new();
+    let _ = match result {
+        Ok(Reply::Done) => writeln!(reply, "Ok"),
+        Ok(Reply::Fee(fee)) => writeln!(reply, "Ok {}", fee),
+        Ok(Reply::Barrier(state)) => writeln!(reply, "Ok {}", state.as_str()),
+        Err(reason) => writeln!(reply, "Error: {}", reason),
+    };
+    if let Err(e) = socket.write_all(reply.as_bytes()).await {
+        warn!("write error: {:?}", e);
+    }
+}
+
+/// What a command answers besides `Ok`.
+enum Reply {
+    Done,
+    Fee(u32),
+    Barrier(BarrierState),
+}
+
+/// Carries out a command from TCP port 6000, the reply and the error are sent
+/// back. Everything but the remote keys and `State` needs the operator key.
+async fn run_command(command: Command) -> Result<Reply, &'static str> {
+    if command.key().is_some_and(|key| !is_operator(Some(key))) {
+        warn!("Command with a wrong key");
+        return Err("wrong key");
+    }
+
+    match command {
+        Command::RemoteOpen(remote) => {
+            let _ = open_barrier(Opener::Remote(remote.as_deref().unwrap_or("unknown")));
+            Ok(Reply::Done)
+        }
+        Command::Barrier(command) => {
+            BARRIER_COMMANDS.send((command, false)).await;
+            Ok(Reply::Done)
+        }
+        Command::Lock(lock, _) => {
+            let command = if lock { BarrierCommand::Lock } else { BarrierCommand::Unlock };
+            BARRIER_REPLY.reset();
+            BARRIER_COMMANDS.send((command, true)).await;
+            Ok(Reply::Barrier(BARRIER_REPLY.wait().await))
+        }
+        Command::State => Ok(Reply::Barrier(BARRIER_STATE.lock(|s| s.get()))),
+        Command::Emergency(on, _) => {
+            if on {
+                EMERGENCY.signal(());
+                return Ok(Reply::Done);
+            }
+            if EMERGENCY_INPUT.load(Ordering::Relaxed) {
+                return Err("emergency input active");
+            }
+            BARRIER_REPLY.reset();
+            BARRIER_COMMANDS.send((BarrierCommand::ClearEmergency, true)).await;
+            Ok(Reply::Barrier(BARRIER_REPLY.wait().await))
+        }
+        Command::Reserve { spot, holder, window, .. } => {
+            let now = sntp::now_utc().ok_or(ReservationError::Unsynced.as_str())?.as_unix_secs();
+            let (start, end) = window.resolve(now);
+            let reservation = Reservation { spot, holder, start, end };
+            let lot = OCCUPANCY.lock(|o| o.borrow().snapshot()).with_reserved(RESERVED.lock(|r| r.get()));
+            RESERVATIONS
+                .lock(|r| r.borrow_mut().add(reservation, now, &lot))
+                .map_err(|e| e.as_str())?;
+            info!("Spot {} reserved from {} to {}", spot, start, end);
+            RESERVATIONS_CHANGED.signal(());
+            Ok(Reply::Done)
+        }
+        Command::CancelReservation(spot, _) => {
+            if RESERVATIONS.lock(|r| r.borrow_mut().cancel(spot)) == 0 {
+                return Err("no reservation");
+            }
+            info!("Reservations of spot {} cancelled", spot);
+            RESERVATIONS_CHANGED.signal(());
+            Ok(Reply::Done)
+        }
+        Command::Pay(spot, _) => {
+            let departure = SESSIONS.lock(|s| s.borrow().departure(spot)).ok_or("no departure")?;
+            let fee = session_fee(&departure.session).ok_or("clock unsynced")?;
+            SESSIONS.lock(|s| s.borrow_mut().pay(spot));
+            info!("Spot {} paid {} cents", spot, fee);
+            log_event(Event::FeePaid(spot));
+            Ok(Reply::Fee(fee))
+        }
+        Command::Exit(spot, _) => {
+            open_barrier(Opener::Exit(spot)).map_err(|e| e.as_str())?;
+            Ok(Reply::Done)
+        }
+    }
+}
+
+/// Why [`open_barrier`] didn't open the barrier.
+enum OpenError {
+    Refused(Refusal),
+    /// Too many barrier commands queued.
+    Busy,
+}
+
+impl OpenError {
+    fn as_str(&self) -> &'static str {
+        match self {
+            OpenError::Refused(refusal) => refusal.as_str(),
+            OpenError::Busy => "barrier busy",
+        }
+    }
+}
+
+/// Opens the barrier for `opener` if the [`gate`] lets it. Every open
+/// request goes through here, whatever it came over.
+fn open_barrier(opener: Opener) -> Result<(), OpenError> {
+    let now = Instant::now();
+    let departures: Vec<Departure, SPOT_COUNT> = SESSIONS.lock(|s| s.borrow().departures().collect());
+    let waiting: Vec<Waiting, SPOT_COUNT> = departures
+        .iter()
+        .map(|departure| Waiting {
+            spot: departure.session.spot,
+            paid: departure.paid,
+            fee: session_fee(&departure.session),
+            left_secs: now.saturating_duration_since(departure.session.end).as_secs(),
+        })
+        .collect();
+    let lot = OCCUPANCY.lock(|o| o.borrow().snapshot()).with_reserved(RESERVED.lock(|r| r.get()));
+    // Holders of the reserved spots that are still free
+    let held: Vec<Holder, MAX_RESERVATIONS> = match sntp::now_utc() {
+        Some(utc) => RESERVATIONS.lock(|r| {
+            r.borrow()
+                .active(utc.as_unix_secs())
+                .filter(|r| lot.spots[r.spot as usize - 1] == SpotState::Free)
+                .map(|r| r.holder.clone())
+                .collect()
+        }),
+        None => Vec::new(),
+    };
+    let held_for: Vec<&str, MAX_RESERVATIONS> = held.iter().map(|h| h.as_str()).collect();
+    let gate = Gate {
+        policy: &ACCESS_POLICY,
+        now: local_time(),
+        waiting: &waiting,
+        exit_window_secs: EXIT_WINDOW.as_secs(),
+        free_spots: lot.free_spaces(),
+        held_for: &held_for,
+    };
+
+    let result = gate.check(&opener);
+    if let Opener::Remote(id) = opener {
+        let address = access::remote_address(id);
+        match result {
+            Err(Refusal::Access(reason)) => {
+                warn!("Access denied to {}: {}", id, reason);
+                log_event(Event::AccessDenied(reason, address));
+            }
+            _ => {
+                info!("Access granted to {}", id);
+                log_event(Event::AccessGranted(address));
+            }
+        }
+    }
+
+    let pass = match result {
+        Ok(pass) => pass,
+        Err(refusal) => {
+            warn!("Barrier kept closed for {}: {}", opener, refusal.as_str());
+            if let Refusal::Unpaid(spot) = refusal {
+                log_event(Event::ExitUnpaid(spot));
+            }
+            return Err(OpenError::Refused(refusal));
+        }
+    };
+    if BARRIER_COMMANDS.try_send((BarrierCommand::Open, false)).is_err() {
+        warn!("Barrier busy, dropping the opening for {}", opener);
+        return Err(OpenError::Busy);
+    }
+
+    match pass {
+        Pass::Open => {}
+        Pass::Exit(spot) => SESSIONS.lock(|s| s.borrow_mut().exited(spot)),
+        Pass::ExitUncharged(spot) => {
+            warn!("Spot {} let out uncharged, the clock isn't synced", spot);
+            log_event(Event::ExitUncharged(spot));
+            SESSIONS.lock(|s| s.borrow_mut().exited(spot));
+        }
+    }
+    Ok(())
+}
+
+/// Fee of a finished session, `None` if it can't be told yet. Only stays
+/// within the grace period can be charged without the clock, the night hours
+/// need the time of day.
+fn session_fee(session: &Session) -> Option<u32> {
+    let tariff = SETTINGS.lock(|s| s.get()).tariff;
+    if tariff.is_grace(session.duration().as_secs()) {
+        return Some(0);
+    }
+    let entry = to_local(session.start)?;
+    let exit = to_local(session.end)?;
+    Some(tariff.fee(entry.as_unix_secs(), exit.as_unix_secs()))
+}
+
+/// `at` in the local time zone, `None` until the clock is synced.
+fn to_local(at: Instant) -> Option<UtcTime> {
+    let utc = sntp::to_utc(at)?;
+    let offset_minutes = SETTINGS.lock(|s| s.get()).utc_offset_minutes;
+    Some(UtcTime::from_unix_millis(utc.as_unix_millis().checked_add_signed(offset_minutes * 60_000)?))
+}
+
+/// Local time for the access schedules, `None` until the clock is synced.
+fn local_time() -> Option<LocalTime> {
+    let local = to_local(Instant::now())?;
+    let (year, month, day, hour, minute, _) = local.to_civil();
+
+    Some(LocalTime {
+        date: Date::new(year as u16, month, day),
+        weekday: Weekday::from_days_since_epoch(local.as_unix_secs() / 86_400),
+        minute: hour as u16 * 60 + minute as u16,
+    })
+}
+
+/// Saves the reservations after every change, drops the ones that ran out
+/// and keeps the reserved spots up to date.
+#[embassy_executor::task]
+async fn reservation_task(mut store: ReservationStore<FlashPartition>) {
+    loop {
+        let changed = with_timeout(RESERVATION_CHECK_INTERVAL, RESERVATIONS_CHANGED.wait()).await.is_ok();
+        let now = sntp::now_utc().map(|utc| utc.as_unix_secs());
+
+        let (expired, list) = RESERVATIONS.lock(|r| {
+            let mut r = r.borrow_mut();
+            let expired = now.is_some_and(|now| r.expire(now));
+            (expired, r.clone())
+        });
+
+        if changed || expired {
+            match store.save(&list) {
+                Ok(()) => info!("Reservations saved"),
+                Err(e) => warn!("Saving the reservations failed: {:?}", e),
+            }
+        }
+
+        // Without the time nothing is known to be reserved
+        let reserved = now.map(|now| list.reserved(now)).unwrap_or_default();
+        if RESERVED.lock(|r| r.replace(reserved)) != reserved {
+            info!("Reserved spots: {}", reserved);
+            state_changed();
+        }
+    }
+}
+
+#[derive(Debug, defmt::Format)]
+enum MqttError {
+    Io(embassy_net::tcp::Error),
+    Closed,
+    /// The broker refused the connection with this CONNACK return code.
+    Refused(u8),
+    Decode(mqtt::DecodeError),
+    /// A packet didn't fit [`mqtt::MAX_PACKET`].
+    Encode,
+    /// A publish wasn't acknowledged within [`MQTT_ACK_TIMEOUT`].
+    NoAck,
+}
+
+/// What the broker was last told, `None` until published in this session.
+struct MqttPublished {
+    spots: [Option<SpotState>; SPOT_COUNT],
+    barrier: Option<BarrierState>,
+}
+
+/// Publishes the spots and the barrier to the MQTT broker and takes barrier
+/// commands from it, connecting again whenever the connection drops.
+#[embassy_executor::task]
+async fn mqtt_task(stack: Stack<'static>, broker: Ipv4Address) {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(MQTT_KEEP_ALIVE * 2));
+
+        match socket.connect(IpEndpoint::new(IpAddress::Ipv4(broker), mqtt::MQTT_PORT)).await {
             Ok(_) => {
-                info!("Successfully joined WiFi network: {}", WIFI_NETWORK);
-
-                info!("Waiting for DHCP...");
-                loop {
-                    if stack.is_config_up() {
-                        if let Some(ip_config) = stack.config_v4() {
-                            let ip = ip_config.address.address(); 
-                            info!("Assigned IP address: {}", ip);
-                            break; 
+                info!("Connected to MQTT broker {}", broker);
+                if let Err(e) = mqtt_session(&mut socket).await {
+                    warn!("MQTT session ended: {}", e);
+                }
+            }
+            Err(e) => warn!("MQTT connect error: {:?}", e),
+        }
+
+        socket.abort();
+        let _ = socket.flush().await;
+        Timer::after(MQTT_RETRY_INTERVAL).await;
+    }
+}
+
+/// Runs one broker connection until it fails.
+///
+/// State is published retained with QoS 1. A publish the broker doesn't
+/// acknowledge within [`MQTT_ACK_TIMEOUT`] ends the session, and every new
+/// session publishes the whole state again. Barrier commands need the
+/// operator key, see [`mqtt::parse_command`].
+async fn mqtt_session(socket: &mut TcpSocket<'_>) -> Result<(), MqttError> {
+    let mut client_id: String<32> = String::new();
+    let _ = core::write!(client_id, "parking-{}", MQTT_LOT);
+    let mut command_topic: String<64> = String::new();
+    let _ = core::write!(command_topic, "parking/{}/command", MQTT_LOT);
+
+    // The broker accepts packets right after CONNECT, a refusal shows up as CONNACK
+    mqtt_send(socket, mqtt::connect(&client_id, MQTT_KEEP_ALIVE.as_secs() as u16)).await?;
+    mqtt_send(socket, mqtt::subscribe(&command_topic, QoS::AtLeastOnce, mqtt::SUBSCRIBE_ID)).await?;
+
+    let mut published = MqttPublished {
+        spots: [None; SPOT_COUNT],
+        barrier: None,
+    };
+    let mut unacked = mqtt::Unacked::<MQTT_IN_FLIGHT>::new();
+    let mut rx = [0; mqtt::MAX_PACKET];
+    let mut len = 0;
+    let mut ping = Ticker::every(MQTT_KEEP_ALIVE / 2);
+
+    loop {
+        mqtt_publish_changes(socket, &mut published, &mut unacked).await?;
+        // Checked on every wake up, at least every ping
+        if unacked.overdue(Instant::now(), MQTT_ACK_TIMEOUT) {
+            return Err(MqttError::NoAck);
+        }
+
+        match select3(MQTT_CHANGED.wait(), socket.read(&mut rx[len..]), ping.next()).await {
+            Either3::First(_) => {}
+            Either3::Second(Ok(0)) => return Err(MqttError::Closed),
+            Either3::Second(Ok(n)) => len += n,
+            Either3::Second(Err(e)) => return Err(MqttError::Io(e)),
+            Either3::Third(_) => mqtt_send(socket, mqtt::pingreq()).await?,
+        }
+
+        // Handle every complete packet, keep the start of the next one
+        loop {
+            let (packet, n) = match mqtt::decode(&rx[..len]) {
+                Ok(decoded) => decoded,
+                Err(mqtt::DecodeError::Incomplete) => break,
+                Err(e) => return Err(MqttError::Decode(e)),
+            };
+
+            match packet {
+                mqtt::Packet::ConnAck { return_code } if return_code != 0 => {
+                    return Err(MqttError::Refused(return_code));
+                }
+                mqtt::Packet::Publish { topic, payload, packet_id } if topic == command_topic.as_str() => {
+                    match mqtt::parse_command(payload) {
+                        Some((command, key)) if !is_operator(Some(key)) => {
+                            warn!("MQTT barrier command {} with a wrong key", command);
+                        }
+                        Some((BarrierCommand::Open, _)) => {
+                            info!("MQTT barrier command: Open");
+                            let _ = open_barrier(Opener::Operator);
                         }
+                        Some((command, _)) => {
+                            info!("MQTT barrier command: {}", command);
+                            if BARRIER_COMMANDS.try_send((command, false)).is_err() {
+                                warn!("Barrier busy, dropping MQTT command");
+                            }
+                        }
+                        None => warn!("Unknown MQTT command"),
+                    }
+                    if let Some(id) = packet_id {
+                        mqtt_send(socket, mqtt::puback(id)).await?;
                     }
-                    Timer::after_millis(100).await;
                 }
-                break; 
-            }
-            Err(err) => {
-                info!("Join failed with status={}", err.status);
-                Timer::after(Duration::from_secs(3)).await; 
+                mqtt::Packet::PubAck { packet_id } => {
+                    if !unacked.acked(packet_id) {
+                        warn!("MQTT PUBACK for unknown packet {}", packet_id);
+                    }
+                }
+                _ => {}
             }
+
+            rx.copy_within(n..len, 0);
+            len -= n;
         }
     }
+}
+
+/// Publishes the spots and the barrier state that changed since the last call.
+/// What doesn't fit in flight waits for the next call.
+async fn mqtt_publish_changes(
+    socket: &mut TcpSocket<'_>,
+    published: &mut MqttPublished,
+    unacked: &mut mqtt::Unacked<MQTT_IN_FLIGHT>,
+) -> Result<(), MqttError> {
+    let spots = OCCUPANCY.lock(|o| o.borrow().snapshot()).spots;
+    for (i, spot) in spots.iter().enumerate() {
+        if published.spots[i] == Some(*spot) {
+            continue;
+        }
+        let mut topic: String<64> = String::new();
+        let _ = core::write!(topic, "parking/{}/spot/{}", MQTT_LOT, i + 1);
+        let payload = match spot {
+            SpotState::Free => "free",
+            SpotState::Occupied => "occupied",
+        };
+        let Some(packet) = mqtt_publish(unacked, &topic, payload)? else {
+            return Ok(());
+        };
+        mqtt_send(socket, Some(packet)).await?;
+        published.spots[i] = Some(*spot);
+    }
+
+    let barrier = BARRIER_STATE.lock(|s| s.get());
+    if published.barrier != Some(barrier) {
+        let mut topic: String<64> = String::new();
+        let _ = core::write!(topic, "parking/{}/barrier", MQTT_LOT);
+        let payload = match barrier {
+            BarrierState::Open => "open",
+            BarrierState::Closed => "closed",
+            BarrierState::Locked => "locked",
+            BarrierState::Emergency => "emergency",
+        };
+        let Some(packet) = mqtt_publish(unacked, &topic, payload)? else {
+            return Ok(());
+        };
+        mqtt_send(socket, Some(packet)).await?;
+        published.barrier = Some(barrier);
+    }
+
+    Ok(())
+}
+
+/// Retained QoS 1 publish, `None` while too many wait for their PUBACK.
+fn mqtt_publish(
+    unacked: &mut mqtt::Unacked<MQTT_IN_FLIGHT>,
+    topic: &str,
+    payload: &str,
+) -> Result<Option<mqtt::Buffer>, MqttError> {
+    match unacked.publish(topic, payload.as_bytes(), true, Instant::now()) {
+        Ok(packet) => Ok(Some(packet)),
+        Err(mqtt::PublishError::Full) => Ok(None),
+        Err(mqtt::PublishError::TooLarge) => Err(MqttError::Encode),
+    }
+}
+
+async fn mqtt_send(socket: &mut TcpSocket<'_>, packet: Option<mqtt::Buffer>) -> Result<(), MqttError> {
+    let packet = packet.ok_or(MqttError::Encode)?;
+    socket.write_all(&packet).await.map_err(MqttError::Io)
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+
+    let peripherals = embassy_rp::init(Default::default());
+
+    // Restart the board whenever a supervised task hangs
+    supervisor::start(&spawner, peripherals.WATCHDOG);
+
+    if OPERATOR_KEY.is_none() {
+        warn!("Built without PARKING_OPERATOR_KEY, commands that need the operator key are refused");
+    }
+
+    // Barrier LED pins
+    let barrier_led_open = Output::new(peripherals.PIN_16, Level::Low);
+    let barrier_led_closed = Output::new(peripherals.PIN_17, Level::High);
+
+    // Event log in its own flash region, continuing after the last run
+    let flash = ota::init_flash(peripherals.FLASH);
+    let log_region = FlashPartition::new(flash, EVENT_LOG_OFFSET, EVENT_LOG_SIZE);
+    let log = unwrap!(EventLog::mount(log_region, 0, EVENT_LOG_SIZE));
+    info!("Event log mounted, boot {}", log.boot());
+    let event_log = EVENT_LOG.init(AsyncMutex::new(log));
+    spawner.spawn(event_log_task(event_log)).unwrap();
+
+    // Reservations from the last run, saved again on every change
+    let reservation_region = FlashPartition::new(flash, RESERVATIONS_OFFSET, RESERVATIONS_SIZE);
+    let (store, reservations) = unwrap!(ReservationStore::load(reservation_region, 0));
+    info!("{} reservations loaded", reservations.iter().count());
+    RESERVATIONS.lock(|r| r.replace(reservations));
+    spawner.spawn(reservation_task(store)).unwrap();
+
+    // Init WiFi driver
+    let (net_device, mut control) = embassy_lab_utils::init_wifi!(&spawner, peripherals).await;
+
+    // As access point we pick our own address, otherwise DHCP, falling back to
+    // the address the IR board used to expect
+    let addressing = if ACCESS_POINT {
+        ap::addressing()
+    } else {
+        Addressing::DhcpWithFallback {
+            hostname: Some(HOSTNAME),
+            fallback: StaticConfigV4 {
+                address: Ipv4Cidr::new(FALLBACK_IP, 24),
+                gateway: None,
+                dns_servers: Vec::new(),
+            },
+            timeout: DHCP_FALLBACK_TIMEOUT,
+        }
+    };
+
+    // Init network stack
+    let stack = embassy_lab_utils::init_network_stack(&spawner, net_device, &RESOURCES, addressing);
+
+    // Stream the log to `tools/netlog`
+    #[cfg(feature = "netlog")]
+    if let Some(level) = NET_LOG {
+        netlog::start(&spawner, stack, level);
+    }
+
+    // Take firmware updates for all boards, ours is rolled back unless the lot comes up
+    ota::start(&spawner, stack, flash, "main", true);
+
+    if ACCESS_POINT {
+        // Run our own network, the other boards join it when they can't find theirs
+        ap::start(&spawner, &mut control, stack).await;
+    } else {
+        // Connect to WiFi, and keep reconnecting if the link drops
+        wifi::start(&spawner, control, stack, NETWORKS);
+    }
+    wifi::wait_connected().await;
+
+    // Tell the other boards where we are and learn where the display is
+    announce::start(&spawner, stack, "main");
+
+    // Wall-clock time for the event log and the status
+    sntp::start(&spawner, stack, NTP_SERVER);
 
     //Start the sensor tasks
-    let sensor_no1:u64 = 1;
+    let sensor_no1: u8 = 1;
     let pin_27_clone = Output::new(peripherals.PIN_27, Level::Low);
     let pin_26_clone = Output::new(peripherals.PIN_26, Level::Low);
     let pin_14_clone = peripherals.PIN_14.degrade();
-    spawner.spawn(sensor_task(pin_14_clone, pin_26_clone, pin_27_clone, stack, sensor_no1)).unwrap(); 
+    spawner.spawn(sensor_task(pin_14_clone, pin_26_clone, pin_27_clone, sensor_no1, supervisor::register("sensor 1", SENSOR_TIMEOUT))).unwrap(); 
 
-    let sensor_no2:u64 = 2;
+    let sensor_no2: u8 = 2;
     let pin_3_clone = Output::new(peripherals.PIN_3, Level::Low);
     let pin_4_clone = Output::new(peripherals.PIN_4, Level::Low);
     let pin_15_clone = peripherals.PIN_15.degrade();
-    spawner.spawn(sensor_task(pin_15_clone, pin_3_clone, pin_4_clone, stack, sensor_no2)).unwrap();
+    spawner.spawn(sensor_task(pin_15_clone, pin_3_clone, pin_4_clone, sensor_no2, supervisor::register("sensor 2", SENSOR_TIMEOUT))).unwrap();
 
-    let sensor_no3:u64 = 3;
+    let sensor_no3: u8 = 3;
     let pin_6_clone = Output::new(peripherals.PIN_6, Level::Low);
     let pin_7_clone = Output::new(peripherals.PIN_7, Level::Low);
     let pin_18_clone = peripherals.PIN_18.degrade();
-    spawner.spawn(sensor_task(pin_18_clone, pin_6_clone, pin_7_clone, stack, sensor_no3)).unwrap();
+    spawner.spawn(sensor_task(pin_18_clone, pin_6_clone, pin_7_clone, sensor_no3, supervisor::register("sensor 3", SENSOR_TIMEOUT))).unwrap();
 
-    let sensor_no4:u64 = 4;
+    let sensor_no4: u8 = 4;
     let pin_8_clone = Output::new(peripherals.PIN_8, Level::Low);
     let pin_9_clone = Output::new(peripherals.PIN_9, Level::Low);
     let pin_19_clone = peripherals.PIN_19.degrade();
-    spawner.spawn(sensor_task(pin_19_clone, pin_8_clone, pin_9_clone, stack, sensor_no4)).unwrap();
-
-    // Start TCP server
-    let mut rx_buffer = [0; 4096];
-    let mut tx_buffer = [0; 4096];
-    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    spawner.spawn(sensor_task(pin_19_clone, pin_8_clone, pin_9_clone, sensor_no4, supervisor::register("sensor 4", SENSOR_TIMEOUT))).unwrap();
 
-    socket.set_timeout(Some(Duration::from_secs(1000)));
+    // Start publishing the lot status to the display board
+    spawner.spawn(snapshot_task(stack, supervisor::register("snapshot", SNAPSHOT_TIMEOUT))).unwrap();
 
     // Configure PWM for servo control
     let mut servo_config: PwmConfig = Default::default();
@@ -167,98 +1635,41 @@ async fn main(spawner: Spawner) {
     let max_pulse = ((MAX_PULSE_US * servo_config.top as usize) / PERIOD_US) as u16;
 
     // Initialize PWM for servo control
-    let mut servo = Pwm::new_output_a(
+    let servo = Pwm::new_output_a(
         peripherals.PWM_SLICE1, 
         peripherals.PIN_2, 
         servo_config.clone()
     );
 
-    loop {
-        // Accept a new connection
-        info!("Listening on TCP:6000...");
-        let mut rx_buffer = [0; 4096]; // Move buffer initialization here
-        let mut tx_buffer = [0; 4096]; // Move buffer initialization here
-        let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
-    
-        if let Err(e) = socket.accept(6000).await {
-            warn!("accept error: {:?}", e);
-            continue; // Continue to the next iteration to accept a new connection
-        }
-    
-        info!("Received connection from {:?}", socket.remote_endpoint());
-        let mut buf = [0; 4096];
-    
-        // State variables
-        let mut is_open = false; // Tracks whether the barrier is open
-        let mut is_locked = false; // Tracks whether the barrier is locked
-    
-        // Ensure the closed LED is red by default
-        barrier_led_closed.set_high(); // Red LED ON
-        barrier_led_open.set_low();    // Green LED OFF
-    
-        loop {
-            // Read data from the socket
-            let n = match socket.read(&mut buf).await {
-                Ok(0) => {
-                    warn!("read EOF");
-                    break; // Exit the inner loop to accept a new connection
-                }
-                Ok(n) => n,
-                Err(e) => {
-                    warn!("read error: {:?}", e);
-                    break; // Exit the inner loop to accept a new connection
-                }
-            };
-    
-            // Parse the received data as a command
-            if let Ok(command) = core::str::from_utf8(&buf[..n]) {
-                match command.trim() {
-                    "100" => {
-                        if is_locked {
-                            info!("Barrier is locked. Cannot open.");
-                        } else if !is_open {
-                            // Open the barrier
-                            servo_config.compare_a = min_pulse * 2; // Open position
-                            servo.set_config(&servo_config);
-                            info!("Barrier opened");
-    
-                            // Update LEDs: Green ON, Red OFF
-                            barrier_led_open.set_high();  // Green LED ON
-                            barrier_led_closed.set_low(); // Red LED OFF
-    
-                            // Automatically close the barrier after 5 seconds
-                            Timer::after(Duration::from_secs(5)).await;
-                            servo_config.compare_a = max_pulse; // Closed position
-                            servo.set_config(&servo_config);
-                            info!("Barrier closed automatically");
-                            is_open = false;
-    
-                            // Update LEDs: Red ON, Green OFF
-                            barrier_led_open.set_low();   // Green LED OFF
-                            barrier_led_closed.set_high(); // Red LED ON
-                        } else {
-                            info!("Barrier is already open");
-                        }
-                    }
-                    "90" => {
-                        if is_locked {
-                            // Unlock the barrier
-                            is_locked = false;
-                            info!("Barrier unlocked");
-                        } else {
-                            // Lock the barrier
-                            is_locked = true;
-                            info!("Barrier locked");
-                        }
-                    }
-                    _ => {
-                        warn!("Unknown command received: {}", command);
-                    }
-                }
-            }
-    
-            // Add a small delay to prevent busy looping
-            Timer::after(Duration::from_millis(100)).await;
-        }
+    // Hand the barrier over to its task, commands arrive from the IR board and HTTP
+    let barrier = Barrier {
+        servo,
+        servo_config,
+        open_pulse: min_pulse * 2,
+        closed_pulse: max_pulse,
+        led_open: barrier_led_open,
+        led_closed: barrier_led_closed,
+    };
+    spawner.spawn(barrier_task(barrier, supervisor::register("barrier", BARRIER_TIMEOUT))).unwrap();
+
+    // Emergency button or alarm contact, active low
+    let emergency_input = Input::new(peripherals.PIN_22, Pull::Up);
+    spawner.spawn(emergency_input_task(emergency_input)).unwrap();
+
+    // Start the HTTP status and control API
+    spawner.spawn(http_task(stack, event_log)).unwrap();
+
+    // Publish the lot to the MQTT broker, if there is one
+    if let Some(broker) = MQTT_BROKER {
+        spawner.spawn(mqtt_task(stack, broker)).unwrap();
+    }
+
+    // Everything runs, keep this firmware
+    ota::healthy();
+
+    // Serve the command port to several clients at once, the IR board keeps
+    // one connection open
+    for name in COMMAND_TASKS {
+        spawner.spawn(command_task(stack, supervisor::register(name, COMMANDS_TIMEOUT))).unwrap();
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,24 @@
+#!/bin/sh
+# Builds the bootloader and every board against Cargo.lock and runs the host
+# tests, the check before merging. Without a Cargo.lock it resolves the
+# dependencies first; commit the new lockfile along with the change.
+set -e
+cd "$(dirname "$0")/.."
+
+if [ ! -f Cargo.lock ]; then
+    cargo generate-lockfile
+    echo "Cargo.lock created, commit it"
+fi
+
+(cd bootloader && cargo build --release --locked --features rp2040 --target thumbv6m-none-eabi)
+(cd bootloader && cargo build --release --locked --features rp235xa --target thumbv8m.main-none-eabihf)
+for board in main-board display-board ir-rx-board; do
+    (cd "$board" && cargo build --release --locked)
+done
+
+for crate in embassy-lab-utils-core main-board-core display-board-core; do
+    (cd "$crate" && cargo test --locked)
+done
+for tool in tools/parkctl tools/netlog; do
+    (cd "$tool" && cargo build --release && cargo test)
+done
@@ -0,0 +1,236 @@
+//! Collects the logs the boards stream over the network into one timeline.
+//!
+//!     cargo run --release -- [--level <level>] <board>=<elf> [<board>=<elf> ...] [<board>@<address> ...]
+//!
+//! Every board with an ELF file is connected to on TCP port 6003 once its
+//! address is known, from `<board>@<address>` or from the announcements the
+//! boards broadcast on UDP port 6001. The frames are decoded with the ELF
+//! file, which has to be the one the board runs, and printed as they arrive,
+//! with the time they arrived, the board and the board's uptime. `--level`
+//! sets the lowest level the boards send, `info` unless given.
+//!
+//! Lost connections are retried; run it with `main=...`, `display=...` and
+//! `ir=...` for the whole lot.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use defmt_decoder::{DecodeError, Table};
+use defmt_parser::Level;
+
+/// TCP port the boards stream their log on.
+const LOG_PORT: u16 = 6003;
+
+/// UDP port the boards announce their addresses on.
+const ANNOUNCE_PORT: u16 = 6001;
+
+/// Wait before connecting to a board again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// One line of the timeline.
+enum Event {
+    Log { board: String, line: String },
+    Status { board: String, message: String },
+}
+
+struct Board {
+    table: &'static Table,
+    address: Option<IpAddr>,
+    connected: bool,
+}
+
+type Boards = Arc<Mutex<HashMap<String, Board>>>;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut level = Level::Info;
+    let mut boards = HashMap::new();
+    let mut addresses = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--level" {
+            let name = args.next().ok_or("--level needs a level")?;
+            level = parse_level(&name).ok_or_else(|| format!("unknown level {name}"))?;
+        } else if let Some((board, elf)) = arg.split_once('=') {
+            let elf = std::fs::read(elf).map_err(|e| format!("{elf}: {e}"))?;
+            let table = Table::parse(&elf)?.ok_or_else(|| format!("no defmt data in the ELF file of {board}"))?;
+            let board_entry = Board {
+                table: Box::leak(Box::new(table)),
+                address: None,
+                connected: false,
+            };
+            boards.insert(board.to_string(), board_entry);
+        } else if let Some((board, address)) = arg.split_once('@') {
+            addresses.push((board.to_string(), address.parse::<IpAddr>()?));
+        } else {
+            return Err(usage().into());
+        }
+    }
+    if boards.is_empty() {
+        return Err(usage().into());
+    }
+    for (board, address) in addresses {
+        boards.get_mut(&board).ok_or_else(|| format!("no ELF file for {board}"))?.address = Some(address);
+    }
+
+    let boards: Boards = Arc::new(Mutex::new(boards));
+    let (events, timeline) = mpsc::channel();
+
+    // Learn the addresses from the announcements
+    {
+        let boards = boards.clone();
+        let events = events.clone();
+        thread::spawn(move || listen_announcements(boards, events));
+    }
+
+    // Connect to the boards whose address is known, again after they drop
+    {
+        let boards = boards.clone();
+        thread::spawn(move || loop {
+            connect_known(&boards, level, &events);
+            thread::sleep(RETRY_INTERVAL);
+        });
+    }
+
+    for event in timeline {
+        match event {
+            Event::Log { board, line } => println!("{} {:<8} {}", now(), board, line),
+            Event::Status { board, message } => eprintln!("{} {:<8} -- {}", now(), board, message),
+        }
+    }
+    Ok(())
+}
+
+fn usage() -> String {
+    "usage: netlog [--level <trace|debug|info|warn|error>] <board>=<elf> ... [<board>@<address> ...]".to_string()
+}
+
+fn parse_level(name: &str) -> Option<Level> {
+    [Level::Trace, Level::Debug, Level::Info, Level::Warn, Level::Error]
+        .into_iter()
+        .find(|level| level.as_str() == name)
+}
+
+/// Records the addresses of `Board: <name> <ip>` announcements.
+fn listen_announcements(boards: Boards, events: Sender<Event>) {
+    let socket = match UdpSocket::bind(("0.0.0.0", ANNOUNCE_PORT)) {
+        Ok(socket) => socket,
+        Err(e) => {
+            let message = format!("not listening for announcements: {e}, give the addresses");
+            let _ = events.send(Event::Status { board: "netlog".to_string(), message });
+            return;
+        }
+    };
+
+    let mut buf = [0; 512];
+    while let Ok((n, _)) = socket.recv_from(&mut buf) {
+        let text = String::from_utf8_lossy(&buf[..n]);
+        for line in text.lines() {
+            let mut parts = line.split_whitespace();
+            let (Some("Board:"), Some(name), Some(ip)) = (parts.next(), parts.next(), parts.next()) else {
+                continue;
+            };
+            let Ok(ip) = ip.parse::<IpAddr>() else {
+                continue;
+            };
+            if let Some(board) = boards.lock().unwrap().get_mut(name) {
+                board.address = Some(ip);
+            }
+        }
+    }
+}
+
+/// Starts streaming from every board with an address that isn't connected.
+fn connect_known(boards: &Boards, level: Level, events: &Sender<Event>) {
+    let mut boards_guard = boards.lock().unwrap();
+    for (name, board) in boards_guard.iter_mut() {
+        let Some(address) = board.address else {
+            continue;
+        };
+        if board.connected {
+            continue;
+        }
+        board.connected = true;
+
+        let name = name.clone();
+        let table = board.table;
+        let boards = boards.clone();
+        let events = events.clone();
+        thread::spawn(move || {
+            let result = stream(&name, SocketAddr::new(address, LOG_PORT), table, level, &events);
+            let message = match result {
+                Ok(()) => "disconnected".to_string(),
+                Err(e) => format!("disconnected: {e}"),
+            };
+            let _ = events.send(Event::Status { board: name.clone(), message });
+            if let Some(board) = boards.lock().unwrap().get_mut(&name) {
+                board.connected = false;
+            }
+        });
+    }
+}
+
+/// Decodes the log of one board until the connection ends.
+fn stream(
+    board: &str,
+    address: SocketAddr,
+    table: &Table,
+    level: Level,
+    events: &Sender<Event>,
+) -> Result<(), Box<dyn Error>> {
+    let mut socket = TcpStream::connect_timeout(&address, Duration::from_secs(5))?;
+    socket.write_all(format!("Level: {}\n", level.as_str()).as_bytes())?;
+    let message = format!("connected to {address}");
+    events.send(Event::Status { board: board.to_string(), message })?;
+
+    let mut decoder = table.new_stream_decoder();
+    let mut buf = [0; 1024];
+    loop {
+        let n = socket.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        decoder.received(&buf[..n]);
+
+        loop {
+            match decoder.decode() {
+                Ok(frame) => {
+                    // The board filters too, this catches what it sent before the level changed
+                    if frame.level().is_some_and(|frame_level| frame_level < level) {
+                        continue;
+                    }
+                    let line = format!(
+                        "{:<5} [{}] {}",
+                        frame.level().map_or("", |level| level.as_str()).to_uppercase(),
+                        frame.display_timestamp().map(|t| t.to_string()).unwrap_or_default(),
+                        frame.display_message()
+                    );
+                    events.send(Event::Log { board: board.to_string(), line })?;
+                }
+                Err(DecodeError::UnexpectedEof) => break,
+                // A frame cut off when the buffer overflowed or a client left
+                Err(DecodeError::Malformed) if table.encoding().can_recover() => continue,
+                Err(DecodeError::Malformed) => return Err("malformed frame, is the ELF file the one the board runs?".into()),
+            }
+        }
+    }
+}
+
+/// Time of day the line arrived, UTC.
+fn now() -> String {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() % 86_400;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
@@ -0,0 +1,82 @@
+#!/usr/bin/env python3
+"""Signs firmware images and sends them to the boards.
+
+    ota.py keygen
+    ota.py upload <main board address> <board> <image.bin>
+
+`keygen` writes a new signing key to ota/key.pem and its public half to
+ota/lot.pub. Build the firmware with OTA_PUBLIC_KEY set to the absolute path
+of lot.pub to build the key in. Keep key.pem private and out of the
+repository; images are only accepted when signed with it.
+
+`upload` sends an image to TCP port 6002 of the main board, which keeps it
+when <board> is `main` and relays it to the `display` or `ir` board
+otherwise. The image is the raw binary of a board's firmware, e.g.
+
+    cargo objcopy --release -- -O binary -R .boot2 display.bin
+
+Needs the `cryptography` package.
+"""
+
+import hashlib
+import socket
+import struct
+import sys
+from pathlib import Path
+
+from cryptography.hazmat.primitives import serialization
+from cryptography.hazmat.primitives.asymmetric.ed25519 import Ed25519PrivateKey
+
+OTA_PORT = 6002
+MAGIC = b"OTA2"
+MAX_BOARD = 8
+KEY_DIR = Path(__file__).resolve().parent.parent / "ota"
+
+
+def keygen():
+    key = Ed25519PrivateKey.generate()
+    pem = key.private_bytes(
+        serialization.Encoding.PEM,
+        serialization.PrivateFormat.PKCS8,
+        serialization.NoEncryption(),
+    )
+    public = key.public_key().public_bytes(serialization.Encoding.Raw, serialization.PublicFormat.Raw)
+    (KEY_DIR / "key.pem").write_bytes(pem)
+    (KEY_DIR / "lot.pub").write_bytes(public)
+    print(f"Wrote {KEY_DIR / 'key.pem'} and {KEY_DIR / 'lot.pub'}")
+    print(f"Build with OTA_PUBLIC_KEY={KEY_DIR / 'lot.pub'} and flash every board once")
+
+
+def header(board, image, key):
+    name = board.encode()
+    if len(name) > MAX_BOARD:
+        sys.exit(f"board name longer than {MAX_BOARD} characters: {board}")
+    # The firmware checks the signature of the SHA-512, not of the image
+    signature = key.sign(hashlib.sha512(image).digest())
+    return MAGIC + name.ljust(MAX_BOARD, b"\0") + struct.pack("<I", len(image)) + signature
+
+
+def upload(host, board, path):
+    key = serialization.load_pem_private_key((KEY_DIR / "key.pem").read_bytes(), password=None)
+    image = Path(path).read_bytes()
+
+    with socket.create_connection((host, OTA_PORT), timeout=120) as conn:
+        conn.sendall(header(board, image, key))
+        conn.sendall(image)
+        reply = conn.makefile().readline().strip()
+
+    print(reply or "No answer")
+    return reply == "Ok"
+
+
+def main(args):
+    if args == ["keygen"]:
+        keygen()
+    elif len(args) == 4 and args[0] == "upload":
+        sys.exit(0 if upload(*args[1:]) else 1)
+    else:
+        sys.exit(__doc__)
+
+
+if __name__ == "__main__":
+    main(sys.argv[1:])
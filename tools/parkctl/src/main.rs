@@ -0,0 +1,392 @@
+//! Operator's command line for the parking lot.
+//!
+//!     cargo run --release -- [--host <address>[:<port>]] <command>
+//!
+//!     status                      the lot at a glance
+//!     barrier open|lock|unlock    queues a barrier command
+//!     barrier state               Open, Closed, Locked or Emergency
+//!     emergency on|off            holds the barrier open, or clears that
+//!     spots [watch]               the spots, or every change of them
+//!     config get [<key>]          the settings, or one of them
+//!     config set <key> <value>    changes a setting until the board restarts
+//!     logs [--json]               the event log, CSV unless `--json`
+//!
+//! Everything goes through the main board's HTTP API, which takes a new
+//! connection for every request. The address is `--host`, else
+//! `PARKCTL_HOST`, else the main board's fallback address. Port 80 is used unless the address has one, so anything
+//! serving the same API, like a simulator on this machine, can stand in for
+//! the board. The barrier commands, the emergency and `config set` need the
+//! operator key, `--key` or `PARKCTL_KEY`.
+
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+
+/// The main board's address when DHCP doesn't answer.
+const DEFAULT_HOST: &str = "192.168.23.155";
+
+const HTTP_PORT: u16 = 80;
+
+/// How long a request may take, the board answers one at a time.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often `spots watch` asks for the spots.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What the command line asks for.
+#[derive(Debug, PartialEq, Eq)]
+enum Command<'a> {
+    Status,
+    /// `open`, `lock` or `unlock`.
+    Barrier(&'a str),
+    BarrierState,
+    Emergency(bool),
+    Spots { watch: bool },
+    ConfigGet(Option<&'a str>),
+    ConfigSet(&'a str, &'a str),
+    Logs { json: bool },
+}
+
+impl<'a> Command<'a> {
+    fn parse(args: &[&'a str]) -> Option<Self> {
+        Some(match *args {
+            ["status"] => Command::Status,
+            ["barrier", command @ ("open" | "lock" | "unlock")] => Command::Barrier(command),
+            ["barrier", "state"] => Command::BarrierState,
+            ["emergency", "on"] => Command::Emergency(true),
+            ["emergency", "off"] => Command::Emergency(false),
+            ["spots"] => Command::Spots { watch: false },
+            ["spots", "watch"] => Command::Spots { watch: true },
+            ["config", "get"] => Command::ConfigGet(None),
+            ["config", "get", key] => Command::ConfigGet(Some(key)),
+            ["config", "set", key, value] => Command::ConfigSet(key, value),
+            ["logs"] => Command::Logs { json: false },
+            ["logs", "--json"] => Command::Logs { json: true },
+            _ => return None,
+        })
+    }
+}
+
+/// The board's address, the operator key and the command's words, from the
+/// arguments after the program name. `host` and `key` are the defaults.
+#[derive(Debug, PartialEq, Eq)]
+struct Options {
+    host: String,
+    key: Option<String>,
+    args: Vec<String>,
+}
+
+impl Options {
+    fn parse(args: impl IntoIterator<Item = String>, host: String, key: Option<String>) -> Result<Self, String> {
+        let mut options = Options { host, key, args: Vec::new() };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--host" {
+                options.host = args.next().ok_or("--host needs an address")?;
+            } else if arg == "--key" {
+                options.key = Some(args.next().ok_or("--key needs the operator key")?);
+            } else {
+                options.args.push(arg);
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(
+        std::env::args().skip(1),
+        std::env::var("PARKCTL_HOST").unwrap_or_else(|_| DEFAULT_HOST.to_string()),
+        std::env::var("PARKCTL_KEY").ok(),
+    )?;
+    let args: Vec<&str> = options.args.iter().map(String::as_str).collect();
+    let command = Command::parse(&args).ok_or_else(usage)?;
+    let board = Board::new(&options.host, options.key)?;
+
+    match command {
+        Command::Status => status(&board),
+        Command::Barrier(command) => {
+            board.request("POST", &format!("/barrier/{command}"))?;
+            println!("Barrier {command} queued");
+            Ok(())
+        }
+        Command::BarrierState => {
+            println!("{}", board.status()?["barrier"].as_str().ok_or("no barrier in the status")?);
+            Ok(())
+        }
+        Command::Emergency(true) => {
+            board.request("POST", "/emergency")?;
+            println!("Emergency, barrier held open");
+            Ok(())
+        }
+        Command::Emergency(false) => {
+            board.request("POST", "/emergency/clear")?;
+            println!("Emergency clear queued");
+            Ok(())
+        }
+        Command::Spots { watch: false } => {
+            for (spot, state) in spots(&board.status()?)?.iter().enumerate() {
+                println!("{} {}", spot + 1, state);
+            }
+            Ok(())
+        }
+        Command::Spots { watch: true } => watch_spots(&board),
+        Command::ConfigGet(None) => {
+            let config = board.json("/config")?;
+            for (key, value) in config.as_object().ok_or("the settings aren't an object")? {
+                println!("{key} {value}");
+            }
+            Ok(())
+        }
+        Command::ConfigGet(Some(key)) => {
+            let config = board.json("/config")?;
+            let value = config.get(key).ok_or_else(|| format!("no setting {key}"))?;
+            println!("{value}");
+            Ok(())
+        }
+        Command::ConfigSet(key, value) => {
+            board.request("POST", &format!("/config/{key}/{value}"))?;
+            println!("{key} {value}");
+            Ok(())
+        }
+        Command::Logs { json: false } => {
+            print!("{}", board.request("GET", "/events.csv")?);
+            Ok(())
+        }
+        Command::Logs { json: true } => {
+            print!("{}", board.request("GET", "/events.json")?);
+            Ok(())
+        }
+    }
+}
+
+fn usage() -> String {
+    "usage: parkctl [--host <address>[:<port>]] [--key <operator key>] status | barrier open|lock|unlock|state \
+     | emergency on|off | spots [watch] | config get [<key>] | config set <key> <value> | logs [--json]"
+        .to_string()
+}
+
+/// The main board's HTTP API.
+struct Board {
+    address: SocketAddr,
+    /// Operator key, sent as the bearer token.
+    key: Option<String>,
+}
+
+impl Board {
+    fn new(host: &str, key: Option<String>) -> Result<Self, Box<dyn Error>> {
+        let address = if host.contains(':') {
+            host.to_socket_addrs()
+        } else {
+            (host, HTTP_PORT).to_socket_addrs()
+        }
+        .map_err(|e| format!("{host}: {e}"))?
+        .next()
+        .ok_or_else(|| format!("{host}: no address"))?;
+        Ok(Board { address, key })
+    }
+
+    /// The body of a successful response, the body of any other is the error.
+    fn request(&self, method: &str, path: &str) -> Result<String, Box<dyn Error>> {
+        let mut socket =
+            TcpStream::connect_timeout(&self.address, TIMEOUT).map_err(|e| format!("{}: {e}", self.address))?;
+        socket.set_read_timeout(Some(TIMEOUT))?;
+        let authorization = match &self.key {
+            Some(key) => format!("Authorization: Bearer {key}\r\n"),
+            None => String::new(),
+        };
+        let request = format!(
+            "{method} {path} HTTP/1.1\r\nHost: {}\r\n{authorization}Content-Length: 0\r\nConnection: close\r\n\r\n",
+            self.address
+        );
+        socket.write_all(request.as_bytes())?;
+
+        // Every response ends with the connection
+        let mut response = Vec::new();
+        socket.read_to_end(&mut response)?;
+        let response = String::from_utf8_lossy(&response);
+
+        let (head, body) = response.split_once("\r\n\r\n").ok_or("incomplete response")?;
+        let status = head.split(' ').nth(1).ok_or("malformed response")?;
+        if !status.starts_with('2') {
+            let reason = body.trim();
+            let reason = reason.strip_prefix("Error: ").unwrap_or(reason);
+            return Err(if reason.is_empty() { format!("HTTP {status}") } else { reason.to_string() }.into());
+        }
+        Ok(body.to_string())
+    }
+
+    fn json(&self, path: &str) -> Result<Value, Box<dyn Error>> {
+        Ok(serde_json::from_str(&self.request("GET", path)?)?)
+    }
+
+    fn status(&self) -> Result<Value, Box<dyn Error>> {
+        self.json("/status")
+    }
+}
+
+fn spots(status: &Value) -> Result<Vec<String>, Box<dyn Error>> {
+    let spots = status["spots"].as_array().ok_or("no spots in the status")?;
+    Ok(spots.iter().map(|spot| spot.as_str().unwrap_or("unknown").to_string()).collect())
+}
+
+fn status(board: &Board) -> Result<(), Box<dyn Error>> {
+    let status = board.status()?;
+    let spots = spots(&status)?;
+
+    let list: Vec<String> = spots.iter().enumerate().map(|(spot, state)| format!("{} {}", spot + 1, state)).collect();
+    println!("Spots     {}", list.join(", "));
+    println!("Free      {} of {}", status["free"], spots.len());
+    println!("Barrier   {}", status["barrier"].as_str().unwrap_or("unknown"));
+    println!(
+        "Openings  {}, {} refused while locked",
+        status["counters"]["openings"], status["counters"]["denied_openings"]
+    );
+
+    let sessions = &status["sessions"];
+    let average = match sessions["average_dwell_secs"].as_u64() {
+        Some(secs) => format!("{} average", duration(secs)),
+        None => "no average yet".to_string(),
+    };
+    println!(
+        "Sessions  {} finished, {}, {} per hour, peak {} parked",
+        sessions["total"], average, sessions["turnover_per_hour"], sessions["peak_occupied"]
+    );
+
+    println!("Clock     {}", status["clock"].as_str().unwrap_or("unknown"));
+    match status["ir_last_seen_secs"].as_u64() {
+        Some(secs) => println!("IR board  heard from {} ago", duration(secs)),
+        None => println!("IR board  never heard from"),
+    }
+    println!("Display   {}", if status["display_fault"] == true { "fault" } else { "ok" });
+
+    for reset in status["resets"].as_array().into_iter().flatten() {
+        let detail = reset["detail"].as_str().unwrap_or("");
+        println!(
+            "Restart   {} {} after {}{}{}",
+            reset["board"].as_str().unwrap_or("?"),
+            reset["cause"].as_str().unwrap_or("?"),
+            duration(reset["uptime_secs"].as_u64().unwrap_or(0)),
+            if detail.is_empty() { "" } else { ": " },
+            detail
+        );
+    }
+    Ok(())
+}
+
+/// Prints the spots, then every spot that changes. A lost board is waited for.
+fn watch_spots(board: &Board) -> Result<(), Box<dyn Error>> {
+    let mut last: Option<Vec<String>> = None;
+    let mut failing = false;
+    loop {
+        match board.status().and_then(|status| spots(&status)) {
+            Ok(current) => {
+                if failing {
+                    eprintln!("{} -- reconnected", now());
+                    failing = false;
+                }
+                for (spot, state) in current.iter().enumerate() {
+                    let before = last.as_ref().and_then(|last| last.get(spot));
+                    if before != Some(state) {
+                        println!("{} spot {} {}", now(), spot + 1, state);
+                    }
+                }
+                last = Some(current);
+            }
+            Err(e) => {
+                if !failing {
+                    eprintln!("{} -- {e}", now());
+                    failing = true;
+                }
+            }
+        }
+        thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// `1h 05m`, `5m 03s` or `12s`.
+fn duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m {:02}s", secs / 60, secs % 60),
+        _ => format!("{}h {:02}m", secs / 3600, secs / 60 % 60),
+    }
+}
+
+/// Time of day, UTC.
+fn now() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() % 86_400;
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()), DEFAULT_HOST.to_string(), None)
+    }
+
+    #[test]
+    fn every_command() {
+        let cases: &[(&[&str], Command)] = &[
+            (&["status"], Command::Status),
+            (&["barrier", "open"], Command::Barrier("open")),
+            (&["barrier", "lock"], Command::Barrier("lock")),
+            (&["barrier", "unlock"], Command::Barrier("unlock")),
+            (&["barrier", "state"], Command::BarrierState),
+            (&["emergency", "on"], Command::Emergency(true)),
+            (&["emergency", "off"], Command::Emergency(false)),
+            (&["spots"], Command::Spots { watch: false }),
+            (&["spots", "watch"], Command::Spots { watch: true }),
+            (&["config", "get"], Command::ConfigGet(None)),
+            (&["config", "get", "daily_cap"], Command::ConfigGet(Some("daily_cap"))),
+            (&["config", "set", "daily_cap", "2000"], Command::ConfigSet("daily_cap", "2000")),
+            (&["logs"], Command::Logs { json: false }),
+            (&["logs", "--json"], Command::Logs { json: true }),
+        ];
+        for (args, command) in cases {
+            assert_eq!(Command::parse(args).as_ref(), Some(command), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn unknown_commands() {
+        for args in [&[][..], &["barrier"], &["barrier", "toggle"], &["emergency"], &["config", "set", "daily_cap"], &["logs", "--csv"], &["status", "now"]] {
+            assert_eq!(Command::parse(args), None, "{args:?}");
+        }
+    }
+
+    #[test]
+    fn host_and_key_anywhere() {
+        let parsed = options(&["--host", "127.0.0.1:8080", "barrier", "--key", "secret-key", "open"]).unwrap();
+        assert_eq!(
+            parsed,
+            Options {
+                host: "127.0.0.1:8080".to_string(),
+                key: Some("secret-key".to_string()),
+                args: vec!["barrier".to_string(), "open".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn defaults_from_the_environment() {
+        let parsed = Options::parse(["status".to_string()], "10.0.0.2".to_string(), Some("env-key".to_string())).unwrap();
+        assert_eq!((parsed.host.as_str(), parsed.key.as_deref()), ("10.0.0.2", Some("env-key")));
+
+        // The arguments win
+        let parsed = options(&["--key", "arg-key", "status"]).unwrap();
+        assert_eq!((parsed.host.as_str(), parsed.key.as_deref()), (DEFAULT_HOST, Some("arg-key")));
+    }
+
+    #[test]
+    fn options_without_a_value() {
+        assert_eq!(options(&["status", "--host"]), Err("--host needs an address".to_string()));
+        assert_eq!(options(&["status", "--key"]), Err("--key needs the operator key".to_string()));
+    }
+}